@@ -1,8 +1,22 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
 fn main() {
-    prost_build::compile_protos(
-        &[
-            "src/market_xml/market_xml.proto",
-        ],
-        &["src/"]
-    ).unwrap();
+    let descriptor_path = PathBuf::from(env::var("OUT_DIR").unwrap())
+        .join("market_xml_descriptor.bin");
+    prost_build::Config::new()
+        .file_descriptor_set_path(&descriptor_path)
+        .compile_protos(
+            &[
+                "src/market_xml/market_xml.proto",
+            ],
+            &["src/"]
+        ).unwrap();
+
+    // serde implementations following the canonical proto3 json mapping
+    let descriptor_set = fs::read(descriptor_path).unwrap();
+    pbjson_build::Builder::new()
+        .register_descriptors(&descriptor_set).unwrap()
+        .build(&[".market_xml"]).unwrap();
 }
@@ -0,0 +1,494 @@
+use serde::Deserialize;
+
+use std::fmt::Display;
+use std::io::prelude::*;
+use std::mem;
+use std::str::FromStr;
+
+use crate::market_xml::{self, Offer, Shop, YmlCatalog};
+use crate::parser::{
+    self, CategoryPaths, FeedParser, MarketXmlConfig, MarketXmlError, MarketXmlWarning, ParsedItem,
+};
+
+/// Describes how columns of a csv file are mapped onto offer fields
+#[derive(Deserialize, Debug)]
+pub(crate) struct CsvMapping {
+    columns: Vec<ColumnMapping>,
+    /// Overrides a delimiter of the input format, for example `;`
+    #[serde(default)]
+    delimiter: Option<char>,
+    /// Splits cells of multi-value fields: pictures, barcodes, params and extra fields
+    #[serde(default)]
+    multi_value_delimiter: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ColumnMapping {
+    column: String,
+    /// Name of a yml offer element, `category` for a path like `Home > Kitchen`
+    field: String,
+    /// Name of a param, defaults to the column name
+    #[serde(default)]
+    param_name: Option<String>,
+    #[serde(default)]
+    unit: Option<String>,
+}
+
+impl CsvMapping {
+    pub(crate) fn from_reader<R: Read>(reader: R) -> Result<Self, serde_json::Error> {
+        serde_json::from_reader(reader)
+    }
+}
+
+#[derive(Clone, Debug)]
+enum OfferField {
+    Id,
+    Type,
+    Available,
+    Name,
+    Category,
+    CategoryId,
+    Price,
+    OldPrice,
+    CurrencyId,
+    Url,
+    Vendor,
+    VendorCode,
+    Model,
+    TypePrefix,
+    Picture,
+    Description,
+    SalesNotes,
+    CountryOfOrigin,
+    Barcode,
+    Param { name: String, unit: String },
+    Weight,
+    Dimensions,
+    GroupId,
+    StockQuantity,
+    MinQuantity,
+    Keywords,
+    Delivery,
+    Pickup,
+    Store,
+    Adult,
+    ManufacturerWarranty,
+    Extra(String),
+}
+
+impl OfferField {
+    fn from_mapping(mapping: &ColumnMapping) -> Self {
+        use OfferField::*;
+
+        match mapping.field.as_str() {
+            "id" => Id,
+            "type" => Type,
+            "available" => Available,
+            "name" => Name,
+            "category" => Category,
+            "categoryId" => CategoryId,
+            "price" => Price,
+            "oldprice" => OldPrice,
+            "currencyId" => CurrencyId,
+            "url" => Url,
+            "vendor" => Vendor,
+            "vendorCode" => VendorCode,
+            "model" => Model,
+            "typePrefix" => TypePrefix,
+            "picture" => Picture,
+            "description" => Description,
+            "sales_notes" => SalesNotes,
+            "country_of_origin" => CountryOfOrigin,
+            "barcode" => Barcode,
+            "param" => Param {
+                name: mapping.param_name.clone().unwrap_or_else(|| mapping.column.clone()),
+                unit: mapping.unit.clone().unwrap_or_default(),
+            },
+            "weight" => Weight,
+            "dimensions" => Dimensions,
+            "group_id" => GroupId,
+            "stock_quantity" => StockQuantity,
+            "min_quantity" => MinQuantity,
+            "keywords" => Keywords,
+            "delivery" => Delivery,
+            "pickup" => Pickup,
+            "store" => Store,
+            "adult" => Adult,
+            "manufacturer_warranty" => ManufacturerWarranty,
+            field => Extra(field.to_string()),
+        }
+    }
+
+    fn is_multi_value(&self) -> bool {
+        matches!(
+            self,
+            OfferField::Picture | OfferField::Barcode | OfferField::Param { .. } | OfferField::Extra(_)
+        )
+    }
+}
+
+/// Parser of csv and tsv files with a header row
+pub(crate) struct CsvFeedParser<R: Read> {
+    config: MarketXmlConfig,
+    csv_reader: csv::Reader<R>,
+    // column index -> offer field
+    columns: Vec<(usize, OfferField)>,
+    multi_value_delimiter: Option<String>,
+    record: csv::StringRecord,
+    categories: CategoryPaths,
+    finished: bool,
+    warnings: Vec<MarketXmlWarning>,
+}
+
+impl<R: Read> FeedParser for CsvFeedParser<R> {
+    fn next_item(&mut self) -> Result<ParsedItem, MarketXmlError> {
+        if self.finished {
+            return Ok(ParsedItem::Eof);
+        }
+        let has_record = self.csv_reader.read_record(&mut self.record)
+            .map_err(csv_error)?;
+        if !has_record {
+            self.finished = true;
+            let shop = Shop {
+                categories: self.categories.categories().to_vec(),
+                ..Default::default()
+            };
+            return Ok(ParsedItem::YmlCatalog(YmlCatalog { shop: Some(shop), ..Default::default() }));
+        }
+        self.parse_record().map(ParsedItem::Offer)
+    }
+
+    fn take_warnings(&mut self) -> Vec<MarketXmlWarning> {
+        mem::take(&mut self.warnings)
+    }
+
+    // every row is an offer
+    fn in_offer(&self) -> bool {
+        true
+    }
+
+    fn buffer_position(&self) -> usize {
+        self.csv_reader.position().byte() as usize
+    }
+}
+
+impl<R: Read> CsvFeedParser<R> {
+    /// Reads a header row and finds out indexes of the mapped columns,
+    /// fails when some column is missing
+    pub(crate) fn new(
+        config: MarketXmlConfig, mapping: CsvMapping, delimiter: u8, reader: R
+    ) -> Result<Self, MarketXmlError> {
+        let delimiter = match mapping.delimiter {
+            Some(d) if d.is_ascii() => d as u8,
+            Some(d) => {
+                return Err(MarketXmlError::Validation {
+                    msg: "Delimiter must be an ascii character".to_string(),
+                    line: 0,
+                    column: 0,
+                    value: d.to_string(),
+                });
+            }
+            None => delimiter,
+        };
+        let mut csv_reader = csv::ReaderBuilder::new()
+            .delimiter(delimiter)
+            .flexible(true)
+            .from_reader(reader);
+        let headers = csv_reader.headers().map_err(csv_error)?.clone();
+        let mut columns = vec!();
+        for column_mapping in &mapping.columns {
+            let column_ix = headers.iter()
+                .position(|header| header.trim() == column_mapping.column);
+            match column_ix {
+                Some(column_ix) => {
+                    columns.push((column_ix, OfferField::from_mapping(column_mapping)));
+                }
+                None => {
+                    return Err(MarketXmlError::Validation {
+                        msg: format!("Unknown column: {}", column_mapping.column),
+                        line: 1,
+                        column: 0,
+                        value: column_mapping.column.clone(),
+                    });
+                }
+            }
+        }
+        Ok(Self {
+            config,
+            csv_reader,
+            columns,
+            multi_value_delimiter: mapping.multi_value_delimiter,
+            record: csv::StringRecord::new(),
+            categories: CategoryPaths::default(),
+            finished: false,
+            warnings: vec!(),
+        })
+    }
+
+    fn cur_line(&self) -> usize {
+        self.record.position().map(|pos| pos.line() as usize).unwrap_or(0)
+    }
+
+    fn parse_record(&mut self) -> Result<Offer, MarketXmlError> {
+        let mut offer = Offer::default();
+        let columns = mem::take(&mut self.columns);
+        let res = columns.iter()
+            .try_for_each(|(column_ix, field)| self.parse_cell(*column_ix, field, &mut offer));
+        self.columns = columns;
+        res.map(|_| offer)
+    }
+
+    fn parse_cell(
+        &mut self, column_ix: usize, field: &OfferField, offer: &mut Offer
+    ) -> Result<(), MarketXmlError> {
+        let cell = self.record.get(column_ix).unwrap_or("").to_string();
+        let cell = self.config.whitespace.apply(cell);
+        if cell.is_empty() {
+            return Ok(());
+        }
+        let values = match (&self.multi_value_delimiter, field.is_multi_value()) {
+            (Some(delimiter), true) => {
+                cell.split(delimiter.as_str())
+                    .map(|value| value.trim())
+                    .filter(|value| !value.is_empty())
+                    .map(|value| value.to_string())
+                    .collect()
+            }
+            _ => vec!(cell),
+        };
+        // columns are numbered from 1 like in spreadsheets
+        let column = column_ix + 1;
+        for value in values {
+            self.set_field(field, value, column, offer)?;
+        }
+        Ok(())
+    }
+
+    fn set_field(
+        &mut self, field: &OfferField, value: String, column: usize, offer: &mut Offer
+    ) -> Result<(), MarketXmlError> {
+        use OfferField::*;
+
+        match field {
+            Id => offer.id = value,
+            Type => offer.r#type = value,
+            Available => {
+                offer.available = match parser::parse_presence(&value) {
+                    Some(available) => Some(available),
+                    None => return Err(self.validation_err("unknown presence", value, column)),
+                };
+            }
+            Name => offer.name = value,
+            Category => offer.category_id = self.categories.category_id(&value),
+            CategoryId => offer.category_id = self.parse_value(&value, column)?,
+            Price => offer.price = Some(market_xml::Price {
+                price: self.parse_value(&value, column)?,
+                from: false,
+            }),
+            OldPrice => offer.old_price = Some(market_xml::Price {
+                price: self.parse_value(&value, column)?,
+                from: false,
+            }),
+            CurrencyId => offer.currency_id = value,
+            Url => offer.url = value,
+            Vendor => offer.vendor = value,
+            VendorCode => offer.vendor_code = value,
+            Model => offer.model = value,
+            TypePrefix => offer.type_prefix = value,
+            Picture => offer.pictures.push(value),
+            Description => offer.description = value,
+            SalesNotes => offer.sales_notes = value,
+            CountryOfOrigin => offer.country_of_origin = value,
+            Barcode => offer.barcodes.push(value),
+            Param { name, unit } => offer.params.push(market_xml::Param {
+                name: name.clone(),
+                unit: unit.clone(),
+                value,
+                ..Default::default()
+            }),
+            Weight => offer.weight = self.parse_value(&value, column)?,
+            Dimensions => offer.dimensions = value,
+            GroupId => offer.group_id = self.parse_value(&value, column)?,
+            StockQuantity => offer.stock_quantity = Some(self.parse_value(&value, column)?),
+            MinQuantity => offer.min_quantity = Some(self.parse_value(&value, column)?),
+            Keywords => {
+                offer.keywords.extend(
+                    value.split(',')
+                        .map(|keyword| keyword.trim())
+                        .filter(|keyword| !keyword.is_empty())
+                        .map(|keyword| keyword.to_string())
+                );
+            }
+            Delivery => offer.delivery = Some(self.parse_value(&value, column)?),
+            Pickup => offer.pickup = Some(self.parse_value(&value, column)?),
+            Store => offer.store = Some(self.parse_value(&value, column)?),
+            Adult => offer.adult = self.parse_value(&value, column)?,
+            ManufacturerWarranty => offer.manufacturer_warranty = self.parse_value(&value, column)?,
+            Extra(name) => {
+                offer.extra_fields.entry(name.clone()).or_default().values.push(value);
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_value<T>(&mut self, value: &str, column: usize) -> Result<T, MarketXmlError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let line = self.cur_line();
+        let (value, coerced_from) = parser::parse_str(value, self.config.lenient, line, column)?;
+        if let Some(value) = coerced_from {
+            self.warnings.push(MarketXmlWarning {
+                msg: "Non-standard value was coerced".to_string(),
+                line,
+                column,
+                value,
+            });
+        }
+        Ok(value)
+    }
+
+    fn validation_err(&self, msg: &str, value: String, column: usize) -> MarketXmlError {
+        MarketXmlError::Validation {
+            msg: msg.to_string(),
+            line: self.cur_line(),
+            column,
+            value,
+        }
+    }
+}
+
+fn csv_error(error: csv::Error) -> MarketXmlError {
+    let line = error.position().map(|pos| pos.line() as usize).unwrap_or(0);
+    match error.kind() {
+        csv::ErrorKind::Utf8 { err, .. } => MarketXmlError::InvalidUtf8 {
+            msg: format!("{}", err),
+            line,
+            column: err.field() + 1,
+            value: String::new(),
+        },
+        _ => MarketXmlError::Csv { source: error, line },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use failure::{bail, Error};
+
+    use crate::market_xml::{Category, Param, Price};
+    use crate::parser::{Dialect, FeedParser, MarketXmlConfig, ParsedItem};
+    use super::{CsvFeedParser, CsvMapping};
+
+    const MAPPING: &str = r#"{
+        "columns": [
+            {"column": "SKU", "field": "id"},
+            {"column": "Title", "field": "name"},
+            {"column": "Category", "field": "category"},
+            {"column": "Price", "field": "price"},
+            {"column": "Stock", "field": "available"},
+            {"column": "Image", "field": "picture"},
+            {"column": "More images", "field": "picture"},
+            {"column": "Color", "field": "param", "param_name": "Цвет"},
+            {"column": "Weight", "field": "param", "unit": "kg"},
+            {"column": "Season", "field": "season"},
+            {"column": "Min qty", "field": "min_quantity"}
+        ],
+        "multi_value_delimiter": "|"
+    }"#;
+
+    #[test]
+    fn test_parsing_csv() -> Result<(), Error> {
+        let csv = "\
+SKU,Title,Category,Price,Stock,Image,More images,Color,Weight,Season,Min qty
+1,Pan,Kitchen > Pans,\"1 299,50\",in stock,http://e.com/1.jpg,http://e.com/2.jpg|http://e.com/3.jpg,red,1.5,,2
+2,Pot,Kitchen > Pots,12O,in stock,,,,,,
+3,Lid,Kitchen,10,no,,,,,winter,
+";
+        let mapping = CsvMapping::from_reader(MAPPING.as_bytes())?;
+        let mut config = MarketXmlConfig::for_dialect(Dialect::Yml);
+        config.lenient = true;
+        let mut parser = CsvFeedParser::new(config, mapping, b',', csv.as_bytes())?;
+
+        let o = match parser.next_item()? {
+            ParsedItem::Offer(offer) => offer,
+            _ => bail!("Expected offer"),
+        };
+        assert_eq!(&o.id, "1");
+        assert_eq!(&o.name, "Pan");
+        assert_eq!(o.category_id, 2);
+        assert_eq!(o.price, Some(Price { price: 1299.5, from: false }));
+        assert_eq!(o.available, Some(true));
+        assert_eq!(o.pictures, vec!("http://e.com/1.jpg", "http://e.com/2.jpg", "http://e.com/3.jpg"));
+        assert_eq!(
+            o.params,
+            vec!(
+                Param { name: "Цвет".to_string(), value: "red".to_string(), ..Default::default() },
+                Param {
+                    name: "Weight".to_string(),
+                    unit: "kg".to_string(),
+                    value: "1.5".to_string(),
+                    ..Default::default()
+                },
+            )
+        );
+        assert_eq!(o.min_quantity, Some(2));
+        assert!(o.extra_fields.is_empty());
+        assert_eq!(parser.take_warnings().len(), 1);
+
+        match parser.next_item() {
+            Err(e) => {
+                assert_eq!((e.line(), e.column(), e.value()), (3, 4, Some("12O")));
+            }
+            res => bail!("Expected validation error, got {:?}", res),
+        }
+
+        match parser.next_item() {
+            Err(e) => {
+                assert_eq!((e.line(), e.column(), e.value()), (4, 5, Some("no")));
+            }
+            res => bail!("Expected validation error, got {:?}", res),
+        }
+
+        let s = match parser.next_item()? {
+            ParsedItem::YmlCatalog(yml_catalog) => yml_catalog.shop.unwrap(),
+            _ => bail!("Expected yml catalog"),
+        };
+        assert_eq!(
+            s.categories,
+            vec!(
+                Category { id: 1, name: "Kitchen".to_string(), ..Default::default() },
+                Category { id: 2, parent_id: 1, name: "Pans".to_string(), ..Default::default() },
+                Category { id: 3, parent_id: 1, name: "Pots".to_string(), ..Default::default() },
+            )
+        );
+        assert_eq!(parser.next_item()?, ParsedItem::Eof);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_column() -> Result<(), Error> {
+        let tsv = "SKU\tName\n1\tPan\n";
+        let mapping = CsvMapping::from_reader(MAPPING.as_bytes())?;
+        match CsvFeedParser::new(MarketXmlConfig::default(), mapping, b'\t', tsv.as_bytes()) {
+            Err(e) => assert_eq!(e.value(), Some("Title")),
+            Ok(_) => bail!("Expected unknown column error"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_non_ascii_delimiter() -> Result<(), Error> {
+        let mut mapping = CsvMapping::from_reader(MAPPING.as_bytes())?;
+        mapping.delimiter = Some('¦');
+        match CsvFeedParser::new(MarketXmlConfig::default(), mapping, b',', "SKU\n".as_bytes()) {
+            Err(e) => assert_eq!(e.value(), Some("¦")),
+            Ok(_) => bail!("Expected invalid delimiter error"),
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,445 @@
+use serde::Serialize;
+
+use serde_json::Value;
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+use crate::incremental::offer_hash;
+use crate::market_xml::{Offer, YmlCatalog};
+use crate::parser::MarketXmlError;
+
+/// Change of a single field, missing fields are `null`
+#[derive(Serialize, Debug, PartialEq)]
+pub(crate) struct FieldChange {
+    pub(crate) field: String,
+    pub(crate) old: Value,
+    pub(crate) new: Value,
+}
+
+/// Changed fields of an offer, a category or a currency
+#[derive(Serialize, Debug, PartialEq)]
+pub(crate) struct ItemChanges {
+    pub(crate) id: String,
+    pub(crate) changes: Vec<FieldChange>,
+}
+
+/// Recoverable error of a feed, an offer with such an error is skipped
+#[derive(Serialize, Debug, PartialEq)]
+pub(crate) struct FeedError {
+    pub(crate) line: usize,
+    pub(crate) column: usize,
+    pub(crate) msg: String,
+}
+
+impl From<&MarketXmlError> for FeedError {
+    fn from(error: &MarketXmlError) -> Self {
+        Self { line: error.line(), column: error.column(), msg: format!("{}", error) }
+    }
+}
+
+#[derive(Serialize, Default, Debug, PartialEq)]
+pub(crate) struct DiffSummary {
+    pub(crate) old_offers: u64,
+    pub(crate) new_offers: u64,
+    pub(crate) added_offers: u64,
+    pub(crate) removed_offers: u64,
+    pub(crate) changed_offers: u64,
+    pub(crate) unchanged_offers: u64,
+    pub(crate) old_errors: u64,
+    pub(crate) new_errors: u64,
+}
+
+#[derive(Serialize, Default, Debug, PartialEq)]
+pub(crate) struct CatalogDiff {
+    /// Changes of the catalog date and shop fields except categories and currencies
+    pub(crate) shop: Vec<FieldChange>,
+    pub(crate) added_categories: Vec<String>,
+    pub(crate) removed_categories: Vec<String>,
+    pub(crate) changed_categories: Vec<ItemChanges>,
+    pub(crate) added_currencies: Vec<String>,
+    pub(crate) removed_currencies: Vec<String>,
+    pub(crate) changed_currencies: Vec<ItemChanges>,
+}
+
+/// Differences between two feeds, fields are compared using the canonical json mapping
+#[derive(Serialize, Default, Debug, PartialEq)]
+pub(crate) struct FeedDiff {
+    pub(crate) summary: DiffSummary,
+    pub(crate) catalog: CatalogDiff,
+    pub(crate) added_offers: Vec<String>,
+    pub(crate) removed_offers: Vec<String>,
+    pub(crate) changed_offers: Vec<ItemChanges>,
+    /// Offers with these errors are missing in a feed, so they can be listed as added or removed
+    pub(crate) old_errors: Vec<FeedError>,
+    pub(crate) new_errors: Vec<FeedError>,
+}
+
+/// Compares offers of two feeds that are read twice: at first offers are compared by their hashes,
+/// then fields are compared only for changed offers, so whole feeds are never kept in memory
+#[derive(Default)]
+pub(crate) struct OffersDiff {
+    old_hashes: HashMap<String, Vec<u8>>,
+    new_hashes: HashMap<String, Vec<u8>>,
+    old_offers: u64,
+    new_offers: u64,
+    /// Old versions of changed offers waiting for new ones
+    changed_offers: HashMap<String, Offer>,
+    changes: Vec<ItemChanges>,
+}
+
+impl OffersDiff {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an offer of the old feed in the first pass
+    pub(crate) fn add_old_hash(&mut self, offer: &mut Offer) {
+        self.old_offers += 1;
+        self.old_hashes.insert(offer.id.clone(), offer_hash(offer));
+    }
+
+    /// Adds an offer of the new feed in the first pass
+    pub(crate) fn add_new_hash(&mut self, offer: &mut Offer) {
+        self.new_offers += 1;
+        self.new_hashes.insert(offer.id.clone(), offer_hash(offer));
+    }
+
+    /// Checks if the second pass is needed
+    pub(crate) fn has_changed_offers(&self) -> bool {
+        self.new_hashes.keys().any(|id| self.is_changed(id))
+    }
+
+    /// Adds an offer of the old feed in the second pass, only changed offers are kept
+    pub(crate) fn add_old_offer(&mut self, offer: Offer) {
+        if self.is_changed(&offer.id) {
+            self.changed_offers.insert(offer.id.clone(), offer);
+        }
+    }
+
+    /// Adds an offer of the new feed in the second pass, all the old offers must be added before
+    pub(crate) fn add_new_offer(&mut self, offer: &Offer) {
+        if let Some(old_offer) = self.changed_offers.remove(&offer.id) {
+            self.changes.push(ItemChanges {
+                id: offer.id.clone(),
+                changes: diff_fields(&to_value(&old_offer), &to_value(offer)),
+            });
+        }
+    }
+
+    pub(crate) fn finish(
+        self,
+        old_catalog: &YmlCatalog, old_errors: &[MarketXmlError],
+        new_catalog: &YmlCatalog, new_errors: &[MarketXmlError],
+    ) -> FeedDiff {
+        let mut diff = FeedDiff {
+            catalog: diff_catalogs(old_catalog, new_catalog),
+            changed_offers: self.changes,
+            old_errors: old_errors.iter().map(FeedError::from).collect(),
+            new_errors: new_errors.iter().map(FeedError::from).collect(),
+            ..Default::default()
+        };
+        for (id, hash) in &self.new_hashes {
+            match self.old_hashes.get(id) {
+                Some(old_hash) if old_hash == hash => diff.summary.unchanged_offers += 1,
+                Some(_) => {}
+                None => diff.added_offers.push(id.clone()),
+            }
+        }
+        let new_hashes = self.new_hashes;
+        diff.removed_offers = self.old_hashes.into_keys()
+            .filter(|id| !new_hashes.contains_key(id))
+            .collect();
+        diff.removed_offers.sort_unstable();
+        diff.added_offers.sort_unstable();
+        diff.changed_offers.sort_unstable_by(|a, b| a.id.cmp(&b.id));
+
+        diff.summary.old_offers = self.old_offers;
+        diff.summary.new_offers = self.new_offers;
+        diff.summary.added_offers = diff.added_offers.len() as u64;
+        diff.summary.removed_offers = diff.removed_offers.len() as u64;
+        diff.summary.changed_offers = diff.changed_offers.len() as u64;
+        diff.summary.old_errors = diff.old_errors.len() as u64;
+        diff.summary.new_errors = diff.new_errors.len() as u64;
+        diff
+    }
+
+    fn is_changed(&self, id: &str) -> bool {
+        match (self.old_hashes.get(id), self.new_hashes.get(id)) {
+            (Some(old_hash), Some(new_hash)) => old_hash != new_hash,
+            _ => false,
+        }
+    }
+}
+
+fn diff_catalogs(old_catalog: &YmlCatalog, new_catalog: &YmlCatalog) -> CatalogDiff {
+    let old_shop = old_catalog.shop.clone().unwrap_or_default();
+    let new_shop = new_catalog.shop.clone().unwrap_or_default();
+    let mut diff = CatalogDiff::default();
+
+    if old_catalog.date != new_catalog.date {
+        diff.shop.push(FieldChange {
+            field: "date".to_string(),
+            old: Value::from(old_catalog.date.clone()),
+            new: Value::from(new_catalog.date.clone()),
+        });
+    }
+    let mut old_value = to_value(&old_shop);
+    let mut new_value = to_value(&new_shop);
+    for value in [&mut old_value, &mut new_value] {
+        if let Value::Object(fields) = value {
+            fields.remove("categories");
+            fields.remove("currencies");
+        }
+    }
+    diff.shop.extend(diff_fields(&old_value, &new_value));
+
+    let (added, removed, changed) = diff_items(
+        old_shop.categories.iter().map(|c| (c.id.to_string(), to_value(c))),
+        new_shop.categories.iter().map(|c| (c.id.to_string(), to_value(c))),
+    );
+    diff.added_categories = added;
+    diff.removed_categories = removed;
+    diff.changed_categories = changed;
+
+    let (added, removed, changed) = diff_items(
+        old_shop.currencies.iter().map(|c| (c.id.clone(), to_value(c))),
+        new_shop.currencies.iter().map(|c| (c.id.clone(), to_value(c))),
+    );
+    diff.added_currencies = added;
+    diff.removed_currencies = removed;
+    diff.changed_currencies = changed;
+
+    diff
+}
+
+/// Compares items by their ids returning added, removed and changed items
+fn diff_items(
+    old_items: impl Iterator<Item = (String, Value)>,
+    new_items: impl Iterator<Item = (String, Value)>,
+) -> (Vec<String>, Vec<String>, Vec<ItemChanges>) {
+    let mut old_items = old_items.collect::<BTreeMap<_, _>>();
+    let mut added = vec!();
+    let mut changed = vec!();
+    for (id, new_value) in new_items {
+        match old_items.remove(&id) {
+            Some(old_value) if old_value != new_value => {
+                changed.push(ItemChanges { changes: diff_fields(&old_value, &new_value), id });
+            }
+            Some(_) => {}
+            None => added.push(id),
+        }
+    }
+    (added, old_items.into_keys().collect(), changed)
+}
+
+/// Compares top level fields of two json objects
+fn diff_fields(old: &Value, new: &Value) -> Vec<FieldChange> {
+    let empty = serde_json::Map::new();
+    let old_fields = old.as_object().unwrap_or(&empty);
+    let new_fields = new.as_object().unwrap_or(&empty);
+    let mut fields = old_fields.keys().chain(new_fields.keys()).collect::<Vec<_>>();
+    fields.sort_unstable();
+    fields.dedup();
+    fields.into_iter()
+        .filter_map(|field| {
+            let old_value = old_fields.get(field).unwrap_or(&Value::Null);
+            let new_value = new_fields.get(field).unwrap_or(&Value::Null);
+            if old_value == new_value {
+                return None;
+            }
+            Some(FieldChange { field: field.clone(), old: old_value.clone(), new: new_value.clone() })
+        })
+        .collect()
+}
+
+fn to_value<T: Serialize>(value: &T) -> Value {
+    serde_json::to_value(value).unwrap_or(Value::Null)
+}
+
+/// Writes a human readable report
+pub(crate) fn write_report<W: Write>(diff: &FeedDiff, mut writer: W) -> io::Result<()> {
+    let summary = &diff.summary;
+    writeln!(
+        writer,
+        "Offers: {} -> {} (added {}, removed {}, changed {}, unchanged {})",
+        summary.old_offers, summary.new_offers,
+        summary.added_offers, summary.removed_offers, summary.changed_offers, summary.unchanged_offers,
+    )?;
+    write_errors(&mut writer, "old", &diff.old_errors)?;
+    write_errors(&mut writer, "new", &diff.new_errors)?;
+
+    let catalog = &diff.catalog;
+    if !catalog.shop.is_empty() {
+        writeln!(writer, "\nShop:")?;
+        write_changes(&mut writer, &catalog.shop, "  ")?;
+    }
+    write_items(
+        &mut writer, "Categories",
+        &catalog.added_categories, &catalog.removed_categories, &catalog.changed_categories,
+    )?;
+    write_items(
+        &mut writer, "Currencies",
+        &catalog.added_currencies, &catalog.removed_currencies, &catalog.changed_currencies,
+    )?;
+    write_items(&mut writer, "Offers", &diff.added_offers, &diff.removed_offers, &diff.changed_offers)?;
+
+    writer.flush()
+}
+
+fn write_errors<W: Write>(writer: &mut W, feed: &str, errors: &[FeedError]) -> io::Result<()> {
+    if errors.is_empty() {
+        return Ok(());
+    }
+    writeln!(
+        writer, "\nErrors of the {} feed: {}, offers with them are skipped and can be listed as added or removed",
+        feed, errors.len(),
+    )?;
+    for error in errors {
+        writeln!(writer, "  line {}, column {}: {}", error.line, error.column, error.msg)?;
+    }
+    Ok(())
+}
+
+fn write_items<W: Write>(
+    writer: &mut W, title: &str, added: &[String], removed: &[String], changed: &[ItemChanges]
+) -> io::Result<()> {
+    if added.is_empty() && removed.is_empty() && changed.is_empty() {
+        return Ok(());
+    }
+    writeln!(
+        writer, "\n{}: added {}, removed {}, changed {}",
+        title, added.len(), removed.len(), changed.len(),
+    )?;
+    for id in added {
+        writeln!(writer, "  + {}", id)?;
+    }
+    for id in removed {
+        writeln!(writer, "  - {}", id)?;
+    }
+    for item in changed {
+        writeln!(writer, "  ~ {}", item.id)?;
+        write_changes(writer, &item.changes, "      ")?;
+    }
+    Ok(())
+}
+
+fn write_changes<W: Write>(writer: &mut W, changes: &[FieldChange], indent: &str) -> io::Result<()> {
+    for change in changes {
+        writeln!(writer, "{}{}: {} -> {}", indent, change.field, change.old, change.new)?;
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::market_xml::{Category, Currency, Offer, Param, Price, Shop, YmlCatalog};
+    use crate::parser::MarketXmlError;
+    use super::{write_report, FeedDiff, FieldChange, ItemChanges, OffersDiff};
+
+    fn catalog(categories: Vec<Category>) -> YmlCatalog {
+        YmlCatalog {
+            date: "2020-01-01 00:00".to_string(),
+            shop: Some(Shop {
+                name: "Shop".to_string(),
+                currencies: vec!(Currency { id: "UAH".to_string(), rate: "1".to_string(), ..Default::default() }),
+                categories,
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn diff_feeds(
+        old_catalog: &YmlCatalog, old_offers: Vec<Offer>,
+        new_catalog: &YmlCatalog, new_offers: Vec<Offer>,
+        new_errors: &[MarketXmlError],
+    ) -> FeedDiff {
+        let mut offers_diff = OffersDiff::new();
+        for mut offer in old_offers.clone() {
+            offers_diff.add_old_hash(&mut offer);
+        }
+        for mut offer in new_offers.clone() {
+            offers_diff.add_new_hash(&mut offer);
+        }
+        assert!(offers_diff.has_changed_offers());
+        for offer in old_offers {
+            offers_diff.add_old_offer(offer);
+        }
+        for offer in &new_offers {
+            offers_diff.add_new_offer(offer);
+        }
+        offers_diff.finish(old_catalog, &[], new_catalog, new_errors)
+    }
+
+    fn offer(id: &str, price: f32) -> Offer {
+        Offer {
+            id: id.to_string(),
+            available: Some(true),
+            name: "Pan".to_string(),
+            price: Some(Price { price, from: false }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_diff_feeds() {
+        let old_catalog = catalog(vec!(
+            Category { id: 1, name: "Kitchen".to_string(), ..Default::default() },
+            Category { id: 2, parent_id: 1, name: "Pans".to_string(), ..Default::default() },
+        ));
+        let mut new_catalog = catalog(vec!(
+            Category { id: 1, name: "Kitchenware".to_string(), ..Default::default() },
+            Category { id: 3, parent_id: 1, name: "Pots".to_string(), ..Default::default() },
+        ));
+        new_catalog.date = "2020-01-02 00:00".to_string();
+        let mut changed_offer = offer("2", 120.0);
+        changed_offer.available = Some(false);
+        changed_offer.params.push(Param { name: "Color".to_string(), value: "red".to_string(), ..Default::default() });
+
+        let diff = diff_feeds(
+            &old_catalog, vec!(offer("1", 100.0), offer("2", 110.0), offer("3", 90.0)),
+            &new_catalog, vec!(offer("1", 100.0), changed_offer, offer("4", 50.0)),
+            &[MarketXmlError::Validation {
+                msg: "invalid float literal".to_string(),
+                line: 12,
+                column: 24,
+                value: "1O".to_string(),
+            }],
+        );
+
+        assert_eq!(
+            diff.catalog.shop,
+            vec!(FieldChange { field: "date".to_string(), old: json!("2020-01-01 00:00"), new: json!("2020-01-02 00:00") })
+        );
+        assert_eq!(diff.catalog.added_categories, vec!("3"));
+        assert_eq!(diff.catalog.removed_categories, vec!("2"));
+        assert_eq!(
+            diff.catalog.changed_categories,
+            vec!(ItemChanges {
+                id: "1".to_string(),
+                changes: vec!(FieldChange { field: "name".to_string(), old: json!("Kitchen"), new: json!("Kitchenware") }),
+            })
+        );
+        assert!(diff.catalog.changed_currencies.is_empty());
+        assert_eq!(diff.added_offers, vec!("4"));
+        assert_eq!(diff.removed_offers, vec!("3"));
+        let changed_fields = diff.changed_offers[0].changes.iter()
+            .map(|change| change.field.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(changed_fields, vec!("available", "params", "price"));
+        assert_eq!(diff.changed_offers[0].changes[2].new, json!({"price": 120.0}));
+        assert_eq!(diff.summary.unchanged_offers, 1);
+        assert_eq!(diff.summary.new_errors, 1);
+        assert_eq!((diff.new_errors[0].line, diff.new_errors[0].column), (12, 24));
+
+        let mut report = vec!();
+        write_report(&diff, &mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.starts_with("Offers: 3 -> 3 (added 1, removed 1, changed 1, unchanged 1)\n"));
+        assert!(report.contains("\nErrors of the new feed: 1, offers with them are skipped and can be listed as added or removed\n  line 12, column 24: "));
+        assert!(report.contains("\nOffers: added 1, removed 1, changed 1\n  + 4\n  - 3\n  ~ 2\n      available: true -> false\n"));
+    }
+}
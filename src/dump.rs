@@ -0,0 +1,347 @@
+use bytes::Bytes;
+
+use flate2::read::GzDecoder;
+
+use prost::{DecodeError, Message};
+
+use serde::Serialize;
+
+use snafu::{ResultExt, Snafu};
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+use crate::market_xml::{Error, Errors, Offer, OfferHashes, OfferIds, Warnings, YmlCatalog};
+
+#[derive(Debug, Snafu)]
+pub(crate) enum DumpError {
+    #[snafu(display("{}", source))]
+    Read { source: io::Error },
+    #[snafu(display("Error when decoding protobuf: {}", source))]
+    Decode { source: DecodeError },
+}
+
+/// Type of a file written by the parse command
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum DumpFileType {
+    Offers,
+    YmlCatalog,
+    OfferIds,
+    Errors,
+    Warnings,
+    OfferHashes,
+}
+
+impl DumpFileType {
+    /// Detects a type by a file name, compressed files are supported
+    pub(crate) fn detect(file_path: &Path) -> Option<Self> {
+        let file_name = strip_compression(file_path.file_name()?.to_str()?);
+        if file_name.starts_with("offers-") && file_name.ends_with(".protobuf-delimited") {
+            return Some(DumpFileType::Offers);
+        }
+        match file_name.strip_suffix(".protobuf")? {
+            "yml_catalog" => Some(DumpFileType::YmlCatalog),
+            "errors" => Some(DumpFileType::Errors),
+            "warnings" => Some(DumpFileType::Warnings),
+            "offer-hashes" => Some(DumpFileType::OfferHashes),
+            name if name.starts_with("offer-ids-") => Some(DumpFileType::OfferIds),
+            _ => None,
+        }
+    }
+}
+
+/// File name without an extension of a compression
+pub(crate) fn strip_compression(file_name: &str) -> &str {
+    file_name.strip_suffix(".gz")
+        .or_else(|| file_name.strip_suffix(".zst"))
+        .unwrap_or(file_name)
+}
+
+/// Opens a file decompressing it by an extension
+pub(crate) fn open_file(file_path: &Path) -> io::Result<Box<dyn BufRead>> {
+    let file = File::open(file_path)?;
+    Ok(match file_path.extension().and_then(|ext| ext.to_str()) {
+        Some("gz") => Box::new(BufReader::new(GzDecoder::new(file))),
+        Some("zst") => Box::new(BufReader::new(zstd::Decoder::new(file)?)),
+        _ => Box::new(BufReader::new(file)),
+    })
+}
+
+/// Reads a whole file decompressing it by an extension
+pub(crate) fn read_file(file_path: &Path) -> io::Result<Vec<u8>> {
+    let mut data = vec!();
+    open_file(file_path)?.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Reads length-delimited messages one by one so a whole file is never kept in memory
+pub(crate) struct DelimitedReader<R> {
+    reader: R,
+    buf: Vec<u8>,
+}
+
+impl<R: BufRead> DelimitedReader<R> {
+    pub(crate) fn new(reader: R) -> Self {
+        Self { reader, buf: vec!() }
+    }
+
+    /// Returns bytes of the next message or `None` at the end of the stream
+    pub(crate) fn next_message(&mut self) -> io::Result<Option<&[u8]>> {
+        let len = match read_varint(&mut self.reader)? {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+        // a corrupted length must not allocate a huge buffer, the buffer grows as bytes arrive
+        self.buf.clear();
+        (&mut self.reader).take(len).read_to_end(&mut self.buf)?;
+        if (self.buf.len() as u64) < len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated message"));
+        }
+        Ok(Some(&self.buf))
+    }
+}
+
+// Reads a length of a message, `None` means there are no more messages
+fn read_varint<R: BufRead>(reader: &mut R) -> io::Result<Option<u64>> {
+    let mut value = 0;
+    // a varint takes 10 bytes at most
+    for ix in 0..10 {
+        let byte = match reader.fill_buf()?.first() {
+            Some(&byte) => byte,
+            None if ix == 0 => return Ok(None),
+            None => {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated length of a message"));
+            }
+        };
+        reader.consume(1);
+        value |= u64::from(byte & 0x7f) << (7 * ix);
+        if byte & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidData, "invalid length of a message"))
+}
+
+/// A single item of a dumped file
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(untagged)]
+pub(crate) enum Record {
+    Offer(Box<Offer>),
+    YmlCatalog(Box<YmlCatalog>),
+    OfferId(String),
+    Error(Error),
+    OfferHash { id: String, hash: String },
+}
+
+impl Record {
+    /// Offer id used for filtering
+    pub(crate) fn offer_id(&self) -> Option<&str> {
+        match self {
+            Record::Offer(offer) => Some(&offer.id),
+            Record::OfferId(id) | Record::OfferHash { id, .. } => Some(id),
+            Record::YmlCatalog(_) | Record::Error(_) => None,
+        }
+    }
+
+    /// Writes a record as a single json line
+    pub(crate) fn write_json<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        serde_json::to_writer(&mut writer, self)?;
+        writeln!(writer)
+    }
+
+    pub(crate) fn write_text<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        match self {
+            Record::Offer(offer) => writeln!(writer, "{:#?}", offer),
+            Record::YmlCatalog(yml_catalog) => writeln!(writer, "{:#?}", yml_catalog),
+            Record::OfferId(id) => writeln!(writer, "{}", id),
+            Record::Error(error) => {
+                writeln!(writer, "Line {}:{}: {}: {}", error.line, error.column, error.message, error.value)
+            }
+            Record::OfferHash { id, hash } => writeln!(writer, "{} {}", id, hash),
+        }
+    }
+}
+
+/// Decodes records of a file, offers are streamed so a chunk is never kept in memory
+pub(crate) fn decode_records(
+    file_type: DumpFileType, reader: Box<dyn BufRead>
+) -> Result<Box<dyn Iterator<Item = Result<Record, DumpError>>>, DumpError> {
+    let records = match file_type {
+        DumpFileType::Offers => {
+            let mut reader = DelimitedReader::new(reader);
+            return Ok(Box::new(std::iter::from_fn(move || {
+                match reader.next_message() {
+                    Ok(Some(data)) => Some(
+                        Offer::decode(data)
+                            .map(|offer| Record::Offer(Box::new(offer)))
+                            .context(DecodeSnafu)
+                    ),
+                    Ok(None) => None,
+                    Err(e) => Some(Err(e).context(ReadSnafu)),
+                }
+            })));
+        }
+        DumpFileType::YmlCatalog => {
+            vec!(Record::YmlCatalog(Box::new(YmlCatalog::decode(read_all(reader)?).context(DecodeSnafu)?)))
+        }
+        DumpFileType::OfferIds => OfferIds::decode(read_all(reader)?).context(DecodeSnafu)?.ids.into_iter()
+            .map(Record::OfferId)
+            .collect(),
+        DumpFileType::Errors => Errors::decode(read_all(reader)?).context(DecodeSnafu)?.errors.into_iter()
+            .map(Record::Error)
+            .collect(),
+        DumpFileType::Warnings => Warnings::decode(read_all(reader)?).context(DecodeSnafu)?.warnings.into_iter()
+            .map(Record::Error)
+            .collect(),
+        DumpFileType::OfferHashes => {
+            let hashes = OfferHashes::decode(read_all(reader)?).context(DecodeSnafu)?;
+            let mut hashes = hashes.hashes.into_iter().collect::<Vec<_>>();
+            hashes.sort_unstable();
+            hashes.into_iter()
+                .map(|(id, hash)| Record::OfferHash {
+                    id,
+                    hash: hash.iter().map(|b| format!("{:02x}", b)).collect(),
+                })
+                .collect()
+        }
+    };
+    Ok(Box::new(records.into_iter().map(Ok)))
+}
+
+// files other than offer chunks contain a single message
+fn read_all(mut reader: Box<dyn BufRead>) -> Result<Bytes, DumpError> {
+    let mut data = vec!();
+    reader.read_to_end(&mut data).context(ReadSnafu)?;
+    Ok(data.into())
+}
+
+/// Calls `f` for the first `head` records and then for the last `tail` of them,
+/// only the tail is kept in memory
+pub(crate) fn for_each_selected<T, E>(
+    records: impl Iterator<Item = Result<T, E>>,
+    head: Option<usize>,
+    tail: Option<usize>,
+    mut f: impl FnMut(T) -> Result<(), E>,
+) -> Result<(), E> {
+    let records = records.take(head.unwrap_or(usize::MAX));
+    match tail {
+        None => {
+            for record in records {
+                f(record?)?;
+            }
+        }
+        Some(tail) => {
+            let mut last_records = VecDeque::with_capacity(tail);
+            for record in records {
+                let record = record?;
+                if tail == 0 {
+                    continue;
+                }
+                if last_records.len() == tail {
+                    last_records.pop_front();
+                }
+                last_records.push_back(record);
+            }
+            for record in last_records {
+                f(record)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use failure::Error;
+
+    use prost::Message;
+
+    use std::collections::HashMap;
+    use std::io::Cursor;
+    use std::path::Path;
+
+    use crate::market_xml::{Offer, OfferHashes};
+    use super::{decode_records, for_each_selected, DelimitedReader, DumpError, DumpFileType, Record};
+
+    #[test]
+    fn test_detecting_file_type() {
+        let detect = |name| DumpFileType::detect(Path::new(name));
+        assert_eq!(detect("out/offers-12.protobuf-delimited"), Some(DumpFileType::Offers));
+        assert_eq!(detect("offers-0.protobuf-delimited.zst"), Some(DumpFileType::Offers));
+        assert_eq!(detect("yml_catalog.protobuf.gz"), Some(DumpFileType::YmlCatalog));
+        assert_eq!(detect("offer-ids-available.protobuf"), Some(DumpFileType::OfferIds));
+        assert_eq!(detect("offer-hashes.protobuf"), Some(DumpFileType::OfferHashes));
+        assert_eq!(detect("warnings.protobuf"), Some(DumpFileType::Warnings));
+        assert_eq!(detect("offers-0.jsonl"), None);
+        assert_eq!(detect("manifest.json"), None);
+    }
+
+    #[test]
+    fn test_decoding_records() {
+        let mut data = vec!();
+        for id in &["1", "2"] {
+            Offer { id: id.to_string(), ..Default::default() }.encode_length_delimited(&mut data).unwrap();
+        }
+        let offer_ids = decode_records(DumpFileType::Offers, Box::new(Cursor::new(data.clone()))).unwrap()
+            .map(|record| record.unwrap().offer_id().map(|id| id.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(offer_ids, vec!(Some("1".to_string()), Some("2".to_string())));
+
+        data.pop();
+        let records = decode_records(DumpFileType::Offers, Box::new(Cursor::new(data))).unwrap()
+            .collect::<Vec<_>>();
+        assert!(matches!(records[..], [Ok(_), Err(DumpError::Read { .. })]));
+
+        let hashes = OfferHashes { hashes: HashMap::from([("1".to_string(), vec!(0, 255))]) };
+        let records = decode_records(DumpFileType::OfferHashes, Box::new(Cursor::new(hashes.encode_to_vec()))).unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let mut json = vec!();
+        records[0].write_json(&mut json).unwrap();
+        assert_eq!(String::from_utf8(json).unwrap(), "{\"id\":\"1\",\"hash\":\"00ff\"}\n");
+        assert_eq!(records[0], Record::OfferHash { id: "1".to_string(), hash: "00ff".to_string() });
+    }
+
+    #[test]
+    fn test_reading_delimited() -> Result<(), Error> {
+        let mut data = vec!();
+        for id in vec!("1".to_string(), "2".repeat(200)) {
+            Offer { id, ..Default::default() }.encode_length_delimited(&mut data)?;
+        }
+        let mut reader = DelimitedReader::new(data.as_slice());
+        assert_eq!(Offer::decode(reader.next_message()?.unwrap())?.id, "1");
+        assert_eq!(Offer::decode(reader.next_message()?.unwrap())?.id.len(), 200);
+        assert_eq!(reader.next_message()?, None);
+
+        let mut reader = DelimitedReader::new(&data[..data.len() - 1]);
+        reader.next_message()?;
+        assert!(reader.next_message().is_err());
+
+        // a corrupted length must not be allocated
+        let corrupted = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01, b'a'];
+        let mut reader = DelimitedReader::new(&corrupted[..]);
+        assert!(reader.next_message().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_selecting_records() {
+        let select = |head, tail| {
+            let mut selected = vec!();
+            for_each_selected((1..=10).map(Ok::<_, ()>), head, tail, |r| {
+                selected.push(r);
+                Ok(())
+            }).unwrap();
+            selected
+        };
+        assert_eq!(select(None, None).len(), 10);
+        assert_eq!(select(Some(3), None), vec!(1, 2, 3));
+        assert_eq!(select(None, Some(2)), vec!(9, 10));
+        assert_eq!(select(Some(5), Some(2)), vec!(4, 5));
+        assert_eq!(select(None, Some(0)), Vec::<i32>::new());
+    }
+}
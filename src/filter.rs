@@ -0,0 +1,503 @@
+use serde_json::Value;
+
+use snafu::Snafu;
+
+use std::cmp::Ordering;
+use std::iter::Peekable;
+use std::str::{CharIndices, FromStr};
+
+use crate::market_xml::Offer;
+use crate::stats::OFFER_FIELDS;
+
+#[derive(Debug, Snafu)]
+pub(crate) enum FilterError {
+    #[snafu(display("Invalid filter at position {}: {}", position, msg))]
+    Parse { msg: String, position: usize },
+}
+
+/// Boolean expression over offer fields, for example
+/// `available && price.price > 100 && category_id in (1, 2)`
+///
+/// Fields are resolved using the canonical json mapping of an offer, both snake case and camel case
+/// names are accepted. Missing fields have default values, extra fields resolve to their list of values.
+/// Comparing a list matches if any of its values match.
+#[derive(Debug, PartialEq)]
+pub(crate) struct OfferFilter {
+    expr: Expr,
+    /// Top level fields referenced by the expression, both as written and in camel case
+    fields: Vec<String>,
+}
+
+impl OfferFilter {
+    pub(crate) fn matches(&self, offer: &Offer) -> bool {
+        let mut value = serde_json::to_value(offer).unwrap_or(Value::Null);
+        if let Value::Object(ref mut fields) = value {
+            fields.retain(|name, _| self.fields.contains(name));
+        }
+        self.expr.eval(&value)
+    }
+}
+
+impl FromStr for OfferFilter {
+    type Err = FilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut parser = ExprParser { tokens, pos: 0, end: s.len() };
+        let expr = parser.parse_or()?;
+        if parser.pos < parser.tokens.len() {
+            return parser.error("unexpected token");
+        }
+        let mut fields = vec!();
+        expr.collect_fields(&mut fields);
+        fields.sort_unstable();
+        fields.dedup();
+        Ok(OfferFilter { expr, fields })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Operand, CompareOp, Operand),
+    In(Operand, Vec<Value>),
+    Truthy(Operand),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, PartialEq)]
+enum Operand {
+    Path(Vec<PathSegment>),
+    Literal(Value),
+}
+
+#[derive(Debug, PartialEq)]
+enum PathSegment {
+    Field(String),
+    Key(String),
+    Index(usize),
+}
+
+impl Expr {
+    fn eval(&self, offer: &Value) -> bool {
+        match self {
+            Expr::Or(left, right) => left.eval(offer) || right.eval(offer),
+            Expr::And(left, right) => left.eval(offer) && right.eval(offer),
+            Expr::Not(expr) => !expr.eval(offer),
+            Expr::Compare(left, op, right) => compare(&left.eval(offer), *op, &right.eval(offer)),
+            Expr::In(operand, values) => {
+                let value = operand.eval(offer);
+                values.iter().any(|v| compare(&value, CompareOp::Eq, v))
+            }
+            Expr::Truthy(operand) => is_truthy(&operand.eval(offer)),
+        }
+    }
+
+    fn collect_fields(&self, fields: &mut Vec<String>) {
+        match self {
+            Expr::Or(left, right) | Expr::And(left, right) => {
+                left.collect_fields(fields);
+                right.collect_fields(fields);
+            }
+            Expr::Not(expr) => expr.collect_fields(fields),
+            Expr::Compare(left, _, right) => {
+                left.collect_fields(fields);
+                right.collect_fields(fields);
+            }
+            Expr::In(operand, _) | Expr::Truthy(operand) => operand.collect_fields(fields),
+        }
+    }
+}
+
+impl Operand {
+    fn eval(&self, offer: &Value) -> Value {
+        match self {
+            Operand::Literal(value) => value.clone(),
+            Operand::Path(segments) => {
+                let mut value = offer;
+                for segment in segments {
+                    value = match (segment, value) {
+                        (PathSegment::Field(name), Value::Object(fields)) => {
+                            match fields.get(name) {
+                                Some(field) => field,
+                                None => fields.get(&camel_case(name)).unwrap_or(&Value::Null),
+                            }
+                        }
+                        (PathSegment::Key(key), Value::Object(fields)) => {
+                            match fields.get(key) {
+                                // extra fields are objects with a single list of values
+                                Some(Value::Object(field)) if field.len() == 1 && field.contains_key("values") => {
+                                    &field["values"]
+                                }
+                                Some(field) => field,
+                                None => &Value::Null,
+                            }
+                        }
+                        (PathSegment::Index(index), Value::Array(items)) => items.get(*index).unwrap_or(&Value::Null),
+                        _ => &Value::Null,
+                    };
+                }
+                value.clone()
+            }
+        }
+    }
+
+    fn collect_fields(&self, fields: &mut Vec<String>) {
+        if let Operand::Path(segments) = self {
+            if let Some(PathSegment::Field(name)) = segments.first() {
+                fields.push(name.clone());
+                fields.push(camel_case(name));
+            }
+        }
+    }
+}
+
+fn camel_case(name: &str) -> String {
+    let mut result = String::with_capacity(name.len());
+    let mut upper = false;
+    for c in name.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            result.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64().is_some_and(|n| n != 0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(_) => true,
+    }
+}
+
+fn compare(left: &Value, op: CompareOp, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Array(items), _) => match op {
+            CompareOp::Ne => !items.iter().any(|item| compare(item, CompareOp::Eq, right)),
+            _ => items.iter().any(|item| compare(item, op, right)),
+        },
+        (_, Value::Array(items)) => match op {
+            CompareOp::Ne => !items.iter().any(|item| compare(left, CompareOp::Eq, item)),
+            _ => items.iter().any(|item| compare(left, op, item)),
+        },
+        _ => match compare_scalars(left, right) {
+            Some(ordering) => match op {
+                CompareOp::Eq => ordering == Ordering::Equal,
+                CompareOp::Ne => ordering != Ordering::Equal,
+                CompareOp::Lt => ordering == Ordering::Less,
+                CompareOp::Le => ordering != Ordering::Greater,
+                CompareOp::Gt => ordering == Ordering::Greater,
+                CompareOp::Ge => ordering != Ordering::Less,
+            },
+            None => op == CompareOp::Ne,
+        },
+    }
+}
+
+/// Missing fields are compared as default values, 64-bit integers are json strings
+fn compare_scalars(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::Null, Value::Null) => Some(Ordering::Equal),
+        (Value::Null, Value::Bool(_) | Value::Number(_) | Value::String(_)) => {
+            compare_scalars(&default_value(right), right)
+        }
+        (Value::Bool(_) | Value::Number(_) | Value::String(_), Value::Null) => {
+            compare_scalars(left, &default_value(left))
+        }
+        (Value::Bool(l), Value::Bool(r)) => Some(l.cmp(r)),
+        (Value::String(l), Value::String(r)) => Some(l.cmp(r)),
+        (Value::Number(l), Value::Number(r)) => l.as_f64()?.partial_cmp(&r.as_f64()?),
+        (Value::Number(l), Value::String(r)) => l.as_f64()?.partial_cmp(&r.parse::<f64>().ok()?),
+        (Value::String(l), Value::Number(r)) => l.parse::<f64>().ok()?.partial_cmp(&r.as_f64()?),
+        _ => None,
+    }
+}
+
+fn default_value(value: &Value) -> Value {
+    match value {
+        Value::Bool(_) => Value::Bool(false),
+        Value::Number(_) => Value::from(0),
+        _ => Value::from(""),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Num(f64),
+    Punct(&'static str),
+}
+
+const PUNCTS: [&str; 15] = ["&&", "||", "==", "!=", "<=", ">=", "<", ">", "!", "(", ")", "[", "]", ".", ","];
+
+fn tokenize(s: &str) -> Result<Vec<(usize, Token)>, FilterError> {
+    let mut tokens = vec!();
+    let mut chars = s.char_indices().peekable();
+    while let Some(&(pos, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            tokens.push((pos, Token::Str(read_string(&mut chars, pos)?)));
+        } else if c.is_ascii_digit() || (c == '-' && s[pos + 1..].starts_with(|c: char| c.is_ascii_digit())) {
+            let end = s[pos + 1..].find(|c: char| !c.is_ascii_digit() && c != '.')
+                .map_or(s.len(), |i| pos + 1 + i);
+            let num = s[pos..end].parse::<f64>()
+                .map_err(|_| FilterError::Parse { msg: format!("invalid number: {}", &s[pos..end]), position: pos })?;
+            tokens.push((pos, Token::Num(num)));
+            while chars.peek().is_some_and(|&(i, _)| i < end) {
+                chars.next();
+            }
+        } else if c.is_alphabetic() || c == '_' {
+            let end = s[pos..].find(|c: char| !c.is_alphanumeric() && c != '_')
+                .map_or(s.len(), |i| pos + i);
+            tokens.push((pos, Token::Ident(s[pos..end].to_string())));
+            while chars.peek().is_some_and(|&(i, _)| i < end) {
+                chars.next();
+            }
+        } else {
+            let punct = PUNCTS.iter()
+                .find(|punct| s[pos..].starts_with(**punct))
+                .ok_or_else(|| FilterError::Parse { msg: format!("unexpected character {:?}", c), position: pos })?;
+            for _ in 0..punct.len() {
+                chars.next();
+            }
+            tokens.push((pos, Token::Punct(punct)));
+        }
+    }
+    Ok(tokens)
+}
+
+fn read_string(chars: &mut Peekable<CharIndices>, start: usize) -> Result<String, FilterError> {
+    let mut value = String::new();
+    while let Some((_, c)) = chars.next() {
+        match c {
+            '"' => return Ok(value),
+            '\\' => match chars.next() {
+                Some((_, 'n')) => value.push('\n'),
+                Some((_, 't')) => value.push('\t'),
+                Some((_, c)) => value.push(c),
+                None => break,
+            },
+            c => value.push(c),
+        }
+    }
+    Err(FilterError::Parse { msg: "unterminated string".to_string(), position: start })
+}
+
+struct ExprParser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    /// Length of the expression for errors at its end
+    end: usize,
+}
+
+impl ExprParser {
+    fn error<T>(&self, msg: &str) -> Result<T, FilterError> {
+        let position = self.tokens.get(self.pos).map_or(self.end, |(position, _)| *position);
+        Err(FilterError::Parse { msg: msg.to_string(), position })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn eat_punct(&mut self, punct: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Punct(p)) if *p == punct) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect_punct(&mut self, punct: &str) -> Result<(), FilterError> {
+        if !self.eat_punct(punct) {
+            return self.error(&format!("expected `{}`", punct));
+        }
+        Ok(())
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, FilterError> {
+        let mut expr = self.parse_and()?;
+        while self.eat_punct("||") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, FilterError> {
+        let mut expr = self.parse_unary()?;
+        while self.eat_punct("&&") {
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, FilterError> {
+        if self.eat_punct("!") {
+            return Ok(Expr::Not(Box::new(self.parse_unary()?)));
+        }
+        if self.eat_punct("(") {
+            let expr = self.parse_or()?;
+            self.expect_punct(")")?;
+            return Ok(expr);
+        }
+        let operand = self.parse_operand()?;
+        let op = match self.peek() {
+            Some(Token::Punct("==")) => CompareOp::Eq,
+            Some(Token::Punct("!=")) => CompareOp::Ne,
+            Some(Token::Punct("<")) => CompareOp::Lt,
+            Some(Token::Punct("<=")) => CompareOp::Le,
+            Some(Token::Punct(">")) => CompareOp::Gt,
+            Some(Token::Punct(">=")) => CompareOp::Ge,
+            Some(Token::Ident(ident)) if ident == "in" => {
+                self.pos += 1;
+                self.expect_punct("(")?;
+                let mut values = vec!(self.parse_literal()?);
+                while self.eat_punct(",") {
+                    values.push(self.parse_literal()?);
+                }
+                self.expect_punct(")")?;
+                return Ok(Expr::In(operand, values));
+            }
+            _ => return Ok(Expr::Truthy(operand)),
+        };
+        self.pos += 1;
+        Ok(Expr::Compare(operand, op, self.parse_operand()?))
+    }
+
+    fn parse_literal(&mut self) -> Result<Value, FilterError> {
+        let start = self.pos;
+        match self.parse_operand()? {
+            Operand::Literal(value) => Ok(value),
+            Operand::Path(_) => {
+                self.pos = start;
+                self.error("expected a literal")
+            }
+        }
+    }
+
+    fn parse_operand(&mut self) -> Result<Operand, FilterError> {
+        let ident = match self.peek() {
+            Some(Token::Str(s)) => Operand::Literal(Value::from(s.clone())),
+            Some(Token::Num(n)) => Operand::Literal(Value::from(*n)),
+            Some(Token::Ident(ident)) => match ident.as_str() {
+                "true" => Operand::Literal(Value::Bool(true)),
+                "false" => Operand::Literal(Value::Bool(false)),
+                "null" => Operand::Literal(Value::Null),
+                _ => return self.parse_path(),
+            },
+            _ => return self.error("expected a field or a literal"),
+        };
+        self.pos += 1;
+        Ok(ident)
+    }
+
+    fn parse_path(&mut self) -> Result<Operand, FilterError> {
+        let mut segments = vec!();
+        if let Some(Token::Ident(ident)) = self.peek() {
+            let is_offer_field = OFFER_FIELDS.iter()
+                .any(|(field, _)| *field == ident.as_str() || camel_case(field) == *ident);
+            if !is_offer_field {
+                return self.error(&format!("unknown offer field `{}`", ident));
+            }
+            segments.push(PathSegment::Field(ident.clone()));
+            self.pos += 1;
+        }
+        loop {
+            if self.eat_punct(".") {
+                match self.peek() {
+                    Some(Token::Ident(ident)) => segments.push(PathSegment::Field(ident.clone())),
+                    _ => return self.error("expected a field name"),
+                }
+                self.pos += 1;
+            } else if self.eat_punct("[") {
+                match self.peek() {
+                    Some(Token::Str(key)) => segments.push(PathSegment::Key(key.clone())),
+                    Some(Token::Num(n)) if *n >= 0.0 && n.fract() == 0.0 => {
+                        segments.push(PathSegment::Index(*n as usize))
+                    }
+                    _ => return self.error("expected a string key or an index"),
+                }
+                self.pos += 1;
+                self.expect_punct("]")?;
+            } else {
+                return Ok(Operand::Path(segments));
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::market_xml::{Offer, OfferExtraField, Price};
+    use super::OfferFilter;
+
+    fn matches(filter: &str, offer: &Offer) -> bool {
+        filter.parse::<OfferFilter>().unwrap().matches(offer)
+    }
+
+    #[test]
+    fn test_filtering_offers() {
+        let offer = Offer {
+            id: "1".to_string(),
+            available: Some(true),
+            category_id: 2,
+            price: Some(Price { price: 150.0, from: false }),
+            pictures: vec!("a.jpg".to_string(), "b.jpg".to_string()),
+            extra_fields: HashMap::from([(
+                "supplier_id".to_string(),
+                OfferExtraField { values: vec!("x".to_string()) },
+            )]),
+            ..Default::default()
+        };
+        assert!(matches("available && price.price > 100 && category_id in (1, 2)", &offer));
+        assert!(matches("extra_fields[\"supplier_id\"] == \"x\"", &offer));
+        assert!(matches("pictures == \"b.jpg\" && pictures[0] == \"a.jpg\"", &offer));
+        assert!(matches("!(price.price <= 150) || categoryId != 3", &offer));
+        assert!(matches("vendor == \"\" && bid == 0 && !delivery", &offer));
+        assert!(!matches("available && price.price > 200", &offer));
+        assert!(!matches("extra_fields[\"missing\"] == \"x\" || old_price.price >= 1", &offer));
+        assert!(!matches("category_id in (-1, 3)", &offer));
+
+        let filter = "available && price.price > 100 && category_id in (1, 2)".parse::<OfferFilter>().unwrap();
+        assert_eq!(filter.fields, vec!("available", "categoryId", "category_id", "price"));
+    }
+
+    #[test]
+    fn test_invalid_filters() {
+        let error = |filter: &str| filter.parse::<OfferFilter>().unwrap_err().to_string();
+        assert_eq!(error("available &&"), "Invalid filter at position 12: expected a field or a literal");
+        assert_eq!(error("price.price > 1)"), "Invalid filter at position 15: unexpected token");
+        assert_eq!(error("id in (name)"), "Invalid filter at position 7: expected a literal");
+        assert_eq!(error("id in (1, price.price)"), "Invalid filter at position 10: expected a literal");
+        assert_eq!(error("name == \"x"), "Invalid filter at position 8: unterminated string");
+        assert_eq!(error("id = 1"), "Invalid filter at position 3: unexpected character '='");
+        assert_eq!(error("available && prise > 1"), "Invalid filter at position 13: unknown offer field `prise`");
+        assert!("oldPrice.price > 1 && old_price.price < 2".parse::<OfferFilter>().is_ok());
+    }
+}
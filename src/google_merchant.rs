@@ -0,0 +1,822 @@
+use quick_xml::{PositionWithLine, Reader as XmlReader, Error as XmlError};
+use quick_xml::events::{Event, BytesStart};
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::prelude::BufRead;
+use std::mem;
+use std::str::{self, FromStr};
+
+use crate::lenient;
+use crate::market_xml::{
+    Condition, DeliveryOption, Offer, Param, Price, Shop, YmlCatalog,
+};
+use crate::parser::{
+    self, CategoryPaths, FeedParser, MarketXmlConfig, MarketXmlError, MarketXmlWarning, Namespaces,
+    ParsedItem,
+};
+
+const GOOGLE_NAMESPACE: &[u8] = b"http://base.google.com/ns/1.0";
+const ATOM_NAMESPACE: &[u8] = b"http://www.w3.org/2005/Atom";
+
+// Attributes of a product that are stored as offer params
+const PARAM_FIELDS: &[&str] = &[
+    "color", "size", "gender", "age_group", "material", "pattern", "size_type", "size_system",
+];
+
+/// Parser of Google Merchant Center feeds, both RSS 2.0 and Atom 1.0 ones
+pub(crate) struct GoogleMerchantParser<B: BufRead> {
+    config: MarketXmlConfig,
+    xml_reader: XmlReader<B, PositionWithLine>,
+    buf: Vec<u8>,
+    ns: Namespaces,
+    state: State,
+    yml_catalog: YmlCatalog,
+    categories: CategoryPaths,
+    /// An item is being parsed, so an error belongs to it
+    in_item: bool,
+    warnings: Vec<MarketXmlWarning>,
+}
+
+/// Vocabulary of an element resolved by its namespace
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum Vocabulary {
+    /// Product attributes like `g:id`
+    Google,
+    /// Elements without a namespace, product attributes are often written without a prefix too
+    Rss,
+    Atom,
+    Other,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum State {
+    Begin,
+    Channel,
+    End,
+}
+
+impl<B: BufRead> FeedParser for GoogleMerchantParser<B> {
+    fn next_item(&mut self) -> Result<ParsedItem, MarketXmlError> {
+        self.in_item = false;
+        loop {
+            match self.state {
+                State::Begin => {
+                    self.state = self.begin()?;
+                }
+                State::Channel => {
+                    match self.parse_channel()? {
+                        Some(offer) => {
+                            return Ok(ParsedItem::Offer(offer));
+                        }
+                        None => {
+                            self.state = State::End;
+                            return Ok(ParsedItem::YmlCatalog(self.yml_catalog.clone()));
+                        }
+                    }
+                }
+                State::End => {
+                    return Ok(ParsedItem::Eof);
+                }
+            }
+        }
+    }
+
+    fn take_warnings(&mut self) -> Vec<MarketXmlWarning> {
+        mem::take(&mut self.warnings)
+    }
+
+    fn in_offer(&self) -> bool {
+        self.in_item
+    }
+
+    fn buffer_position(&self) -> usize {
+        self.xml_reader.buffer_position()
+    }
+}
+
+impl<B: BufRead> GoogleMerchantParser<B> {
+    pub(crate) fn new(config: MarketXmlConfig, reader: B) -> Self {
+        let mut xml_reader = XmlReader::from_reader_with_position_tracker(
+            reader, PositionWithLine::default()
+        );
+        // whitespaces are handled by the whitespace policy
+        xml_reader.trim_text(false);
+        Self {
+            config,
+            xml_reader,
+            buf: vec!(),
+            ns: Namespaces::default(),
+            state: State::Begin,
+            yml_catalog: YmlCatalog::default(),
+            categories: CategoryPaths::default(),
+            in_item: false,
+            warnings: vec!(),
+        }
+    }
+
+    fn cur_line(&self) -> usize {
+        self.xml_reader.position().line()
+    }
+
+    fn cur_column(&self) -> usize {
+        self.xml_reader.position().column()
+    }
+
+    fn warn(&mut self, msg: String, value: String) {
+        self.warnings.push(MarketXmlWarning {
+            msg,
+            line: self.cur_line(),
+            column: self.cur_column(),
+            value,
+        });
+    }
+
+    fn xml_err(&self, source: XmlError) -> MarketXmlError {
+        MarketXmlError::Xml {
+            source,
+            line: self.cur_line(),
+            column: self.cur_column(),
+        }
+    }
+
+    fn validation_err(&self, msg: &str, value: String) -> MarketXmlError {
+        MarketXmlError::Validation {
+            msg: msg.to_string(),
+            line: self.cur_line(),
+            column: self.cur_column(),
+            value,
+        }
+    }
+
+    fn next_event(&mut self) -> Result<Event<'_>, MarketXmlError> {
+        parser::read_event(&mut self.xml_reader, &mut self.buf, &mut self.ns)
+    }
+
+    // Vocabulary of the last read element
+    fn vocabulary(&self) -> Vocabulary {
+        match self.ns.foreign() {
+            None => Vocabulary::Rss,
+            Some(ns) if ns == GOOGLE_NAMESPACE => Vocabulary::Google,
+            Some(ns) if ns == ATOM_NAMESPACE => Vocabulary::Atom,
+            Some(_) => Vocabulary::Other,
+        }
+    }
+
+    fn begin(&mut self) -> Result<State, MarketXmlError> {
+        loop {
+            match self.next_event()? {
+                Event::Start(tag) => {
+                    match (self.vocabulary(), tag.local_name()) {
+                        (Vocabulary::Rss, b"rss") | (Vocabulary::Atom, b"feed") => return Ok(State::Channel),
+                        _ => {
+                            return Err(MarketXmlError::UnexpectedTag {
+                                tag: String::from_utf8_lossy(tag.name()).to_string(),
+                                line: self.cur_line(),
+                                column: self.cur_column(),
+                            });
+                        }
+                    }
+                }
+                Event::Eof => {
+                    return Err(self.xml_err(XmlError::UnexpectedEof("Feed".to_string())));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Reads fields of a channel up to the next item,
+    // returns `None` when the channel is over
+    fn parse_channel(&mut self) -> Result<Option<Offer>, MarketXmlError> {
+        loop {
+            let event = self.next_event()?.into_owned();
+            let vocabulary = self.vocabulary();
+            match event {
+                Event::Start(tag) => {
+                    match (vocabulary, tag.local_name()) {
+                        // rss wraps all the fields into a channel
+                        (Vocabulary::Rss, b"channel") => {}
+                        (Vocabulary::Rss, b"item") | (Vocabulary::Atom, b"entry") => {
+                            return self.parse_item().map(Some);
+                        }
+                        (Vocabulary::Rss | Vocabulary::Atom, _) => self.parse_channel_field(&tag)?,
+                        _ => {
+                            self.read_nested_text()?;
+                        }
+                    }
+                }
+                Event::Empty(tag) if vocabulary == Vocabulary::Atom && tag.local_name() == b"link" => {
+                    if let Some(href) = self.link_href(&tag)? {
+                        get_shop(&mut self.yml_catalog).url = href;
+                    }
+                }
+                Event::End(tag) => {
+                    if let (Vocabulary::Rss, b"rss") | (Vocabulary::Atom, b"feed") = (vocabulary, tag.local_name()) {
+                        let categories = self.categories.categories().to_vec();
+                        get_shop(&mut self.yml_catalog).categories = categories;
+                        return Ok(None);
+                    }
+                }
+                Event::Eof => {
+                    return Err(self.xml_err(XmlError::UnexpectedEof("Feed".to_string())));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn parse_channel_field(&mut self, tag: &BytesStart) -> Result<(), MarketXmlError> {
+        match tag.local_name() {
+            b"title" => {
+                get_shop(&mut self.yml_catalog).name = self.read_text()?;
+            }
+            b"link" => {
+                // atom links can have a content but their target is an attribute anyway
+                let href = self.link_href(tag)?;
+                let text = self.read_nested_text()?;
+                get_shop(&mut self.yml_catalog).url = href.unwrap_or(text);
+            }
+            b"lastBuildDate" | b"pubDate" | b"updated" => {
+                self.yml_catalog.date = self.read_text()?;
+            }
+            _ => {
+                self.read_nested_text()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_item(&mut self) -> Result<Offer, MarketXmlError> {
+        self.in_item = true;
+        let item_depth = self.ns.depth();
+        let mut offer = Offer::default();
+        if let Err(e) = self.parse_item_fields(&mut offer) {
+            // the rest of the item must not be taken for channel fields,
+            // an xml error is fatal anyway
+            if !matches!(e, MarketXmlError::Xml { .. }) {
+                self.skip_to_end(item_depth)?;
+            }
+            return Err(e);
+        }
+        Ok(offer)
+    }
+
+    fn parse_item_fields(&mut self, offer: &mut Offer) -> Result<(), MarketXmlError> {
+        let mut price = None;
+        let mut sale_price = None;
+        let mut image_link = None;
+        loop {
+            let event = self.next_event()?.into_owned();
+            let vocabulary = self.vocabulary();
+            match event {
+                Event::Start(tag) => {
+                    match (vocabulary, tag.local_name()) {
+                        (Vocabulary::Google | Vocabulary::Rss, b"price") => {
+                            price = self.read_price(offer)?;
+                        }
+                        (Vocabulary::Google | Vocabulary::Rss, b"sale_price") => {
+                            sale_price = self.read_price(offer)?;
+                        }
+                        (Vocabulary::Google | Vocabulary::Rss, b"image_link") => {
+                            image_link = Some(self.read_text()?);
+                        }
+                        (Vocabulary::Google, b"link") => {
+                            offer.url = self.read_text()?;
+                        }
+                        (Vocabulary::Rss | Vocabulary::Atom, _) => self.parse_feed_field(&tag, offer)?,
+                        (Vocabulary::Google, _) => self.parse_item_field(&tag, offer)?,
+                        (Vocabulary::Other, _) => {
+                            self.read_nested_text()?;
+                        }
+                    }
+                }
+                Event::Empty(tag) if vocabulary == Vocabulary::Atom && tag.local_name() == b"link" => {
+                    if let Some(href) = self.link_href(&tag)? {
+                        // product attributes take precedence over the feed ones
+                        if offer.url.is_empty() {
+                            offer.url = href;
+                        }
+                    }
+                }
+                Event::End(_) => break,
+                Event::Eof => {
+                    return Err(self.xml_err(XmlError::UnexpectedEof("Item".to_string())));
+                }
+                _ => {}
+            }
+        }
+        if let Some(image_link) = image_link {
+            offer.pictures.insert(0, image_link);
+        }
+        match sale_price {
+            Some(sale_price) => {
+                offer.old_price = price;
+                offer.price = Some(sale_price);
+            }
+            None => {
+                offer.price = price;
+            }
+        }
+        Ok(())
+    }
+
+    // Fields of an rss item or an atom entry, they are overridden by the product attributes
+    fn parse_feed_field(&mut self, tag: &BytesStart, offer: &mut Offer) -> Result<(), MarketXmlError> {
+        let is_atom = self.vocabulary() == Vocabulary::Atom;
+        match tag.local_name() {
+            b"title" => {
+                let title = self.read_text()?;
+                if offer.name.is_empty() {
+                    offer.name = title;
+                }
+            }
+            b"link" => {
+                // atom links can have a content but their target is an attribute anyway
+                let href = self.link_href(tag)?;
+                let text = self.read_nested_text()?;
+                if offer.url.is_empty() {
+                    offer.url = href.unwrap_or(text);
+                }
+            }
+            b"description" | b"summary" | b"content" => {
+                let description = self.read_text()?;
+                if offer.description.is_empty() {
+                    offer.description = description;
+                }
+            }
+            // an atom id is an uri of the entry rather than an id of the product
+            _ if is_atom => {
+                self.read_nested_text()?;
+            }
+            b"guid" | b"pubDate" | b"author" | b"category" | b"comments" | b"enclosure" | b"source" => {
+                self.read_nested_text()?;
+            }
+            _ => self.parse_item_field(tag, offer)?,
+        }
+        Ok(())
+    }
+
+    fn parse_item_field(&mut self, tag: &BytesStart, offer: &mut Offer) -> Result<(), MarketXmlError> {
+        let field_name = tag.local_name();
+        match field_name {
+            b"id" => {
+                offer.id = self.read_text()?;
+            }
+            b"title" => {
+                offer.name = self.read_text()?;
+            }
+            b"description" | b"summary" | b"content" => {
+                offer.description = self.read_text()?;
+            }
+            b"additional_image_link" => {
+                offer.pictures.push(self.read_text()?);
+            }
+            b"availability" => {
+                let availability = self.read_text()?;
+                offer.available = match parser::parse_presence(&availability) {
+                    Some(available) => Some(available),
+                    None => return Err(self.validation_err("unknown availability", availability)),
+                };
+            }
+            b"brand" => {
+                offer.vendor = self.read_text()?;
+            }
+            b"mpn" => {
+                offer.vendor_code = self.read_text()?;
+            }
+            b"gtin" => {
+                offer.barcodes.push(self.read_text()?);
+            }
+            b"condition" => {
+                let condition = self.read_text()?;
+                // yml describes a condition of used goods only
+                if condition != "new" {
+                    offer.condition = Some(Condition { r#type: condition, reason: String::new() });
+                }
+            }
+            b"product_type" => {
+                let product_type = self.read_text()?;
+                // the first product type is the main one
+                if offer.category_id == 0 {
+                    offer.category_id = self.categories.category_id(&product_type);
+                }
+            }
+            b"item_group_id" => {
+                let item_group_id = self.read_text()?;
+                match item_group_id.parse() {
+                    Ok(group_id) => offer.group_id = group_id,
+                    Err(_) => add_extra_field(offer, "item_group_id", item_group_id),
+                }
+            }
+            b"adult" => {
+                let adult = self.read_text()?;
+                offer.adult = match lenient::parse_bool(&adult) {
+                    Some(adult) => adult,
+                    None => return Err(self.validation_err("invalid boolean", adult)),
+                };
+            }
+            b"shipping" => {
+                let shipping = self.parse_shipping()?;
+                offer.delivery_options.push(shipping);
+            }
+            b"shipping_weight" => {
+                let weight = self.read_text()?;
+                offer.weight = self.parse_weight(weight)?;
+            }
+            _ => {
+                let name = self.decode_value(field_name)?.to_string();
+                let value = self.read_nested_text()?;
+                if PARAM_FIELDS.contains(&name.as_str()) {
+                    offer.params.push(Param { name, value, ..Default::default() });
+                } else {
+                    add_extra_field(offer, &name, value);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // `<g:shipping>` holds country, region, service, price and transit times
+    fn parse_shipping(&mut self) -> Result<DeliveryOption, MarketXmlError> {
+        let mut shipping = DeliveryOption::default();
+        let mut fields = HashMap::new();
+        loop {
+            match self.next_event()?.into_owned() {
+                Event::Start(tag) => {
+                    let name = tag.local_name().to_vec();
+                    fields.insert(name, self.read_text()?);
+                }
+                Event::End(_) => break,
+                Event::Eof => {
+                    return Err(self.xml_err(XmlError::UnexpectedEof("Shipping".to_string())));
+                }
+                _ => {}
+            }
+        }
+        if let Some(price) = fields.remove(&b"price"[..]) {
+            let (cost, _) = self.parse_amount::<f32>(price)?;
+            shipping.cost = cost.round() as u32;
+        }
+        let min_days = fields.remove(&b"min_transit_time"[..]).unwrap_or_default();
+        let max_days = fields.remove(&b"max_transit_time"[..]).unwrap_or_default();
+        shipping.days = match (min_days.is_empty(), max_days.is_empty()) {
+            (false, false) if min_days != max_days => format!("{}-{}", min_days, max_days),
+            (false, _) => min_days,
+            (true, _) => max_days,
+        };
+        Ok(shipping)
+    }
+
+    // Reads a price like `15.00 USD`, the currency becomes a currency of the offer
+    fn read_price(&mut self, offer: &mut Offer) -> Result<Option<Price>, MarketXmlError> {
+        let price = self.read_text()?;
+        if price.is_empty() {
+            return Ok(None);
+        }
+        let (value, currency) = self.parse_amount(price)?;
+        if let Some(currency) = currency {
+            offer.currency_id = currency;
+        }
+        Ok(Some(Price { price: value, from: false }))
+    }
+
+    // Converts a weight like `2.5 kg` to kilograms
+    fn parse_weight(&mut self, weight: String) -> Result<f32, MarketXmlError> {
+        let (value, unit) = self.parse_amount::<f32>(weight.clone())?;
+        let kilograms = match unit.as_deref() {
+            None | Some("kg") => 1.0,
+            Some("g") => 0.001,
+            Some("lb") => 0.453_592,
+            Some("oz") => 0.028_35,
+            Some(_) => return Err(self.validation_err("unknown weight unit", weight)),
+        };
+        Ok(value * kilograms)
+    }
+
+    // Splits an amount into a number and an optional unit, that can precede the number
+    fn parse_amount<T>(&mut self, amount: String) -> Result<(T, Option<String>), MarketXmlError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let amount = amount.trim();
+        let is_unit = |c: char| c.is_alphabetic();
+        let (number, unit) = if amount.starts_with(is_unit) {
+            let number_ix = amount.find(|c: char| !is_unit(c)).unwrap_or(amount.len());
+            (&amount[number_ix..], &amount[..number_ix])
+        } else {
+            let unit_ix = amount.find(is_unit).unwrap_or(amount.len());
+            (&amount[..unit_ix], &amount[unit_ix..])
+        };
+        let (value, coerced_from) = parser::parse_str(
+            number.trim(), self.config.lenient, self.cur_line(), self.cur_column()
+        )?;
+        if let Some(value) = coerced_from {
+            self.warn("Non-standard value was coerced".to_string(), value);
+        }
+        let unit = unit.trim();
+        Ok((value, if unit.is_empty() { None } else { Some(unit.to_string()) }))
+    }
+
+    fn link_href(&self, tag: &BytesStart) -> Result<Option<String>, MarketXmlError> {
+        for attr_res in tag.attributes() {
+            let attr = attr_res.map_err(|e| self.xml_err(e))?;
+            if attr.key == b"href" {
+                let href = attr.unescaped_value().map_err(|e| self.xml_err(e))?;
+                return Ok(Some(self.decode_value(&href)?.to_string()));
+            }
+        }
+        Ok(None)
+    }
+
+    fn decode_value<'b>(&self, v: &'b [u8]) -> Result<&'b str, MarketXmlError> {
+        str::from_utf8(v)
+            .map_err(|e| {
+                MarketXmlError::InvalidUtf8 {
+                    msg: format!("{}", e),
+                    value: String::from_utf8_lossy(v).to_string(),
+                    line: self.cur_line(),
+                    column: self.cur_column(),
+                }
+            })
+    }
+
+    fn read_text(&mut self) -> Result<String, MarketXmlError> {
+        let text = parser::read_element_text(&mut self.xml_reader, &mut self.buf, &mut self.ns)?;
+        Ok(self.config.whitespace.apply(text))
+    }
+
+    /// Reads a text of the current element like `read_text` does but does not fail
+    /// on child elements. Their texts are joined with colons like Google Merchant
+    /// does in text feeds: `US:CA:Overnight:16.00 USD`.
+    fn read_nested_text(&mut self) -> Result<String, MarketXmlError> {
+        let mut texts = vec!();
+        let mut text = String::new();
+        let mut depth = 0;
+        loop {
+            match self.next_event()?.into_owned() {
+                Event::Text(tag_text) |
+                Event::CData(tag_text) => {
+                    let bytes = tag_text.unescaped().map_err(|e| self.xml_err(e))?;
+                    text.push_str(self.decode_value(&bytes)?);
+                }
+                Event::Start(_) => {
+                    depth += 1;
+                    text.clear();
+                }
+                Event::End(_) => {
+                    let text = self.config.whitespace.apply(mem::take(&mut text));
+                    if depth == 0 {
+                        if texts.is_empty() {
+                            return Ok(text);
+                        }
+                        return Ok(texts.join(":"));
+                    }
+                    depth -= 1;
+                    if !text.is_empty() {
+                        texts.push(text);
+                    }
+                }
+                Event::Eof => {
+                    return Err(self.xml_err(XmlError::UnexpectedEof("Text".to_string())));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Skips events up to the end of an element that was started at the depth
+    fn skip_to_end(&mut self, depth: usize) -> Result<(), MarketXmlError> {
+        while self.ns.depth() >= depth {
+            if let Event::Eof = self.next_event()? {
+                return Err(self.xml_err(XmlError::UnexpectedEof("Item".to_string())));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn get_shop(yml_catalog: &mut YmlCatalog) -> &mut Shop {
+    yml_catalog.shop.get_or_insert(Shop::default())
+}
+
+fn add_extra_field(offer: &mut Offer, name: &str, value: String) {
+    offer.extra_fields.entry(name.to_string()).or_default().values.push(value);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufReader;
+
+    use failure::{bail, Error};
+
+    use crate::market_xml::{Category, DeliveryOption, Param, Price};
+    use crate::parser::{Dialect, FeedParser, MarketXmlConfig, MarketXmlError, ParsedItem};
+    use super::GoogleMerchantParser;
+
+    #[test]
+    fn test_parsing_rss() -> Result<(), Error> {
+        let xml = r#"<?xml version="1.0"?>
+        <rss version="2.0" xmlns:g="http://base.google.com/ns/1.0">
+          <channel>
+            <title>Example Store</title>
+            <link>https://example.com</link>
+            <description>Products</description>
+            <item>
+              <g:id>TV_123</g:id>
+              <g:title>LED TV</g:title>
+              <g:description><![CDATA[Full HD <b>TV</b>]]></g:description>
+              <g:link>https://example.com/tv</g:link>
+              <g:image_link>https://example.com/tv.jpg</g:image_link>
+              <g:additional_image_link>https://example.com/tv-2.jpg</g:additional_image_link>
+              <g:availability>in stock</g:availability>
+              <g:price>1 500,00 USD</g:price>
+              <g:sale_price>1299.00 USD</g:sale_price>
+              <g:brand>Acme</g:brand>
+              <g:gtin>71919219405200</g:gtin>
+              <g:mpn>TV-1</g:mpn>
+              <g:condition>refurbished</g:condition>
+              <g:product_type>Electronics &gt; TV</g:product_type>
+              <g:google_product_category>404</g:google_product_category>
+              <g:color>black</g:color>
+              <g:shipping>
+                <g:country>US</g:country>
+                <g:service>Standard</g:service>
+                <g:price>14.95 USD</g:price>
+                <g:min_transit_time>3</g:min_transit_time>
+                <g:max_transit_time>5</g:max_transit_time>
+              </g:shipping>
+              <g:shipping_weight>500 g</g:shipping_weight>
+              <g:installment>
+                <g:months>6</g:months>
+                <g:amount>50 USD</g:amount>
+              </g:installment>
+            </item>
+            <item>
+              <g:id>TV_124</g:id>
+              <g:availability>sold</g:availability>
+              <g:title>Broken TV</g:title>
+            </item>
+            <item>
+              <g:id>RADIO_1</g:id>
+              <g:availability>out of stock</g:availability>
+              <g:product_type>Electronics &gt; Audio</g:product_type>
+            </item>
+          </channel>
+        </rss>
+        "#;
+        let reader = BufReader::new(xml.as_bytes());
+        let mut config = MarketXmlConfig::for_dialect(Dialect::Yml);
+        config.lenient = true;
+        let mut parser = GoogleMerchantParser::new(config, reader);
+
+        let o = match parser.next_item()? {
+            ParsedItem::Offer(offer) => offer,
+            _ => bail!("Expected offer"),
+        };
+        assert_eq!(&o.id, "TV_123");
+        assert_eq!(&o.name, "LED TV");
+        assert_eq!(&o.description, "Full HD <b>TV</b>");
+        assert_eq!(&o.url, "https://example.com/tv");
+        assert_eq!(o.pictures, vec!("https://example.com/tv.jpg", "https://example.com/tv-2.jpg"));
+        assert_eq!(o.available, Some(true));
+        assert_eq!(o.price, Some(Price { price: 1299.0, from: false }));
+        assert_eq!(o.old_price, Some(Price { price: 1500.0, from: false }));
+        assert_eq!(&o.currency_id, "USD");
+        assert_eq!(&o.vendor, "Acme");
+        assert_eq!(o.barcodes, vec!("71919219405200"));
+        assert_eq!(&o.vendor_code, "TV-1");
+        assert_eq!(o.condition.as_ref().map(|c| c.r#type.as_str()), Some("refurbished"));
+        assert_eq!(o.category_id, 2);
+        assert_eq!(
+            o.params,
+            vec!(Param { name: "color".to_string(), value: "black".to_string(), ..Default::default() })
+        );
+        assert_eq!(
+            o.delivery_options,
+            vec!(DeliveryOption { cost: 15, days: "3-5".to_string(), order_before: None })
+        );
+        assert_eq!(o.weight, 0.5);
+        assert_eq!(o.extra_fields["google_product_category"].values, vec!("404"));
+        assert_eq!(o.extra_fields["installment"].values, vec!("6:50 USD"));
+        assert_eq!(parser.take_warnings().len(), 1);
+
+        match parser.next_item() {
+            Err(e) => assert_eq!(e.value(), Some("sold")),
+            res => bail!("Expected validation error, got {:?}", res),
+        }
+
+        let o = match parser.next_item()? {
+            ParsedItem::Offer(offer) => offer,
+            _ => bail!("Expected offer"),
+        };
+        assert_eq!(&o.id, "RADIO_1");
+        assert_eq!(o.available, Some(false));
+        assert_eq!(o.category_id, 3);
+
+        let c = match parser.next_item()? {
+            ParsedItem::YmlCatalog(yml_catalog) => yml_catalog,
+            _ => bail!("Expected yml catalog"),
+        };
+        let s = c.shop.unwrap();
+        assert_eq!(&s.name, "Example Store");
+        assert_eq!(&s.url, "https://example.com");
+        assert_eq!(
+            s.categories,
+            vec!(
+                Category { id: 1, name: "Electronics".to_string(), ..Default::default() },
+                Category { id: 2, parent_id: 1, name: "TV".to_string(), ..Default::default() },
+                Category { id: 3, parent_id: 1, name: "Audio".to_string(), ..Default::default() },
+            )
+        );
+        assert_eq!(parser.next_item()?, ParsedItem::Eof);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parsing_atom() -> Result<(), Error> {
+        let xml = r#"<?xml version="1.0"?>
+        <feed xmlns="http://www.w3.org/2005/Atom" xmlns:g="http://base.google.com/ns/1.0">
+          <title>Example Store</title>
+          <link rel="self" href="https://example.com"/>
+          <updated>2020-01-01T12:00:00Z</updated>
+          <entry>
+            <g:id>1</g:id>
+            <id>tag:example.com,2020:pan</id>
+            <title>Pan</title>
+            <link href="https://example.com/pan"/>
+            <summary>Frying pan</summary>
+            <g:price>25 EUR</g:price>
+            <g:availability>preorder</g:availability>
+          </entry>
+        </feed>
+        "#;
+        let reader = BufReader::new(xml.as_bytes());
+        let mut parser = GoogleMerchantParser::new(MarketXmlConfig::default(), reader);
+
+        let o = match parser.next_item()? {
+            ParsedItem::Offer(offer) => offer,
+            _ => bail!("Expected offer"),
+        };
+        assert_eq!(&o.id, "1");
+        assert_eq!(&o.name, "Pan");
+        assert_eq!(&o.url, "https://example.com/pan");
+        assert_eq!(&o.description, "Frying pan");
+        assert_eq!(o.price, Some(Price { price: 25.0, from: false }));
+        assert_eq!(&o.currency_id, "EUR");
+        assert_eq!(o.available, Some(false));
+
+        let c = match parser.next_item()? {
+            ParsedItem::YmlCatalog(yml_catalog) => yml_catalog,
+            _ => bail!("Expected yml catalog"),
+        };
+        assert_eq!(&c.date, "2020-01-01T12:00:00Z");
+        let s = c.shop.unwrap();
+        assert_eq!(&s.name, "Example Store");
+        assert_eq!(&s.url, "https://example.com");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_skipping_invalid_item() -> Result<(), Error> {
+        let mut xml = br#"<?xml version="1.0"?>
+        <rss version="2.0" xmlns:g="http://base.google.com/ns/1.0">
+          <channel>
+            <title>Example Store</title>
+            <item>
+              <g:id>1</g:id>
+              <g:title>"#.to_vec();
+        xml.extend_from_slice(b"\xff");
+        xml.extend_from_slice(br#"</g:title>
+              <title>Not a store</title>
+            </item>
+            <item>
+              <g:id>2</g:id>
+            </item>
+          </channel>
+        </rss>
+        "#);
+        let mut parser = GoogleMerchantParser::new(MarketXmlConfig::default(), BufReader::new(xml.as_slice()));
+
+        match parser.next_item() {
+            Err(MarketXmlError::InvalidUtf8 { .. }) => {}
+            res => bail!("Expected invalid utf-8 error, got {:?}", res),
+        }
+        let o = match parser.next_item()? {
+            ParsedItem::Offer(offer) => offer,
+            _ => bail!("Expected offer"),
+        };
+        assert_eq!(&o.id, "2");
+        let c = match parser.next_item()? {
+            ParsedItem::YmlCatalog(yml_catalog) => yml_catalog,
+            _ => bail!("Expected yml catalog"),
+        };
+        assert_eq!(&c.shop.unwrap().name, "Example Store");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::html;
+use crate::market_xml::{Offer, Price, Shop};
+use crate::stats::category_key;
+use crate::yml_writer::{escape, indent};
+
+const GOOGLE_NS: &str = "http://base.google.com/ns/1.0";
+
+const MAX_DESCRIPTION_LENGTH: usize = 5000;
+const MAX_ADDITIONAL_IMAGES: usize = 10;
+
+// Offer params that have their own Google Merchant attributes
+const PARAM_ATTRIBUTES: &[(&str, &str)] = &[
+    ("color", "color"),
+    ("цвет", "color"),
+    ("колір", "color"),
+    ("size", "size"),
+    ("размер", "size"),
+    ("розмір", "size"),
+    ("material", "material"),
+    ("материал", "material"),
+    ("матеріал", "material"),
+    ("pattern", "pattern"),
+    ("узор", "pattern"),
+];
+
+/// Writes offers as a Google Merchant Center RSS 2.0 feed
+pub(crate) struct GoogleMerchantWriter<W: Write> {
+    writer: W,
+    // category key -> full path like `Home > Kitchen > Pans`
+    product_types: HashMap<String, String>,
+    // used for offers without a currency
+    base_currency: Option<String>,
+    // yml has no country of delivery options, shipping requires it
+    shipping_country: Option<String>,
+}
+
+impl<W: Write> GoogleMerchantWriter<W> {
+    /// Delivery options are written as shipping only when a country is given
+    pub(crate) fn new(writer: W, shop: &Shop, shipping_country: Option<String>) -> Self {
+        let base_currency = shop.currencies.iter()
+            .find(|currency| currency.rate == "1")
+            .map(|currency| currency.id.clone());
+        Self {
+            writer,
+            product_types: product_types(shop),
+            base_currency,
+            shipping_country,
+        }
+    }
+
+    /// Returns required Google Merchant attributes that cannot be filled from the offer
+    pub(crate) fn missing_fields(&self, offer: &Offer) -> Vec<&'static str> {
+        let mut missing = vec!();
+        if offer.id.is_empty() {
+            missing.push("id");
+        }
+        if self.title(offer).is_empty() {
+            missing.push("title");
+        }
+        if offer.description.is_empty() {
+            missing.push("description");
+        }
+        if offer.url.is_empty() {
+            missing.push("link");
+        }
+        if offer.pictures.is_empty() {
+            missing.push("image_link");
+        }
+        if offer.available.is_none() {
+            missing.push("availability");
+        }
+        if offer.price.is_none() || self.currency(offer).is_none() {
+            missing.push("price");
+        }
+        missing
+    }
+
+    pub(crate) fn write_start(&mut self, shop: &Shop) -> io::Result<()> {
+        writeln!(self.writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(self.writer, r#"<rss version="2.0" xmlns:g="{}">"#, GOOGLE_NS)?;
+        writeln!(self.writer, "  <channel>")?;
+        self.write_text("title", &shop.name, 2)?;
+        self.write_text("link", &shop.url, 2)?;
+        let description = if shop.company.is_empty() { &shop.name } else { &shop.company };
+        self.write_text("description", description, 2)
+    }
+
+    pub(crate) fn write_end(&mut self) -> io::Result<()> {
+        writeln!(self.writer, "  </channel>")?;
+        writeln!(self.writer, "</rss>")?;
+        self.writer.flush()
+    }
+
+    pub(crate) fn write_offer(&mut self, offer: &Offer) -> io::Result<()> {
+        let depth = 3;
+        writeln!(self.writer, "    <item>")?;
+        self.write_text("g:id", &offer.id, depth)?;
+        self.write_text("g:title", &self.title(offer), depth)?;
+        let description = if offer.description_text.is_empty() {
+            html::sanitize(&offer.description, Some(MAX_DESCRIPTION_LENGTH)).text
+        } else {
+            offer.description_text.chars().take(MAX_DESCRIPTION_LENGTH).collect()
+        };
+        self.write_text("g:description", &description, depth)?;
+        self.write_text("g:link", &offer.url, depth)?;
+        let mut pictures = offer.pictures.iter();
+        if let Some(picture) = pictures.next() {
+            self.write_text("g:image_link", picture, depth)?;
+        }
+        for picture in pictures.take(MAX_ADDITIONAL_IMAGES) {
+            self.write_text("g:additional_image_link", picture, depth)?;
+        }
+        let availability = match offer.available {
+            Some(true) => "in_stock",
+            Some(false) => "out_of_stock",
+            None => "",
+        };
+        self.write_text("g:availability", availability, depth)?;
+        // yml keeps a price before a discount separately
+        let (price, sale_price) = match (&offer.price, &offer.old_price) {
+            (Some(price), Some(old_price)) => (Some(old_price), Some(price)),
+            (price, _) => (price.as_ref(), None),
+        };
+        if let Some(price) = price {
+            self.write_text("g:price", &self.format_price(offer, price), depth)?;
+        }
+        if let Some(sale_price) = sale_price {
+            self.write_text("g:sale_price", &self.format_price(offer, sale_price), depth)?;
+        }
+        self.write_text("g:brand", &offer.vendor, depth)?;
+        if let Some(gtin) = offer.barcodes.first() {
+            self.write_text("g:gtin", gtin, depth)?;
+        }
+        self.write_text("g:mpn", &offer.vendor_code, depth)?;
+        if offer.barcodes.is_empty() && offer.vendor_code.is_empty() {
+            self.write_text("g:identifier_exists", "no", depth)?;
+        }
+        let condition = match offer.condition {
+            None => "new",
+            Some(ref condition) => match condition.r#type.as_str() {
+                "new" => "new",
+                "likenew" | "showcasesample" | "refurbished" => "refurbished",
+                _ => "used",
+            },
+        };
+        self.write_text("g:condition", condition, depth)?;
+        let category = category_key(offer.category_id, &offer.category_raw_id);
+        if let Some(product_type) = self.product_types.get(&category).cloned() {
+            self.write_text("g:product_type", &product_type, depth)?;
+        }
+        if offer.group_id != 0 {
+            self.write_text("g:item_group_id", &offer.group_id.to_string(), depth)?;
+        }
+        if offer.adult {
+            self.write_text("g:adult", "yes", depth)?;
+        }
+        for param in &offer.params {
+            let name = param.name.to_lowercase();
+            let attribute = PARAM_ATTRIBUTES.iter()
+                .find(|(param_name, _)| *param_name == name)
+                .map(|(_, attribute)| attribute);
+            if let Some(attribute) = attribute {
+                self.write_text(&format!("g:{}", attribute), &param.value, depth)?;
+            }
+        }
+        if let Some(country) = self.shipping_country.clone() {
+            for option in &offer.delivery_options {
+                self.write_shipping(offer, &country, option.cost, &option.days, depth)?;
+            }
+        }
+        if offer.weight != 0.0 {
+            self.write_text("g:shipping_weight", &format!("{} kg", offer.weight), depth)?;
+        }
+        writeln!(self.writer, "    </item>")
+    }
+
+    fn write_shipping(
+        &mut self, offer: &Offer, country: &str, cost: u32, days: &str, depth: usize
+    ) -> io::Result<()> {
+        indent(&mut self.writer, depth)?;
+        writeln!(self.writer, "<g:shipping>")?;
+        self.write_text("g:country", country, depth + 1)?;
+        if let Some(currency) = self.currency(offer) {
+            self.write_text("g:price", &format!("{}.00 {}", cost, currency), depth + 1)?;
+        }
+        // days are like `1`, `2-4` or empty when a delivery date is unknown
+        let mut days = days.splitn(2, '-').map(|d| d.trim());
+        let min_days = days.next().unwrap_or("");
+        let max_days = days.next().unwrap_or(min_days);
+        self.write_text("g:min_transit_time", min_days, depth + 1)?;
+        self.write_text("g:max_transit_time", max_days, depth + 1)?;
+        indent(&mut self.writer, depth)?;
+        writeln!(self.writer, "</g:shipping>")
+    }
+
+    // Google requires a title so it is composed of other fields for vendor.model offers
+    fn title(&self, offer: &Offer) -> String {
+        if !offer.name.is_empty() {
+            return offer.name.clone();
+        }
+        [&offer.type_prefix, &offer.vendor, &offer.model].iter()
+            .filter(|part| !part.is_empty())
+            .map(|part| part.as_str())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn currency(&self, offer: &Offer) -> Option<String> {
+        let currency = if offer.currency_id.is_empty() {
+            self.base_currency.as_deref()?
+        } else {
+            offer.currency_id.as_str()
+        };
+        // yml uses an outdated code of the russian ruble
+        match currency {
+            "RUR" => Some("RUB".to_string()),
+            currency => Some(currency.to_string()),
+        }
+    }
+
+    fn format_price(&self, offer: &Offer, price: &Price) -> String {
+        match self.currency(offer) {
+            Some(currency) => format!("{:.2} {}", price.price, currency),
+            None => format!("{:.2}", price.price),
+        }
+    }
+
+    fn write_text(&mut self, name: &str, value: &str, depth: usize) -> io::Result<()> {
+        if value.is_empty() {
+            return Ok(());
+        }
+        indent(&mut self.writer, depth)?;
+        writeln!(self.writer, "<{}>{}</{}>", name, escape(value, false), name)
+    }
+}
+
+fn product_types(shop: &Shop) -> HashMap<String, String> {
+    let categories = shop.categories.iter()
+        .map(|category| (category_key(category.id, &category.raw_id), category))
+        .collect::<HashMap<_, _>>();
+    let mut product_types = HashMap::new();
+    for (key, category) in &categories {
+        let mut path = vec!(category.name.as_str());
+        let mut parent_key = category_key(category.parent_id, &category.raw_parent_id);
+        // a depth limit protects from cycles in broken feeds
+        while let Some(parent) = categories.get(&parent_key).filter(|_| path.len() < categories.len()) {
+            path.push(&parent.name);
+            parent_key = category_key(parent.parent_id, &parent.raw_parent_id);
+        }
+        path.reverse();
+        product_types.insert(key.clone(), path.join(" > "));
+    }
+    product_types
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufReader;
+
+    use failure::{bail, Error};
+
+    use crate::google_merchant::GoogleMerchantParser;
+    use crate::market_xml::{Category, Currency, DeliveryOption, Offer, Param, Price, Shop};
+    use crate::parser::{FeedParser, MarketXmlConfig, ParsedItem};
+    use super::{GoogleMerchantWriter, MAX_DESCRIPTION_LENGTH};
+
+    #[test]
+    fn test_writing_google_merchant_feed() -> Result<(), Error> {
+        let shop = Shop {
+            name: "Shop".to_string(),
+            url: "http://example.com".to_string(),
+            currencies: vec!(Currency { id: "RUR".to_string(), rate: "1".to_string(), plus: "".to_string() }),
+            categories: vec!(
+                Category { id: 1, name: "Kitchen".to_string(), ..Default::default() },
+                Category { id: 2, parent_id: 1, name: "Pans".to_string(), ..Default::default() },
+            ),
+            ..Default::default()
+        };
+        let offer = Offer {
+            id: "1".to_string(),
+            available: Some(true),
+            name: "Pan".to_string(),
+            category_id: 2,
+            price: Some(Price { price: 899.0, from: false }),
+            old_price: Some(Price { price: 999.0, from: false }),
+            url: "http://example.com/pan".to_string(),
+            pictures: vec!("http://example.com/1.jpg".to_string(), "http://example.com/2.jpg".to_string()),
+            description: "<p>Frying &amp; stewing</p>".to_string(),
+            barcodes: vec!("4601234567890".to_string()),
+            params: vec!(Param { name: "Цвет".to_string(), value: "red".to_string(), ..Default::default() }),
+            delivery_options: vec!(DeliveryOption { cost: 300, days: "1-3".to_string(), order_before: None }),
+            ..Default::default()
+        };
+        let incomplete_offer = Offer {
+            id: "2".to_string(),
+            vendor: "Acme".to_string(),
+            model: "X1".to_string(),
+            price: Some(Price { price: 10.0, from: false }),
+            ..Default::default()
+        };
+
+        let mut xml = vec!();
+        let mut writer = GoogleMerchantWriter::new(&mut xml, &shop, Some("RU".to_string()));
+        assert!(writer.missing_fields(&offer).is_empty());
+        assert_eq!(
+            writer.missing_fields(&incomplete_offer),
+            vec!("description", "link", "image_link", "availability")
+        );
+        writer.write_start(&shop)?;
+        writer.write_offer(&offer)?;
+        writer.write_end()?;
+
+        let mut parser = GoogleMerchantParser::new(MarketXmlConfig::default(), BufReader::new(xml.as_slice()));
+        let o = match parser.next_item()? {
+            ParsedItem::Offer(offer) => offer,
+            _ => bail!("Expected offer"),
+        };
+        assert_eq!(&o.id, "1");
+        assert_eq!(&o.name, "Pan");
+        assert_eq!(&o.description, "Frying & stewing");
+        assert_eq!(o.pictures, offer.pictures);
+        assert_eq!(o.available, Some(true));
+        assert_eq!(o.price, offer.price);
+        assert_eq!(o.old_price, offer.old_price);
+        assert_eq!(&o.currency_id, "RUB");
+        assert_eq!(o.barcodes, offer.barcodes);
+        assert_eq!(
+            o.params,
+            vec!(Param { name: "color".to_string(), value: "red".to_string(), ..Default::default() })
+        );
+        assert!(o.condition.is_none());
+        assert_eq!(
+            o.delivery_options,
+            vec!(DeliveryOption { cost: 300, days: "1-3".to_string(), order_before: None })
+        );
+        assert!(String::from_utf8(xml.clone())?.contains("<g:country>RU</g:country>"));
+
+        let s = match parser.next_item()? {
+            ParsedItem::YmlCatalog(yml_catalog) => yml_catalog.shop.unwrap(),
+            _ => bail!("Expected yml catalog"),
+        };
+        assert_eq!(&s.name, "Shop");
+        assert_eq!(s.categories.iter().map(|c| c.name.as_str()).collect::<Vec<_>>(), vec!("Kitchen", "Pans"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_product_type_of_raw_category_ids() -> Result<(), Error> {
+        let shop = Shop {
+            categories: vec!(
+                Category { name: "Kitchen".to_string(), raw_id: "a1".to_string(), ..Default::default() },
+                Category {
+                    name: "Pans".to_string(),
+                    raw_id: "a2".to_string(),
+                    raw_parent_id: "a1".to_string(),
+                    ..Default::default()
+                },
+            ),
+            ..Default::default()
+        };
+        let offer = Offer { id: "1".to_string(), category_raw_id: "a2".to_string(), ..Default::default() };
+        let mut xml = vec!();
+        GoogleMerchantWriter::new(&mut xml, &shop, None).write_offer(&offer)?;
+        let xml = String::from_utf8(xml)?;
+        assert!(xml.contains("<g:product_type>Kitchen &gt; Pans</g:product_type>"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_limiting_description_text() -> Result<(), Error> {
+        let offer = Offer {
+            id: "1".to_string(),
+            description_text: "a".repeat(MAX_DESCRIPTION_LENGTH + 10),
+            ..Default::default()
+        };
+        let mut xml = vec!();
+        GoogleMerchantWriter::new(&mut xml, &Shop::default(), None).write_offer(&offer)?;
+        let xml = String::from_utf8(xml)?;
+        assert!(xml.contains(&format!("<g:description>{}</g:description>", "a".repeat(MAX_DESCRIPTION_LENGTH))));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,390 @@
+use std::char;
+
+const ALLOWED_TAGS: &[&str] = &[
+    "p", "br", "hr", "div", "span", "blockquote", "pre",
+    "h1", "h2", "h3", "h4", "h5", "h6",
+    "ul", "ol", "li", "dl", "dt", "dd",
+    "b", "strong", "i", "em", "u", "s", "strike", "sub", "sup", "small",
+    "table", "thead", "tbody", "tfoot", "tr", "th", "td",
+];
+
+const VOID_TAGS: &[&str] = &["br", "hr"];
+
+// Contents of these tags is never shown to a user so we drop it completely
+const SKIPPED_TAGS: &[&str] = &[
+    "script", "style", "head", "title", "noscript", "iframe", "object", "embed", "template",
+];
+
+// Tags that separate lines in a plain text
+const BLOCK_TAGS: &[&str] = &[
+    "p", "br", "hr", "div", "blockquote", "pre",
+    "h1", "h2", "h3", "h4", "h5", "h6",
+    "ul", "ol", "li", "dl", "dt", "dd",
+    "table", "tr",
+];
+
+const NAMED_ENTITIES: &[(&str, char)] = &[
+    ("amp", '&'), ("lt", '<'), ("gt", '>'), ("quot", '"'), ("apos", '\''),
+    ("nbsp", '\u{a0}'), ("shy", '\u{ad}'),
+    ("laquo", '«'), ("raquo", '»'), ("lsaquo", '‹'), ("rsaquo", '›'),
+    ("lsquo", '‘'), ("rsquo", '’'), ("sbquo", '‚'),
+    ("ldquo", '“'), ("rdquo", '”'), ("bdquo", '„'),
+    ("ndash", '–'), ("mdash", '—'), ("minus", '−'), ("hellip", '…'),
+    ("bull", '•'), ("middot", '·'), ("deg", '°'), ("plusmn", '±'), ("times", '×'),
+    ("divide", '÷'), ("frac12", '½'), ("frac14", '¼'), ("frac34", '¾'),
+    ("sup2", '²'), ("sup3", '³'), ("micro", 'µ'), ("para", '¶'), ("sect", '§'),
+    ("copy", '©'), ("reg", '®'), ("trade", '™'),
+    ("euro", '€'), ("pound", '£'), ("yen", '¥'), ("cent", '¢'),
+    ("larr", '←'), ("rarr", '→'), ("uarr", '↑'), ("darr", '↓'),
+];
+
+/// Result of a description sanitizing
+#[derive(Debug, PartialEq)]
+pub(crate) struct SanitizedHtml {
+    /// Html containing only whitelisted tags without any attributes
+    pub(crate) html: String,
+    /// Plain text with decoded entities, one line per block
+    pub(crate) text: String,
+    /// Number of characters of the plain text before truncation
+    pub(crate) text_length: usize,
+    pub(crate) truncated: bool,
+}
+
+/// Sanitizes an html fragment leaving only tags from the whitelist and extracts its plain text.
+/// When `max_length` is set both the html and the text are truncated so that
+/// the text does not exceed `max_length` characters.
+pub(crate) fn sanitize(input: &str, max_length: Option<usize>) -> SanitizedHtml {
+    let (html, text) = Sanitizer::new(input, None).run(input);
+    let text_length = text.chars().count();
+    match max_length {
+        Some(max_length) if text_length > max_length => {
+            let (html, text) = Sanitizer::new(input, Some(max_length)).run(input);
+            SanitizedHtml { html, text, text_length, truncated: true }
+        }
+        _ => SanitizedHtml { html, text, text_length, truncated: false },
+    }
+}
+
+/// Decodes html character references: named, decimal and hexadecimal ones.
+/// Unknown references are left as is.
+pub(crate) fn decode_entities(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(amp_ix) = rest.find('&') {
+        res.push_str(&rest[..amp_ix]);
+        rest = &rest[amp_ix..];
+        match parse_entity(rest) {
+            Some((c, len)) => {
+                res.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                res.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    res.push_str(rest);
+    res
+}
+
+// Returns decoded character and length of the reference including leading `&` and trailing `;`
+fn parse_entity(s: &str) -> Option<(char, usize)> {
+    let end = s[1..].find(';').map(|ix| ix + 1)?;
+    // the longest named entity we know is shorter than that
+    if end > 10 {
+        return None;
+    }
+    let name = &s[1..end];
+    let c = if let Some(code) = name.strip_prefix('#') {
+        let code = if let Some(hex) = code.strip_prefix('x').or_else(|| code.strip_prefix('X')) {
+            u32::from_str_radix(hex, 16).ok()?
+        } else {
+            code.parse().ok()?
+        };
+        char::from_u32(code)?
+    } else {
+        NAMED_ENTITIES.iter()
+            .find(|(entity, _)| *entity == name)
+            .map(|(_, c)| *c)?
+    };
+    Some((c, end + 1))
+}
+
+fn escape(s: &str, out: &mut String) {
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '\u{a0}' => out.push_str("&nbsp;"),
+            _ => out.push(c),
+        }
+    }
+}
+
+enum Token<'a> {
+    Text(&'a str),
+    Open(String, bool),
+    Close(String),
+}
+
+struct Sanitizer {
+    html: String,
+    text: String,
+    open_tags: Vec<&'static str>,
+    remaining: Option<usize>,
+}
+
+impl Sanitizer {
+    fn new(input: &str, max_length: Option<usize>) -> Self {
+        Self {
+            html: String::with_capacity(input.len()),
+            text: String::with_capacity(input.len()),
+            open_tags: vec!(),
+            remaining: max_length,
+        }
+    }
+
+    fn run(mut self, input: &str) -> (String, String) {
+        let mut rest = input;
+        let mut skipped_tag: Option<String> = None;
+        while !rest.is_empty() {
+            let (token, tail) = next_token(rest);
+            rest = tail;
+            let token = match token {
+                Some(token) => token,
+                None => continue,
+            };
+            if let Some(ref skipped) = skipped_tag {
+                if let Token::Close(ref name) = token {
+                    if name == skipped {
+                        skipped_tag = None;
+                    }
+                }
+                continue;
+            }
+            match token {
+                Token::Text(text) => self.push_text(text),
+                Token::Open(name, self_closing) => {
+                    if SKIPPED_TAGS.contains(&name.as_str()) {
+                        if !self_closing {
+                            skipped_tag = Some(name);
+                        }
+                    } else {
+                        self.open_tag(&name, self_closing);
+                    }
+                }
+                Token::Close(name) => self.close_tag(&name),
+            }
+        }
+
+        while let Some(tag) = self.open_tags.pop() {
+            self.html.push_str("</");
+            self.html.push_str(tag);
+            self.html.push('>');
+        }
+        (self.html.trim().to_string(), self.text.trim_end().to_string())
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.remaining == Some(0)
+    }
+
+    fn push_text(&mut self, raw: &str) {
+        let decoded = decode_entities(raw);
+        let mut collapsed = String::with_capacity(decoded.len());
+        let mut last_is_space = self.text.is_empty() ||
+            self.text.ends_with(|c: char| c.is_whitespace() && c != '\u{a0}');
+        for c in decoded.chars() {
+            if c.is_whitespace() && c != '\u{a0}' {
+                if !last_is_space {
+                    collapsed.push(' ');
+                    last_is_space = true;
+                }
+            } else {
+                collapsed.push(c);
+                last_is_space = false;
+            }
+        }
+
+        let visible = match self.remaining {
+            Some(ref mut remaining) => {
+                let visible = match collapsed.char_indices().nth(*remaining) {
+                    Some((ix, _)) => &collapsed[..ix],
+                    None => &collapsed[..],
+                };
+                *remaining -= visible.chars().count();
+                visible
+            }
+            None => &collapsed[..],
+        };
+        self.text.push_str(visible);
+        escape(visible, &mut self.html);
+    }
+
+    fn open_tag(&mut self, name: &str, self_closing: bool) {
+        if BLOCK_TAGS.contains(&name) {
+            self.new_line();
+        }
+        if self.is_exhausted() {
+            return;
+        }
+        let tag = match ALLOWED_TAGS.iter().find(|t| **t == name) {
+            Some(tag) => *tag,
+            None => return,
+        };
+        self.html.push('<');
+        self.html.push_str(tag);
+        self.html.push('>');
+        if !self_closing && !VOID_TAGS.contains(&tag) {
+            self.open_tags.push(tag);
+        }
+    }
+
+    fn close_tag(&mut self, name: &str) {
+        if BLOCK_TAGS.contains(&name) {
+            self.new_line();
+        }
+        // ignore closing tags that were not opened
+        let open_ix = match self.open_tags.iter().rposition(|t| *t == name) {
+            Some(ix) => ix,
+            None => return,
+        };
+        while self.open_tags.len() > open_ix {
+            let tag = self.open_tags.pop().unwrap();
+            self.html.push_str("</");
+            self.html.push_str(tag);
+            self.html.push('>');
+        }
+    }
+
+    fn new_line(&mut self) {
+        let trimmed_len = self.text.trim_end_matches(' ').len();
+        // trailing spaces were counted by `push_text`
+        if let Some(ref mut remaining) = self.remaining {
+            *remaining += self.text.len() - trimmed_len;
+        }
+        self.text.truncate(trimmed_len);
+        if !self.text.is_empty() && !self.text.ends_with('\n') && !self.is_exhausted() {
+            self.text.push('\n');
+            if let Some(ref mut remaining) = self.remaining {
+                *remaining -= 1;
+            }
+        }
+    }
+}
+
+// Returns next token and the rest of the input
+fn next_token(s: &str) -> (Option<Token<'_>>, &str) {
+    if !s.starts_with('<') {
+        let end = s.find('<').unwrap_or(s.len());
+        // `find` could point to the very first char only when it is not a tag start
+        let end = if end == 0 { 1 } else { end };
+        return (Some(Token::Text(&s[..end])), &s[end..]);
+    }
+    if let Some(comment) = s.strip_prefix("<!--") {
+        let rest = comment.find("-->")
+            .map(|ix| &comment[ix + 3..])
+            .unwrap_or("");
+        return (None, rest);
+    }
+    if s.starts_with("<!") || s.starts_with("<?") {
+        let rest = s.find('>').map(|ix| &s[ix + 1..]).unwrap_or("");
+        return (None, rest);
+    }
+
+    let (is_close, name_start) = if s[1..].starts_with('/') { (true, 2) } else { (false, 1) };
+    let name_len = s[name_start..]
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .unwrap_or(s.len() - name_start);
+    let first_char_is_letter = s[name_start..].starts_with(|c: char| c.is_ascii_alphabetic());
+    if name_len == 0 || !first_char_is_letter {
+        // not a tag, just a lonely `<`
+        return (Some(Token::Text("<")), &s[1..]);
+    }
+    let name = s[name_start..name_start + name_len].to_ascii_lowercase();
+
+    let mut quote = None;
+    let mut end = None;
+    for (ix, c) in s.char_indices().skip(name_start + name_len) {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '>') => {
+                end = Some(ix);
+                break;
+            }
+            _ => {}
+        }
+    }
+    let end = match end {
+        Some(end) => end,
+        // an unterminated tag or a mismatched quote, so `<` is just a text
+        None => return (Some(Token::Text("<")), &s[1..]),
+    };
+    let token = if is_close {
+        Token::Close(name)
+    } else {
+        Token::Open(name, s[..end].ends_with('/'))
+    };
+    (Some(token), &s[end + 1..])
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_entities, sanitize};
+
+    #[test]
+    fn test_decode_entities() {
+        assert_eq!(decode_entities("a &amp; b &lt;c&gt;"), "a & b <c>");
+        assert_eq!(decode_entities("&#1071; &#x42F; &laquo;q&raquo;"), "Я Я «q»");
+        assert_eq!(decode_entities("&unknown; & &amp"), "&unknown; & &amp");
+    }
+
+    #[test]
+    fn test_sanitize() {
+        let res = sanitize(
+            r#"<h3 class="title">Мороженица</h3>
+            <script>alert("x")</script>
+            <p style="color: red">Вкусное <b>домашнее</b> <a href="http://x">мороженое</a> &amp; десерты<br/>
+            без&nbsp;консервантов <img src="a.png"></p>
+            <div>1 < 2</div>"#,
+            None
+        );
+        assert_eq!(
+            res.html,
+            "<h3>Мороженица</h3><p>Вкусное <b>домашнее</b> мороженое &amp; десерты<br>\
+             без&nbsp;консервантов </p><div>1 &lt; 2</div>"
+        );
+        assert_eq!(
+            res.text,
+            "Мороженица\nВкусное домашнее мороженое & десерты\nбез\u{a0}консервантов\n1 < 2"
+        );
+        assert!(!res.truncated);
+
+        let res = sanitize(r#"<p>a <b class="x>c</p>"#, None);
+        assert_eq!(res.text, r#"a <b class="x>c"#);
+        let res = sanitize("<p>a <b", None);
+        assert_eq!(res.text, "a <b");
+    }
+
+    #[test]
+    fn test_sanitize_truncated() {
+        let res = sanitize("<p>Hello <b>wonderful</b> world</p><p>Bye</p>", Some(12));
+        assert_eq!(res.html, "<p>Hello <b>wonder</b></p>");
+        assert_eq!(res.text, "Hello wonder");
+        assert_eq!(res.text_length, 25);
+        assert!(res.truncated);
+
+        let res = sanitize("<p>Hello</p>", Some(5));
+        assert_eq!(res.html, "<p>Hello</p>");
+        assert!(!res.truncated);
+
+        let res = sanitize("<p>Hello </p><p>World!</p>", Some(11));
+        assert_eq!(res.text, "Hello\nWorld");
+        assert!(res.truncated);
+    }
+}
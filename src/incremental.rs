@@ -0,0 +1,124 @@
+use prost::Message;
+
+use sha2::{Digest, Sha256};
+
+use std::collections::HashMap;
+use std::mem;
+
+use crate::market_xml::{Offer, OfferHashes, OfferIds};
+
+pub(crate) const OFFER_HASHES_FILE_NAME: &str = "offer-hashes.protobuf";
+
+/// Finds new and changed offers comparing their hashes with hashes from a previous run
+pub(crate) struct OfferChanges {
+    previous: Option<HashMap<String, Vec<u8>>>,
+    current: OfferHashes,
+}
+
+impl OfferChanges {
+    pub(crate) fn new(previous: Option<OfferHashes>) -> Self {
+        Self {
+            previous: previous.map(|hashes| hashes.hashes),
+            current: OfferHashes::default(),
+        }
+    }
+
+    /// Remembers a hash of the offer and checks if the offer is new or changed,
+    /// every offer is considered changed without a previous run
+    pub(crate) fn update(&mut self, offer: &mut Offer) -> bool {
+        let hash = offer_hash(offer);
+        let changed = match self.previous {
+            Some(ref mut previous) => previous.remove(&offer.id).as_ref() != Some(&hash),
+            None => true,
+        };
+        self.current.hashes.insert(offer.id.clone(), hash);
+        changed
+    }
+
+    /// Returns hashes of the current offers and sorted ids of offers missing since the previous run
+    pub(crate) fn finish(self) -> (OfferHashes, Option<OfferIds>) {
+        // seen offers are removed from the previous ones
+        let deleted_ids = self.previous.map(|previous| {
+            let mut ids = previous.into_keys().collect::<Vec<_>>();
+            ids.sort_unstable();
+            OfferIds { ids }
+        });
+        (self.current, deleted_ids)
+    }
+}
+
+/// Truncated sha256 of an encoded offer, maps are encoded in an arbitrary order
+/// so they are hashed separately sorted by keys
+pub(crate) fn offer_hash(offer: &mut Offer) -> Vec<u8> {
+    let extra_fields = mem::take(&mut offer.extra_fields);
+    let localized = mem::take(&mut offer.localized);
+
+    let mut hasher = Sha256::new();
+    hasher.update(offer.encode_to_vec());
+    let mut extra_fields_entries = extra_fields.iter().collect::<Vec<_>>();
+    extra_fields_entries.sort_unstable_by_key(|(name, _)| *name);
+    for (name, field) in extra_fields_entries {
+        hasher.update(name.encode_length_delimited_to_vec());
+        hasher.update(field.encode_length_delimited_to_vec());
+    }
+    let mut localized_entries = localized.iter().collect::<Vec<_>>();
+    localized_entries.sort_unstable_by_key(|(lang, _)| *lang);
+    for (lang, text) in localized_entries {
+        hasher.update(lang.encode_length_delimited_to_vec());
+        hasher.update(text.encode_length_delimited_to_vec());
+    }
+
+    offer.extra_fields = extra_fields;
+    offer.localized = localized;
+    hasher.finalize()[..16].to_vec()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::market_xml::{Offer, OfferExtraField};
+    use super::{offer_hash, OfferChanges};
+
+    fn offer(id: &str, name: &str) -> Offer {
+        Offer { id: id.to_string(), name: name.to_string(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_offer_hash_ignores_order_of_extra_fields() {
+        let fields = (0..20)
+            .map(|i| (format!("field{}", i), OfferExtraField { values: vec!(i.to_string()) }))
+            .collect::<Vec<_>>();
+        let mut offer = offer("1", "Pan");
+        offer.extra_fields = fields.iter().cloned().collect::<HashMap<_, _>>();
+        let mut reversed_offer = offer.clone();
+        reversed_offer.extra_fields = fields.iter().rev().cloned().collect::<HashMap<_, _>>();
+
+        let hash = offer_hash(&mut offer);
+        assert_eq!(hash, offer_hash(&mut reversed_offer));
+        assert_eq!(offer.extra_fields.len(), 20);
+        offer.extra_fields.get_mut("field3").unwrap().values.push("x".to_string());
+        assert_ne!(hash, offer_hash(&mut offer));
+    }
+
+    #[test]
+    fn test_offer_changes() {
+        let mut changes = OfferChanges::new(None);
+        for mut offer in [offer("1", "Pan"), offer("2", "Pot"), offer("3", "Lid")] {
+            assert!(changes.update(&mut offer));
+        }
+        let (previous, deleted_ids) = changes.finish();
+        assert_eq!(deleted_ids, None);
+
+        let mut changes = OfferChanges::new(Some(previous));
+        let changed = vec!(offer("1", "Pan"), offer("2", "Big pot"), offer("4", "Cup"))
+            .into_iter()
+            .filter_map(|mut offer| if changes.update(&mut offer) { Some(offer.id) } else { None })
+            .collect::<Vec<_>>();
+        assert_eq!(changed, vec!("2", "4"));
+        let (hashes, deleted_ids) = changes.finish();
+        assert_eq!(deleted_ids.unwrap().ids, vec!("3"));
+        assert_eq!(hashes.hashes.len(), 3);
+    }
+}
@@ -0,0 +1,154 @@
+const TRUE_WORDS: &[&str] = &[
+    "true", "1", "yes", "y", "on", "+",
+    "да", "д", "есть", "так", "є",
+];
+
+const FALSE_WORDS: &[&str] = &[
+    "false", "0", "no", "n", "off", "-",
+    "нет", "н", "ні", "немає",
+];
+
+// Characters that are used to group digits
+const GROUP_SEPARATORS: &[char] = &[' ', '\u{a0}', '\u{202f}', '\u{2009}', '\''];
+
+/// Parses a boolean written in a human-friendly or a localized way
+pub(crate) fn parse_bool(s: &str) -> Option<bool> {
+    let s = s.trim().to_lowercase();
+    if TRUE_WORDS.contains(&s.as_str()) {
+        Some(true)
+    } else if FALSE_WORDS.contains(&s.as_str()) {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Converts a number with group separators and a decimal comma to the form
+/// that can be parsed by `FromStr`. Returns `None` when the string does not look like a number.
+pub(crate) fn normalize_number(s: &str) -> Option<String> {
+    let mut number: String = s.trim()
+        .chars()
+        .filter(|c| !GROUP_SEPARATORS.contains(c))
+        .collect();
+
+    let last_comma = number.rfind(',');
+    let last_dot = number.rfind('.');
+    let decimal_separator = match (last_comma, last_dot) {
+        (Some(comma), Some(dot)) => if comma > dot { Some(',') } else { Some('.') },
+        (Some(_), None) if number.matches(',').count() == 1 => Some(','),
+        (None, Some(_)) if number.matches('.').count() == 1 => Some('.'),
+        _ => None,
+    };
+    number = match decimal_separator {
+        Some(decimal_separator) => {
+            let decimal_ix = number.rfind(decimal_separator).unwrap();
+            let (int_part, fract_part) = number.split_at(decimal_ix);
+            let int_part = int_part.replace([',', '.'], "");
+            format!("{}.{}", int_part, &fract_part[1..])
+        }
+        None => number.replace([',', '.'], ""),
+    };
+
+    if is_number(&number) {
+        Some(number)
+    } else {
+        None
+    }
+}
+
+/// Checks if a number has a single separator followed by exactly three digits like `1.000`,
+/// it can be read either as a decimal or as a thousands separator
+pub(crate) fn has_ambiguous_separator(s: &str) -> bool {
+    let number: String = s.trim()
+        .chars()
+        .filter(|c| !GROUP_SEPARATORS.contains(c))
+        .collect();
+    let mut separators = number.match_indices([',', '.']);
+    match (separators.next(), separators.next()) {
+        (Some((ix, _)), None) => {
+            let fract_part = &number[ix + 1..];
+            fract_part.len() == 3 && fract_part.bytes().all(|b| b.is_ascii_digit())
+        }
+        _ => false,
+    }
+}
+
+/// Returns candidates that a non-standard value could be coerced to
+pub(crate) fn coercion_candidates(s: &str) -> Vec<String> {
+    let mut candidates = vec!();
+    if let Some(b) = parse_bool(s) {
+        candidates.push(b.to_string());
+    }
+    if let Some(number) = normalize_number(s) {
+        // integer values are often written with a zero fractional part,
+        // but `1.000` is just as likely to mean a thousand
+        let int_part = number.split('.').next().unwrap_or("");
+        if number.len() > int_part.len() && number[int_part.len() + 1..].bytes().all(|b| b == b'0') &&
+            !has_ambiguous_separator(s)
+        {
+            candidates.push(int_part.to_string());
+        }
+        candidates.push(number);
+    }
+    candidates
+}
+
+fn is_number(s: &str) -> bool {
+    let s = s.strip_prefix(|c| c == '-' || c == '+').unwrap_or(s);
+    let mut parts = s.splitn(2, '.');
+    let int_part = parts.next().unwrap_or("");
+    let fract_part = parts.next().unwrap_or("");
+    !(int_part.is_empty() && fract_part.is_empty()) &&
+        int_part.bytes().all(|b| b.is_ascii_digit()) &&
+        fract_part.bytes().all(|b| b.is_ascii_digit())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{coercion_candidates, has_ambiguous_separator, normalize_number, parse_bool};
+
+    #[test]
+    fn test_normalize_number() {
+        assert_eq!(normalize_number("8 990,00").as_deref(), Some("8990.00"));
+        assert_eq!(normalize_number("8\u{a0}990").as_deref(), Some("8990"));
+        assert_eq!(normalize_number("1.234,50").as_deref(), Some("1234.50"));
+        assert_eq!(normalize_number("1,234.50").as_deref(), Some("1234.50"));
+        assert_eq!(normalize_number("1.234.567").as_deref(), Some("1234567"));
+        assert_eq!(normalize_number("3,6").as_deref(), Some("3.6"));
+        assert_eq!(normalize_number("-0,5").as_deref(), Some("-0.5"));
+        assert_eq!(normalize_number("12 шт"), None);
+        assert_eq!(normalize_number(""), None);
+        assert_eq!(normalize_number(","), None);
+    }
+
+    #[test]
+    fn test_ambiguous_separator() {
+        assert!(has_ambiguous_separator("1.000"));
+        assert!(has_ambiguous_separator("-12,500"));
+        assert!(!has_ambiguous_separator("1.000,00"));
+        assert!(!has_ambiguous_separator("1.000.000"));
+        assert!(!has_ambiguous_separator("2,00"));
+        assert!(!has_ambiguous_separator("1 000"));
+    }
+
+    #[test]
+    fn test_parse_bool() {
+        assert_eq!(parse_bool("Да"), Some(true));
+        assert_eq!(parse_bool(" Нет "), Some(false));
+        assert_eq!(parse_bool("yes"), Some(true));
+        assert_eq!(parse_bool("NO"), Some(false));
+        assert_eq!(parse_bool("maybe"), None);
+    }
+
+    #[test]
+    fn test_coercion_candidates() {
+        assert_eq!(coercion_candidates("1 000,00"), vec!("1000", "1000.00"));
+        assert_eq!(coercion_candidates("1"), vec!("true", "1"));
+        assert_eq!(coercion_candidates("так"), vec!("true"));
+        assert_eq!(coercion_candidates("1.000"), vec!("1.000"));
+        assert_eq!(coercion_candidates("1,000"), vec!("1.000"));
+        assert_eq!(coercion_candidates("1.000,00"), vec!("1000", "1000.00"));
+        assert!(coercion_candidates("abc").is_empty());
+    }
+}
@@ -1,39 +1,155 @@
 use byteorder::{LittleEndian, ReadBytesExt};
 
-use bytes::BytesMut;
-
 use clap::Clap;
 
 use flate2::bufread::GzDecoder;
 
 use indicatif::{ProgressBar, ProgressStyle};
 
-use prost::{EncodeError, Message};
+use prost::{DecodeError, Message};
 
-use snafu::{ResultExt, Snafu};
+use snafu::{OptionExt, ResultExt, Snafu};
 
 use std::io::{self, BufReader, BufWriter, Write, SeekFrom};
 use std::io::prelude::*;
-use std::ffi::OsStr;
-use std::fs::{self, create_dir_all, File, OpenOptions};
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
+mod csv_feed;
+mod diff;
+mod dump;
+mod filter;
+mod google_merchant;
+mod google_merchant_writer;
+mod html;
+mod incremental;
+mod lenient;
+mod manifest;
+mod output;
+mod parquet_writer;
 mod parser;
-use parser::{MarketXmlConfig, MarketXmlError, MarketXmlParser, ParsedItem};
+mod sqlite_writer;
+mod stats;
+mod validate;
+mod yml_writer;
+use csv_feed::{CsvFeedParser, CsvMapping};
+use diff::{write_report, OffersDiff};
+use dump::{
+    decode_records, for_each_selected, open_file, read_file, strip_compression, DelimitedReader, DumpError, DumpFileType,
+    Record,
+};
+use filter::OfferFilter;
+use google_merchant::GoogleMerchantParser;
+use google_merchant_writer::GoogleMerchantWriter;
+use incremental::{OfferChanges, OFFER_HASHES_FILE_NAME};
+use manifest::{timestamp_millis, Manifest};
+use output::{FileCompression, Output, OutputConfig, OutputError, OutputFormat, StagingDir};
+use parquet_writer::ParquetCompression;
+use parser::{
+    Dialect, FeedParser, MarketXmlConfig, MarketXmlError, MarketXmlParser, ParsedItem, WhitespacePolicy,
+};
+use stats::{write_stats_report, FeedStats};
+use validate::{write_html_report, write_text_report, ValidationReportFormat, Validator};
+use yml_writer::YmlWriter;
 
 pub(crate) mod market_xml {
     include!(concat!(env!("OUT_DIR"), "/market_xml.rs"));
+    include!(concat!(env!("OUT_DIR"), "/market_xml.serde.rs"));
 }
 
 #[global_allocator]
 static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
+// Subcommands of `Opts`, arguments without one of them are parsed as arguments of `parse`
+const COMMANDS: &[&str] = &[
+    "parse", "write-yml", "write-google-merchant", "diff", "dump", "validate", "stats", "help",
+];
+
 #[derive(Clap, Debug)]
 struct Opts {
-    #[clap(long = "offers-chunk", default_value = "50000")]
-    offers_chunk_size: u32,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+impl Opts {
+    /// Parses arguments inserting the `parse` command when there is no command,
+    /// so the invocation prior to subcommands keeps working
+    fn parse_args<I: IntoIterator<Item = OsString>>(args: I) -> Self {
+        let mut args = args.into_iter().collect::<Vec<_>>();
+        let has_command = args.get(1)
+            .and_then(|arg| arg.to_str())
+            .map_or(true, |arg| {
+                COMMANDS.contains(&arg) || ["-h", "--help", "-V", "--version"].contains(&arg)
+            });
+        if !has_command {
+            args.insert(1, OsString::from("parse"));
+        }
+        Opts::parse_from(args)
+    }
+}
+
+#[derive(Clap, Debug)]
+enum Command {
+    /// Parses a feed into protobuf files
+    Parse(ParseOpts),
+    /// Writes parsed protobuf files back into a yml feed
+    WriteYml(WriteYmlOpts),
+    /// Writes parsed protobuf files as a Google Merchant feed
+    WriteGoogleMerchant(WriteGoogleMerchantOpts),
+    /// Compares two feeds or two output directories of the parse command
+    Diff(DiffOpts),
+    /// Prints records of an output file of the parse command
+    Dump(DumpOpts),
+    /// Checks a feed and writes a report with its errors
+    Validate(ValidateOpts),
+    /// Prints aggregated metrics of a feed or an output directory of the parse command
+    Stats(StatsOpts),
+}
+
+#[derive(Clap, Debug)]
+struct ParseOpts {
+    /// Maximum number of offers in a chunk, 50000 when no chunk limit is set
+    #[clap(long = "offers-chunk")]
+    offers_chunk_size: Option<u32>,
+    /// Maximum size of a chunk in bytes before compression
+    #[clap(long = "offers-chunk-bytes")]
+    offers_chunk_bytes: Option<u64>,
+    /// Files are written into a temporary sibling directory that replaces the output directory on success
     #[clap(long = "output-dir", short = "o")]
     output_dir: PathBuf,
+    /// Replace a non-empty output directory
+    #[clap(long = "overwrite")]
+    overwrite: bool,
+    /// Output directory of a previous run, only new and changed offers are written
+    #[clap(long = "previous")]
+    previous: Option<PathBuf>,
+    /// Only offers matching the expression are written, for example `available && price.price > 100`
+    #[clap(long = "filter")]
+    filter: Option<OfferFilter>,
+    #[clap(long = "format", default_value = "protobuf", possible_values = &["protobuf", "jsonl", "parquet", "es-bulk", "sqlite"])]
+    format: OutputFormat,
+    /// Compression of output files except parquet offers that have their own compression
+    #[clap(long = "compress", default_value = "none", possible_values = &["none", "gzip", "zstd"])]
+    compress: FileCompression,
+    /// Level of the compression: 0-9 for gzip and 1-22 for zstd
+    #[clap(long = "compress-level")]
+    compress_level: Option<i32>,
+    #[clap(long = "parquet-compression", default_value = "snappy", possible_values = &["none", "snappy", "gzip", "zstd"])]
+    parquet_compression: ParquetCompression,
+    /// Maximum number of offers in a row group of a parquet file
+    #[clap(long = "parquet-row-group-size", default_value = "10000")]
+    parquet_row_group_size: usize,
+    /// Index name for the es-bulk format
+    #[clap(long = "es-index")]
+    es_index: Option<String>,
+    /// Write delete actions for unavailable offers in the es-bulk format
+    #[clap(long = "es-delete-unavailable")]
+    es_delete_unavailable: bool,
+    /// Maximum size of an es-bulk file in bytes
+    #[clap(long = "es-bulk-size", default_value = "10485760")]
+    es_bulk_size: u64,
     #[clap(long = "no-progress")]
     no_progress: bool,
     #[clap(long = "dry-run")]
@@ -42,9 +158,225 @@ struct Opts {
     verbose: bool,
     #[clap(long="if-modified-since")]
     if_modified_since: Option<String>,
+    #[clap(flatten)]
+    feed: FeedOpts,
     xml_file: String,
 }
 
+#[derive(Clap, Debug)]
+struct WriteYmlOpts {
+    /// Output file, stdout when missing
+    #[clap(long = "output", short = "o")]
+    output: Option<PathBuf>,
+    /// Directory with results of the parse command
+    input_dir: PathBuf,
+}
+
+#[derive(Clap, Debug)]
+struct WriteGoogleMerchantOpts {
+    /// Output file, stdout when missing
+    #[clap(long = "output", short = "o")]
+    output: Option<PathBuf>,
+    /// Country of delivery options like `RU`, shipping is not written without it
+    #[clap(long = "shipping-country")]
+    shipping_country: Option<String>,
+    /// Directory with results of the parse command
+    input_dir: PathBuf,
+}
+
+#[derive(Clap, Debug)]
+struct DiffOpts {
+    #[clap(long = "format", default_value = "text", possible_values = &["text", "json"])]
+    format: ReportFormat,
+    /// Output file, stdout when missing
+    #[clap(long = "output", short = "o")]
+    output: Option<PathBuf>,
+    #[clap(flatten)]
+    feed: FeedOpts,
+    /// Feed file or directory with results of the parse command
+    old: PathBuf,
+    /// Feed file or directory with results of the parse command
+    new: PathBuf,
+}
+
+#[derive(Clap, Debug)]
+struct DumpOpts {
+    /// Json prints a record per line
+    #[clap(long = "format", default_value = "json", possible_values = &["text", "json"])]
+    format: ReportFormat,
+    /// Output file, stdout when missing
+    #[clap(long = "output", short = "o")]
+    output: Option<PathBuf>,
+    /// Print only records of offers with the id
+    #[clap(long = "id")]
+    ids: Vec<String>,
+    /// Print only first records
+    #[clap(long = "head")]
+    head: Option<usize>,
+    /// Print only last records
+    #[clap(long = "tail")]
+    tail: Option<usize>,
+    /// Print a number of the selected records instead of them
+    #[clap(long = "count")]
+    count: bool,
+    /// Output file of the parse command, or its directory to dump all the offers
+    input: PathBuf,
+}
+
+#[derive(Clap, Debug)]
+struct ValidateOpts {
+    #[clap(long = "format", default_value = "text", possible_values = &["text", "json", "html"])]
+    format: ValidationReportFormat,
+    /// Output file, stdout when missing
+    #[clap(long = "output", short = "o")]
+    output: Option<PathBuf>,
+    /// Maximum number of sample offers for every kind of errors
+    #[clap(long = "samples", default_value = "3")]
+    samples: usize,
+    #[clap(flatten)]
+    feed: FeedOpts,
+    xml_file: PathBuf,
+}
+
+#[derive(Clap, Debug)]
+struct StatsOpts {
+    #[clap(long = "format", default_value = "text", possible_values = &["text", "json"])]
+    format: ReportFormat,
+    /// Output file, stdout when missing
+    #[clap(long = "output", short = "o")]
+    output: Option<PathBuf>,
+    /// Number of the most frequent params, vendors and categories in the report
+    #[clap(long = "top", default_value = "20")]
+    top: usize,
+    #[clap(flatten)]
+    feed: FeedOpts,
+    /// Feed file or directory with results of the parse command
+    input: PathBuf,
+}
+
+/// How to parse feed files, shared by all the commands that read feeds
+#[derive(Clap, Debug)]
+struct FeedOpts {
+    #[clap(long = "input-format", default_value = "yml", possible_values = &["yml", "google-merchant", "csv", "tsv"])]
+    input_format: InputFormat,
+    /// Json file that maps columns of a csv file onto offer fields
+    #[clap(long = "csv-mapping")]
+    csv_mapping: Option<PathBuf>,
+    #[clap(long = "dialect", default_value = "yml", possible_values = &["yml", "prom", "rozetka", "hotline"])]
+    dialect: Dialect,
+    #[clap(long = "whitespace", default_value = "trim", possible_values = &["preserve", "trim", "collapse"])]
+    whitespace: WhitespacePolicy,
+    #[clap(long = "sanitize-description")]
+    sanitize_description: bool,
+    #[clap(long = "description-max-length")]
+    description_max_length: Option<usize>,
+    #[clap(long = "inner-xml-field")]
+    inner_xml_fields: Vec<String>,
+    #[clap(long = "lenient")]
+    lenient: bool,
+    /// Suffix of localized fields with an optional language: `ua` or `ua=uk`
+    #[clap(long = "localized-suffix")]
+    localized_suffixes: Vec<String>,
+    #[clap(long = "lang-attr")]
+    lang_attr: Option<String>,
+    #[clap(long = "default-lang")]
+    default_lang: Option<String>,
+    /// Namespace of yml elements in addition to the namespace of the catalog element
+    #[clap(long = "yml-namespace")]
+    yml_namespaces: Vec<String>,
+}
+
+impl FeedOpts {
+    fn to_parser_config(&self) -> MarketXmlConfig {
+        let mut parser_config = MarketXmlConfig::for_dialect(self.dialect);
+        parser_config.whitespace = self.whitespace;
+        parser_config.lenient = self.lenient;
+        for localized_suffix in &self.localized_suffixes {
+            let (suffix, lang) = match localized_suffix.find('=') {
+                Some(ix) => (&localized_suffix[..ix], &localized_suffix[ix + 1..]),
+                None => (localized_suffix.as_str(), localized_suffix.as_str()),
+            };
+            parser_config.localized_suffixes.insert(suffix.as_bytes().to_vec(), lang.to_string());
+        }
+        parser_config.lang_attr = self.lang_attr.as_ref().map(|attr| attr.as_bytes().to_vec());
+        parser_config.default_lang = self.default_lang.clone();
+        parser_config.yml_namespaces.extend(
+            self.yml_namespaces.iter().map(|ns| ns.as_bytes().to_vec())
+        );
+        // limiting a length makes sense only for a sanitized description
+        parser_config.sanitize_description = self.sanitize_description ||
+            self.description_max_length.is_some();
+        parser_config.description_max_length = self.description_max_length;
+        parser_config.inner_xml_fields.extend(
+            self.inner_xml_fields.iter().map(|f| f.as_bytes().to_vec())
+        );
+        parser_config
+    }
+
+    fn create_parser(&self, file_reader: Box<dyn BufRead>) -> Result<Box<dyn FeedParser>, CliError> {
+        let parser_config = self.to_parser_config();
+        Ok(match self.input_format {
+            InputFormat::Yml => Box::new(MarketXmlParser::new(parser_config, file_reader)),
+            InputFormat::GoogleMerchant => Box::new(GoogleMerchantParser::new(parser_config, file_reader)),
+            InputFormat::Csv | InputFormat::Tsv => {
+                let mapping_path = self.csv_mapping.as_deref().ok_or_else(|| CliError::InvalidOpt {
+                    msg: "csv-mapping is required for csv input".to_string()
+                })?;
+                let mapping_file = File::open(mapping_path)
+                    .context(OpenInputFileSnafu { path: mapping_path })?;
+                let mapping = CsvMapping::from_reader(BufReader::new(mapping_file))
+                    .context(ReadCsvMappingSnafu { path: mapping_path })?;
+                let delimiter = if self.input_format == InputFormat::Tsv { b'\t' } else { b',' };
+                Box::new(
+                    CsvFeedParser::new(parser_config, mapping, delimiter, file_reader)
+                        .context(InvalidCsvMappingSnafu)?
+                )
+            }
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum ReportFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(ReportFormat::Text),
+            "json" => Ok(ReportFormat::Json),
+            _ => Err(format!("unknown report format: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum InputFormat {
+    Yml,
+    /// Google Merchant Center RSS 2.0 or Atom 1.0 feed
+    GoogleMerchant,
+    Csv,
+    Tsv,
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "yml" => Ok(InputFormat::Yml),
+            "google-merchant" => Ok(InputFormat::GoogleMerchant),
+            "csv" => Ok(InputFormat::Csv),
+            "tsv" => Ok(InputFormat::Tsv),
+            _ => Err(format!("unknown input format: {}", s)),
+        }
+    }
+}
+
 #[derive(Debug, Snafu)]
 enum CliError {
     #[snafu(display("Invalid option: {}", msg))]
@@ -53,25 +385,105 @@ enum CliError {
     ParseXml { msg: String },
     #[snafu(display("Cannot open an input file {:?}: {}", path, source))]
     OpenInputFile { source: io::Error, path: PathBuf },
-    #[snafu(display("Cannot create an output directory {:?}: {}", path, source))]
-    CreateOutputDir { source: io::Error, path: PathBuf },
+    #[snafu(display("Cannot read a csv mapping file {:?}: {}", path, source))]
+    ReadCsvMapping { source: serde_json::Error, path: PathBuf },
+    #[snafu(display("Invalid csv mapping: {}", source))]
+    InvalidCsvMapping { source: MarketXmlError },
     #[snafu(display("Cannot open an output file {:?}: {}", path, source))]
     OpenOutputFile { source: io::Error, path: PathBuf },
     #[snafu(display("Cannot write an output file {:?}: {}", path, source))]
     WriteOutputFile { source: io::Error, path: PathBuf },
-    #[snafu(display("Error when encoding to protobuf: {}", source))]
-    ProtobufEncode { source: EncodeError },
+    #[snafu(display("Cannot read an input file {:?}: {}", path, source))]
+    ReadInputFile { source: io::Error, path: PathBuf },
+    #[snafu(context(false), display("{}", source))]
+    Output { source: OutputError },
+    #[snafu(display("Error when decoding protobuf file {:?}: {}", path, source))]
+    ProtobufDecode { source: DecodeError, path: PathBuf },
+    #[snafu(display("Cannot dump a file {:?}: {}", path, source))]
+    DumpFile { source: DumpError, path: PathBuf },
+    #[snafu(display("Error when decoding json file {:?}: {}", path, source))]
+    JsonDecode { source: serde_json::Error, path: PathBuf },
+    #[snafu(display("Cannot read a manifest of {:?}, it is missing until the parse command succeeds: {}", path, source))]
+    ReadManifest { source: io::Error, path: PathBuf },
+    #[snafu(display("Cannot read an output directory {:?}: {}", path, msg))]
+    UnsupportedInputDir { msg: String, path: PathBuf },
     #[snafu(display("Error when downloading an xml file: {}", source))]
     Reqwest { source: reqwest::Error },
+    #[snafu(display("Feed validation failed"))]
+    ValidationFailed,
 }
 
 fn main() -> Result<(), CliError> {
     env_logger::init();
 
-    let opts = Opts::parse();
-    if opts.offers_chunk_size == 0 {
+    match Opts::parse_args(std::env::args_os()).command {
+        Command::Parse(opts) => parse(opts),
+        Command::WriteYml(opts) => write_yml(opts),
+        Command::WriteGoogleMerchant(opts) => write_google_merchant(opts),
+        Command::Diff(opts) => diff(opts),
+        Command::Dump(opts) => dump(opts),
+        Command::Validate(opts) => validate(opts),
+        Command::Stats(opts) => stats(opts),
+    }
+}
+
+fn parse(opts: ParseOpts) -> Result<(), CliError> {
+    if opts.offers_chunk_size == Some(0) {
         return Err(CliError::InvalidOpt { msg: "offers-chunk must be greater than 0".to_string() });
     }
+    if opts.offers_chunk_bytes == Some(0) {
+        return Err(CliError::InvalidOpt { msg: "offers-chunk-bytes must be greater than 0".to_string() });
+    }
+    if opts.parquet_row_group_size == 0 {
+        return Err(CliError::InvalidOpt { msg: "parquet-row-group-size must be greater than 0".to_string() });
+    }
+    if opts.format == OutputFormat::EsBulk && opts.es_index.is_none() {
+        return Err(CliError::InvalidOpt { msg: "es-index is required for es-bulk format".to_string() });
+    }
+    match (opts.compress, opts.compress_level) {
+        (FileCompression::Gzip, Some(level)) if !(0..=9).contains(&level) => {
+            return Err(CliError::InvalidOpt { msg: "gzip compression level must be in 0-9".to_string() });
+        }
+        (FileCompression::Zstd, Some(level)) if !(1..=22).contains(&level) => {
+            return Err(CliError::InvalidOpt { msg: "zstd compression level must be in 1-22".to_string() });
+        }
+        (FileCompression::Gzip | FileCompression::Zstd, _) if opts.format == OutputFormat::Sqlite => {
+            return Err(CliError::InvalidOpt { msg: "sqlite format cannot be compressed".to_string() });
+        }
+        _ => {}
+    }
+    let output_config = OutputConfig {
+        format: opts.format,
+        offers_chunk_size: match (opts.offers_chunk_size, opts.offers_chunk_bytes) {
+            (None, None) => Some(50000),
+            (offers_chunk_size, _) => offers_chunk_size,
+        },
+        offers_chunk_bytes: match opts.format {
+            OutputFormat::EsBulk => Some(
+                opts.offers_chunk_bytes.map_or(opts.es_bulk_size, |bytes| bytes.min(opts.es_bulk_size))
+            ),
+            _ => opts.offers_chunk_bytes,
+        },
+        compression: opts.compress,
+        compression_level: opts.compress_level,
+        parquet_compression: opts.parquet_compression,
+        parquet_row_group_size: opts.parquet_row_group_size,
+        es_index: opts.es_index.clone().unwrap_or_default(),
+        es_delete_unavailable: opts.es_delete_unavailable,
+    };
+
+    let mut manifest = Manifest {
+        tool_version: env!("CARGO_PKG_VERSION").to_string(),
+        source: opts.xml_file.clone(),
+        started_at: timestamp_millis(),
+        ..Default::default()
+    };
+
+    let previous_hashes = match opts.previous {
+        Some(ref previous_dir) => Some(read_message(previous_dir, OFFER_HASHES_FILE_NAME)?),
+        None => None,
+    };
+    let mut offer_changes = OfferChanges::new(previous_hashes);
 
     let (file_reader, file_size) = if opts.xml_file.starts_with("http://") || opts.xml_file.starts_with("https://") {
         let client = reqwest::blocking::ClientBuilder::new()
@@ -97,25 +509,29 @@ fn main() -> Result<(), CliError> {
             log::info!("{not_modified_msg}");
             return Ok(());
         }
-        if let Some(Ok(last_modified)) = response.headers().get(reqwest::header::LAST_MODIFIED).map(|v| v.to_str()) {
-            if !opts.dry_run {
-                ensure_output_dir(&opts.output_dir)?;
-            }
-            let last_modified_path = opts.output_dir.join("last-modified.txt");
-            std::fs::write(&last_modified_path, last_modified)
-                .context(WriteOutputFileSnafu { path: last_modified_path })?;
-        }
+        let header = |name| {
+            response.headers().get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string())
+        };
+        manifest.last_modified = header(reqwest::header::LAST_MODIFIED);
+        manifest.etag = header(reqwest::header::ETAG);
         let content_length = response.content_length();
         (Box::new(BufReader::new(response)) as Box<dyn BufRead>, content_length)
     } else {
         open_market_xml_file(PathBuf::from(&opts.xml_file).as_path())
             .context(OpenInputFileSnafu { path: opts.xml_file })?
     };
-    let mut parser = MarketXmlParser::new(MarketXmlConfig::default(), file_reader);
 
-    if !opts.dry_run {
-        ensure_output_dir(&opts.output_dir)?;
-    }
+    // previous results stay intact until the new ones are complete,
+    // an unmodified feed leaves the output directory untouched
+    let staging_dir = if !opts.dry_run {
+        Some(StagingDir::create(&opts.output_dir, opts.overwrite)?)
+    } else {
+        None
+    };
+
+    let mut parser = opts.feed.create_parser(file_reader)?;
 
     let progressbar = match (opts.no_progress, file_size) {
         (false, Some(file_size)) => {
@@ -130,28 +546,29 @@ fn main() -> Result<(), CliError> {
         _ => None
     };
 
-    let mut buf = BytesMut::new();
     let mut errors = market_xml::Errors::default();
+    let mut warnings = market_xml::Warnings::default();
     let mut available_offer_ids = market_xml::OfferIds::default();
     let mut unavailable_offer_ids = market_xml::OfferIds::default();
     let mut availability_missing_offer_ids = market_xml::OfferIds::default();
-    let mut chunk_ix = 0;
-    let mut chunk_offers = 0;
-    let mut offers_filename = format!("offers-{}.protobuf-delimited", chunk_ix);
-    let mut offers_writer = if !opts.dry_run {
-        Some(
-            DelimitedMessageWriter::open(
-                &opts.output_dir, &offers_filename
-            )?
-        )
-    } else {
-        None
+    let mut output = match staging_dir {
+        Some(ref staging_dir) => Some(Output::create(output_config, staging_dir.path())?),
+        None => None,
     };
+    if let (Some(ref mut output), Some(ref last_modified)) = (&mut output, &manifest.last_modified) {
+        output.write_text("last-modified.txt", "last-modified", last_modified)?;
+    }
     let mut total_offers = 0;
     let mut offers_with_errors = 0;
+    let mut unchanged_offers = 0;
+    let mut filtered_offers = 0;
     loop {
         match parser.next_item() {
-            Ok(ParsedItem::Offer(offer)) => {
+            Ok(ParsedItem::Offer(ref offer)) if opts.filter.as_ref().is_some_and(|filter| !filter.matches(offer)) => {
+                total_offers += 1;
+                filtered_offers += 1;
+            }
+            Ok(ParsedItem::Offer(mut offer)) => {
                 match offer.available {
                     Some(true) => {
                         available_offer_ids.ids.push(offer.id.clone());
@@ -166,39 +583,27 @@ fn main() -> Result<(), CliError> {
                 if offer.available.unwrap_or(false) {
 
                 }
-                if let Some(ref mut offers_writer) = offers_writer {
-                    offers_writer.write(&offer, &mut buf)?;
-                    chunk_offers += 1;
-                }
-                if chunk_offers == opts.offers_chunk_size {
-                    chunk_ix += 1;
-                    chunk_offers = 0;
-                    println!("{offers_filename}");
-                    offers_filename = format!("offers-{}.protobuf-delimited", chunk_ix);
-                    offers_writer = if !opts.dry_run {
-                        Some(
-                            DelimitedMessageWriter::open(
-                                &opts.output_dir, &offers_filename
-                            )?
-                        )
-                    } else {
-                        None
-                    };
+                if offer_changes.update(&mut offer) {
+                    if let Some(ref mut output) = output {
+                        output.write_offer(&offer)?;
+                    }
+                } else {
+                    unchanged_offers += 1;
                 }
                 total_offers += 1;
             }
             Ok(ParsedItem::YmlCatalog(yml_catalog)) => {
-                let catalog_filename = "yml_catalog.protobuf";
-                if !opts.dry_run {
-                    write_message(&opts.output_dir, catalog_filename, &yml_catalog, &mut buf)?;
+                manifest.feed_date = yml_catalog.date.clone();
+                manifest.shop_name = yml_catalog.shop.as_ref().map(|shop| shop.name.clone()).unwrap_or_default();
+                if let Some(ref mut output) = output {
+                    output.write_message("yml_catalog", &yml_catalog)?;
                 }
-                println!("{catalog_filename}");
             }
             Ok(ParsedItem::Eof) => {
                 break;
             }
             Err(e) => {
-                if let MarketXmlError::Xml {..} = e {
+                if let MarketXmlError::Xml {..} | MarketXmlError::Csv {..} = e {
                     return Err(CliError::ParseXml { msg: format!("{}", e) });
                 }
                 if opts.verbose {
@@ -214,11 +619,25 @@ fn main() -> Result<(), CliError> {
                     message: format!("{}", e),
                     value: e.value().map(|v| v.to_string()).unwrap_or("".to_string()),
                 });
-                total_offers += 1;
-                offers_with_errors += 1;
+                if parser.in_offer() {
+                    total_offers += 1;
+                    offers_with_errors += 1;
+                }
             },
         }
 
+        for warning in parser.take_warnings() {
+            if opts.verbose {
+                log::warn!("Line {}: {}: {}", warning.line, warning.msg, warning.value);
+            }
+            warnings.warnings.push(market_xml::Error {
+                line: warning.line as u64,
+                column: warning.column as u64,
+                message: warning.msg,
+                value: warning.value,
+            });
+        }
+
         progressbar.as_ref().map(|pb| {
             let cur_pos = parser.buffer_position() as u64;
             if cur_pos - pb.position() > file_size.unwrap() / 100 {
@@ -227,63 +646,306 @@ fn main() -> Result<(), CliError> {
         });
     }
 
-    if !opts.dry_run {
+    if let Some(mut output) = output {
         available_offer_ids.ids.sort_unstable();
-
-        let offer_ids_available_filename = "offer-ids-available.protobuf";
-        write_message(
-            &opts.output_dir,
-            offer_ids_available_filename,
-            &available_offer_ids,
-            &mut buf
-        )?;
-        println!("{offer_ids_available_filename}");
-
-        let offer_ids_unavailable_filename = "offer-ids-unavailable.protobuf";
+        output.write_message("offer-ids-available", &available_offer_ids)?;
         unavailable_offer_ids.ids.sort_unstable();
-        write_message(
-            &opts.output_dir,
-            &format!("offer-ids-unavailable.protobuf"),
-            &unavailable_offer_ids,
-            &mut buf
-        )?;
-        println!("{offer_ids_unavailable_filename}");
-
-        let offer_ids_missing_filename = "offer-ids-availability-missing.protobuf";
+        output.write_message("offer-ids-unavailable", &unavailable_offer_ids)?;
         availability_missing_offer_ids.ids.sort_unstable();
-        write_message(
-            &opts.output_dir,
-            &format!("offer-ids-availability-missing.protobuf"),
-            &availability_missing_offer_ids,
-            &mut buf
-        )?;
-        println!("{offer_ids_missing_filename}");
-    }
+        output.write_message("offer-ids-availability-missing", &availability_missing_offer_ids)?;
+        let (offer_hashes, deleted_offer_ids) = offer_changes.finish();
+        if let Some(deleted_offer_ids) = deleted_offer_ids {
+            log::info!("Deleted offers: {}", deleted_offer_ids.ids.len());
+            output.write_message("offer-ids-deleted", &deleted_offer_ids)?;
+        }
+        output.write_offer_hashes(&offer_hashes)?;
 
-    if !errors.errors.is_empty() && !opts.dry_run {
-        let errors_filename = "errors.protobuf";
-        write_message(
-            &opts.output_dir, errors_filename, &errors, &mut buf
-        )?;
-        println!("{errors_filename}");
+        if !errors.errors.is_empty() {
+            output.write_message("errors", &errors)?;
+        }
+        if !warnings.warnings.is_empty() {
+            output.write_message("warnings", &warnings)?;
+        }
+
+        manifest.total_offers = total_offers;
+        manifest.offers_with_errors = offers_with_errors;
+        manifest.unchanged_offers = unchanged_offers;
+        manifest.filtered_offers = filtered_offers;
+        manifest.warnings = warnings.warnings.len() as u64;
+        manifest.finished_at = timestamp_millis();
+        output.finish(manifest)?;
+    }
+    if let Some(staging_dir) = staging_dir {
+        staging_dir.commit()?;
     }
 
     progressbar.map(|pb| pb.finish());
 
     log::info!("Total offers: {total_offers}");
     log::info!("Offers with errors: {offers_with_errors}");
+    if opts.previous.is_some() {
+        log::info!("Unchanged offers: {unchanged_offers}");
+    }
+    log::info!("Warnings: {}", warnings.warnings.len());
 
     Ok(())
 }
 
-fn ensure_output_dir(output_dir: &Path) -> Result<(), CliError> {
-    if !output_dir.exists() {
-        create_dir_all(&output_dir)
-            .context(CreateOutputDirSnafu { path: output_dir })?;
+fn write_yml(opts: WriteYmlOpts) -> Result<(), CliError> {
+    let input_dir = InputDir::open(&opts.input_dir)?;
+    let yml_catalog = input_dir.read_catalog()?;
+    let (output, output_path) = create_output(opts.output)?;
+
+    let mut writer = YmlWriter::new(output);
+    writer.write_start(&yml_catalog)
+        .context(WriteOutputFileSnafu { path: &output_path })?;
+    for offer in input_dir.offers()? {
+        writer.write_offer(&offer?)
+            .context(WriteOutputFileSnafu { path: &output_path })?;
     }
+    writer.write_end()
+        .context(WriteOutputFileSnafu { path: &output_path })?;
+
     Ok(())
 }
 
+fn write_google_merchant(opts: WriteGoogleMerchantOpts) -> Result<(), CliError> {
+    let input_dir = InputDir::open(&opts.input_dir)?;
+    let yml_catalog = input_dir.read_catalog()?;
+    let shop = yml_catalog.shop.unwrap_or_default();
+    let (output, output_path) = create_output(opts.output)?;
+
+    let mut writer = GoogleMerchantWriter::new(output, &shop, opts.shipping_country);
+    writer.write_start(&shop)
+        .context(WriteOutputFileSnafu { path: &output_path })?;
+    let mut skipped_offers = 0;
+    for offer in input_dir.offers()? {
+        let offer = offer?;
+        let missing_fields = writer.missing_fields(&offer);
+        // skipped offers are always reported unlike log messages
+        if !missing_fields.is_empty() {
+            eprintln!("Offer {}: missing required fields: {}", offer.id, missing_fields.join(", "));
+            skipped_offers += 1;
+            continue;
+        }
+        writer.write_offer(&offer)
+            .context(WriteOutputFileSnafu { path: &output_path })?;
+    }
+    writer.write_end()
+        .context(WriteOutputFileSnafu { path: &output_path })?;
+
+    eprintln!("Skipped offers: {skipped_offers}");
+
+    Ok(())
+}
+
+fn diff(opts: DiffOpts) -> Result<(), CliError> {
+    let mut offers_diff = OffersDiff::new();
+    let mut old_catalog = market_xml::YmlCatalog::default();
+    let mut new_catalog = market_xml::YmlCatalog::default();
+    let old_errors = scan_feed(&opts.old, &opts.feed, |item| match item {
+        ParsedItem::Offer(mut offer) => offers_diff.add_old_hash(&mut offer),
+        ParsedItem::YmlCatalog(catalog) => old_catalog = catalog,
+        ParsedItem::Eof => {}
+    })?;
+    let new_errors = scan_feed(&opts.new, &opts.feed, |item| match item {
+        ParsedItem::Offer(mut offer) => offers_diff.add_new_hash(&mut offer),
+        ParsedItem::YmlCatalog(catalog) => new_catalog = catalog,
+        ParsedItem::Eof => {}
+    })?;
+    // the feeds are read again to compare fields of the changed offers
+    if offers_diff.has_changed_offers() {
+        scan_feed(&opts.old, &opts.feed, |item| if let ParsedItem::Offer(offer) = item {
+            offers_diff.add_old_offer(offer);
+        })?;
+        scan_feed(&opts.new, &opts.feed, |item| if let ParsedItem::Offer(offer) = item {
+            offers_diff.add_new_offer(&offer);
+        })?;
+    }
+    let diff = offers_diff.finish(&old_catalog, &old_errors, &new_catalog, &new_errors);
+
+    let (output, output_path) = create_output(opts.output)?;
+    match opts.format {
+        ReportFormat::Text => write_report(&diff, output)
+            .context(WriteOutputFileSnafu { path: &output_path })?,
+        ReportFormat::Json => {
+            let mut output = output;
+            serde_json::to_writer_pretty(&mut output, &diff)
+                .map_err(io::Error::from)
+                .and_then(|_| writeln!(output))
+                .context(WriteOutputFileSnafu { path: &output_path })?;
+        }
+    }
+    Ok(())
+}
+
+fn dump(opts: DumpOpts) -> Result<(), CliError> {
+    let (mut output, output_path) = create_output(opts.output)?;
+    let ids = opts.ids;
+    let format = opts.format;
+    let count_only = opts.count;
+    let input = opts.input;
+
+    let records: Box<dyn Iterator<Item = Result<Record, CliError>>> = if input.is_dir() {
+        Box::new(
+            InputDir::open(&input)?.offers()?
+                .map(|offer| offer.map(|offer| Record::Offer(Box::new(offer))))
+        )
+    } else {
+        let file_type = DumpFileType::detect(&input).ok_or_else(|| CliError::InvalidOpt {
+            msg: format!("unknown type of a file: {:?}", input)
+        })?;
+        let reader = open_file(&input)
+            .context(ReadInputFileSnafu { path: &input })?;
+        let input_path = input.clone();
+        Box::new(
+            decode_records(file_type, reader)
+                .context(DumpFileSnafu { path: &input })?
+                .map(move |record| record.context(DumpFileSnafu { path: &input_path }))
+        )
+    };
+    let records = records.filter(|record| match record {
+        Ok(record) if !ids.is_empty() => {
+            record.offer_id().is_some_and(|id| ids.iter().any(|filter_id| filter_id == id))
+        }
+        _ => true,
+    });
+
+    let mut count = 0;
+    for_each_selected(records, opts.head, opts.tail, |record| {
+        count += 1;
+        let res = match format {
+            _ if count_only => Ok(()),
+            ReportFormat::Text => record.write_text(&mut output),
+            ReportFormat::Json => record.write_json(&mut output),
+        };
+        res.context(WriteOutputFileSnafu { path: &output_path })
+    })?;
+    if count_only {
+        writeln!(output, "{}", count)
+            .context(WriteOutputFileSnafu { path: &output_path })?;
+    }
+    output.flush()
+        .context(WriteOutputFileSnafu { path: &output_path })
+}
+
+fn validate(opts: ValidateOpts) -> Result<(), CliError> {
+    let (file_reader, _) = open_market_xml_file(&opts.xml_file)
+        .context(OpenInputFileSnafu { path: &opts.xml_file })?;
+    let mut parser = opts.feed.create_parser(file_reader)?;
+
+    let mut validator = Validator::new(opts.samples);
+    loop {
+        match parser.next_item() {
+            Ok(ParsedItem::Offer(offer)) => validator.add_offer(&offer),
+            Ok(ParsedItem::YmlCatalog(yml_catalog)) => validator.add_catalog(&yml_catalog),
+            Ok(ParsedItem::Eof) => break,
+            Err(e) => {
+                let is_fatal = matches!(e, MarketXmlError::Xml {..} | MarketXmlError::Csv {..});
+                let value = e.value().unwrap_or_default().to_string();
+                // a fatal error stops parsing so it belongs to the whole feed
+                if parser.in_offer() && !is_fatal {
+                    validator.add_error(e.line(), e.column(), format!("{}", e), value);
+                } else {
+                    validator.add_catalog_error(e.line(), e.column(), format!("{}", e), value);
+                }
+                if is_fatal {
+                    break;
+                }
+            }
+        }
+        for warning in parser.take_warnings() {
+            validator.add_warning(warning.line, warning.column, warning.msg, warning.value);
+        }
+    }
+
+    // the feed is read again to show sources of errors
+    let (source_reader, _) = open_market_xml_file(&opts.xml_file)
+        .context(OpenInputFileSnafu { path: &opts.xml_file })?;
+    let report = validator.finish(&opts.xml_file.to_string_lossy(), Some(source_reader))
+        .context(ReadInputFileSnafu { path: &opts.xml_file })?;
+
+    let (mut output, output_path) = create_output(opts.output)?;
+    match opts.format {
+        ValidationReportFormat::Text => write_text_report(&report, output),
+        ValidationReportFormat::Html => write_html_report(&report, output),
+        ValidationReportFormat::Json => serde_json::to_writer_pretty(&mut output, &report)
+            .map_err(io::Error::from)
+            .and_then(|_| writeln!(output)),
+    }
+        .context(WriteOutputFileSnafu { path: &output_path })?;
+
+    if !report.passed {
+        return Err(CliError::ValidationFailed);
+    }
+    Ok(())
+}
+
+fn stats(opts: StatsOpts) -> Result<(), CliError> {
+    let mut stats = FeedStats::new();
+    scan_feed(&opts.input, &opts.feed, |item| match item {
+        ParsedItem::Offer(offer) => stats.add_offer(&offer),
+        ParsedItem::YmlCatalog(yml_catalog) => stats.add_catalog(&yml_catalog),
+        ParsedItem::Eof => {}
+    })?;
+    let report = stats.finish(opts.top);
+
+    let (mut output, output_path) = create_output(opts.output)?;
+    match opts.format {
+        ReportFormat::Text => write_stats_report(&report, opts.top, output),
+        ReportFormat::Json => serde_json::to_writer_pretty(&mut output, &report)
+            .map_err(io::Error::from)
+            .and_then(|_| writeln!(output)),
+    }
+        .context(WriteOutputFileSnafu { path: &output_path })
+}
+
+/// Streams items of a feed file or of an output directory of the parse command,
+/// offers with errors are skipped and the errors are returned
+fn scan_feed(
+    path: &Path, opts: &FeedOpts, mut f: impl FnMut(ParsedItem)
+) -> Result<Vec<MarketXmlError>, CliError> {
+    let mut errors = vec!();
+    if path.is_dir() {
+        let input_dir = InputDir::open(path)?;
+        f(ParsedItem::YmlCatalog(input_dir.read_catalog()?));
+        for offer in input_dir.offers()? {
+            f(ParsedItem::Offer(offer?));
+        }
+        return Ok(errors);
+    }
+
+    let (file_reader, _) = open_market_xml_file(path)
+        .context(OpenInputFileSnafu { path })?;
+    let mut parser = opts.create_parser(file_reader)?;
+    loop {
+        match parser.next_item() {
+            Ok(ParsedItem::Eof) => break,
+            Ok(item) => f(item),
+            Err(e @ (MarketXmlError::Xml {..} | MarketXmlError::Csv {..})) => {
+                return Err(CliError::ParseXml { msg: format!("{}", e) });
+            }
+            Err(e) => {
+                log::warn!("{:?} line {}: {}", path, e.line(), e);
+                errors.push(e);
+            }
+        }
+    }
+    Ok(errors)
+}
+
+/// Creates an output file or uses stdout when a path is missing
+fn create_output(output: Option<PathBuf>) -> Result<(Box<dyn Write>, PathBuf), CliError> {
+    match output {
+        Some(output) => {
+            let file = File::create(&output)
+                .context(OpenOutputFileSnafu { path: &output })?;
+            Ok((Box::new(BufWriter::new(file)), output))
+        }
+        None => Ok((Box::new(BufWriter::new(io::stdout())), PathBuf::from("-"))),
+    }
+}
+
 fn open_market_xml_file(file_path: &Path) -> Result<(Box<dyn BufRead>, Option<u64>), io::Error> {
     let mut file = File::open(file_path)?;
     match file_path.extension() {
@@ -308,46 +970,217 @@ fn get_gzip_file_uncompressed_size(file: &mut File) -> Result<u32, io::Error> {
     return Ok(size);
 }
 
-fn write_message<M: Message>(
-    out_dir: &Path, file_name: &str, msg: &M, buf: &mut BytesMut
-) -> Result<PathBuf, CliError> {
-    let mut file_path = out_dir.to_path_buf();
-    file_path.push(file_name);
-    let mut file = OpenOptions::new().create_new(true).write(true)
-        .open(&file_path)
-        .context(OpenOutputFileSnafu { path: file_path.clone() })?;
-    msg.encode(buf).context(ProtobufEncodeSnafu)?;
-    file.write_all(buf)
-        .context(WriteOutputFileSnafu { path: file_path.clone() })?;
-    buf.clear();
-
-    Ok(file_path)
+fn read_message<M: Message + Default>(in_dir: &Path, file_name: &str) -> Result<M, CliError> {
+    let file_path = in_dir.join(file_name);
+    let data = fs::read(&file_path)
+        .context(ReadInputFileSnafu { path: file_path.clone() })?;
+    M::decode(data.as_slice())
+        .context(ProtobufDecodeSnafu { path: file_path })
 }
 
-struct DelimitedMessageWriter {
-    file_path: PathBuf,
-    writer: BufWriter<File>,
+/// Output directory of the parse command, files are found by its manifest
+/// so incomplete outputs are never read
+struct InputDir {
+    path: PathBuf,
+    manifest: Manifest,
 }
 
-impl DelimitedMessageWriter {
-    fn open(out_dir: &Path, file_name: &str) -> Result<Self, CliError> {
-        let mut file_path = out_dir.to_path_buf();
-        file_path.push(file_name);
-        let file = OpenOptions::new().create_new(true).write(true)
-            .open(&file_path)
-            .context(OpenOutputFileSnafu { path: file_path.clone() })?;
+impl InputDir {
+    fn open(path: &Path) -> Result<Self, CliError> {
+        let manifest = Manifest::read(path)
+            .context(ReadManifestSnafu { path })?;
         Ok(Self {
-            file_path,
-            writer: BufWriter::new(file),
+            path: path.to_path_buf(),
+            manifest,
+        })
+    }
+
+    fn read_catalog(&self) -> Result<market_xml::YmlCatalog, CliError> {
+        let file = self.manifest.files.iter()
+            .find(|file| file.file_type == "yml_catalog")
+            .context(UnsupportedInputDirSnafu {
+                msg: "there is no catalog, only protobuf and jsonl outputs can be read",
+                path: &self.path,
+            })?;
+        let file_path = self.path.join(&file.name);
+        let data = read_file(&file_path)
+            .context(ReadInputFileSnafu { path: &file_path })?;
+        let file_name = strip_compression(&file.name);
+        if file_name.ends_with(".protobuf") {
+            market_xml::YmlCatalog::decode(data.as_slice())
+                .context(ProtobufDecodeSnafu { path: file_path })
+        } else if file_name.ends_with(".json") {
+            serde_json::from_slice(&data)
+                .context(JsonDecodeSnafu { path: file_path })
+        } else {
+            UnsupportedInputDirSnafu { msg: format!("unknown format of a catalog {}", file.name), path: &self.path }
+                .fail()
+        }
+    }
+
+    /// Reads offers from all the chunks in order
+    fn offers(&self) -> Result<OffersReader, CliError> {
+        let mut chunks = vec!();
+        for file in self.manifest.files.iter().filter(|file| file.file_type == "offers") {
+            let format = ChunkFormat::detect(&file.name).with_context(|| UnsupportedInputDirSnafu {
+                msg: format!("offers cannot be read from {}, only protobuf and jsonl outputs can be read", file.name),
+                path: &self.path,
+            })?;
+            chunks.push((self.path.join(&file.name), format));
+        }
+        if chunks.is_empty() {
+            return UnsupportedInputDirSnafu { msg: "there are no chunks of offers", path: &self.path }.fail();
+        }
+        Ok(OffersReader {
+            chunks: chunks.into_iter(),
+            chunk_path: PathBuf::new(),
+            chunk: None,
         })
     }
+}
+
+#[derive(Clone, Copy)]
+enum ChunkFormat {
+    Protobuf,
+    Jsonl,
+}
+
+impl ChunkFormat {
+    fn detect(file_name: &str) -> Option<Self> {
+        let file_name = strip_compression(file_name);
+        if file_name.ends_with(".protobuf-delimited") {
+            Some(ChunkFormat::Protobuf)
+        } else if file_name.ends_with(".jsonl") {
+            Some(ChunkFormat::Jsonl)
+        } else {
+            None
+        }
+    }
+}
+
+enum OffersChunk {
+    Protobuf(DelimitedReader<Box<dyn BufRead>>),
+    Jsonl(io::Lines<Box<dyn BufRead>>),
+}
+
+impl OffersChunk {
+    fn next_offer(&mut self, path: &Path) -> Option<Result<market_xml::Offer, CliError>> {
+        match self {
+            OffersChunk::Protobuf(reader) => match reader.next_message() {
+                Ok(Some(data)) => Some(market_xml::Offer::decode(data).context(ProtobufDecodeSnafu { path })),
+                Ok(None) => None,
+                Err(e) => Some(Err(e).context(ReadInputFileSnafu { path })),
+            },
+            OffersChunk::Jsonl(lines) => match lines.next()? {
+                Ok(line) => Some(serde_json::from_str(&line).context(JsonDecodeSnafu { path })),
+                Err(e) => Some(Err(e).context(ReadInputFileSnafu { path })),
+            },
+        }
+    }
+}
+
+/// Streams offers of chunks since they can be huge
+struct OffersReader {
+    chunks: std::vec::IntoIter<(PathBuf, ChunkFormat)>,
+    chunk_path: PathBuf,
+    chunk: Option<OffersChunk>,
+}
+
+impl Iterator for OffersReader {
+    type Item = Result<market_xml::Offer, CliError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(ref mut chunk) = self.chunk {
+                if let Some(offer) = chunk.next_offer(&self.chunk_path) {
+                    return Some(offer);
+                }
+            }
+            let (chunk_path, format) = self.chunks.next()?;
+            self.chunk_path = chunk_path;
+            let reader = match open_file(&self.chunk_path) {
+                Ok(reader) => reader,
+                Err(e) => return Some(Err(e).context(ReadInputFileSnafu { path: self.chunk_path.clone() })),
+            };
+            self.chunk = Some(match format {
+                ChunkFormat::Protobuf => OffersChunk::Protobuf(DelimitedReader::new(reader)),
+                ChunkFormat::Jsonl => OffersChunk::Jsonl(reader.lines()),
+            });
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsString;
+    use std::fs;
+
+    use failure::Error;
+
+    use crate::manifest::Manifest;
+    use crate::market_xml::{Offer, Shop, YmlCatalog};
+    use crate::output::{FileCompression, Output, OutputConfig, OutputFormat};
+    use crate::parquet_writer::ParquetCompression;
+    use super::{CliError, Command, InputDir, Opts};
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn test_parsing_args_without_command() {
+        let opts = Opts::parse_args(args(&["market-xml", "-o", "out", "--offers-chunk", "100", "feed.xml"]));
+        match opts.command {
+            Command::Parse(parse_opts) => {
+                assert_eq!(parse_opts.output_dir.to_str(), Some("out"));
+                assert_eq!(parse_opts.offers_chunk_size, Some(100));
+                assert_eq!(parse_opts.xml_file, "feed.xml");
+            }
+            command => panic!("Expected parse command, got {:?}", command),
+        }
+
+        let opts = Opts::parse_args(args(&["market-xml", "diff", "old", "new"]));
+        assert!(matches!(opts.command, Command::Diff(_)));
+    }
+
+    #[test]
+    fn test_reading_input_dir() -> Result<(), Error> {
+        let out_dir = std::env::temp_dir().join(format!("market-xml-input-dir-{}", std::process::id()));
+        fs::create_dir_all(&out_dir)?;
+        assert!(matches!(InputDir::open(&out_dir), Err(CliError::ReadManifest { .. })));
+
+        let config = OutputConfig {
+            format: OutputFormat::Protobuf,
+            offers_chunk_size: Some(1),
+            offers_chunk_bytes: None,
+            compression: FileCompression::Zstd,
+            compression_level: None,
+            parquet_compression: ParquetCompression::Snappy,
+            parquet_row_group_size: 10000,
+            es_index: String::new(),
+            es_delete_unavailable: false,
+        };
+        let yml_catalog = YmlCatalog {
+            shop: Some(Shop { name: "Shop".to_string(), ..Default::default() }),
+            ..Default::default()
+        };
+        let mut output = Output::create(config, &out_dir)?;
+        output.write_message("yml_catalog", &yml_catalog)?;
+        for id in &["1", "2"] {
+            output.write_offer(&Offer { id: id.to_string(), ..Default::default() })?;
+        }
+        output.finish(Manifest::default())?;
+
+        let input_dir = InputDir::open(&out_dir)?;
+        let catalog = input_dir.read_catalog()?;
+        let ids = input_dir.offers()?
+            .map(|offer| offer.map(|offer| offer.id))
+            .collect::<Result<Vec<_>, _>>()?;
+        fs::remove_dir_all(&out_dir)?;
+        assert_eq!(catalog, yml_catalog);
+        assert_eq!(ids, vec!("1", "2"));
 
-    fn write<M: Message>(&mut self, msg: &M, buf: &mut BytesMut) -> Result<(), CliError> {
-        msg.encode_length_delimited(buf).context(ProtobufEncodeSnafu)?;
-        self.writer.write_all(buf)
-            .context(WriteOutputFileSnafu { path: self.file_path.clone() })?;
-        buf.clear();
-    
         Ok(())
     }
 }
@@ -0,0 +1,148 @@
+use serde::{Deserialize, Serialize};
+
+use sha2::{Digest, Sha256};
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub(crate) const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// Describes a parsing run, it is written after all the other files
+/// so its presence means the output is complete
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub(crate) struct Manifest {
+    pub(crate) tool_version: String,
+    pub(crate) source: String,
+    pub(crate) feed_date: String,
+    pub(crate) shop_name: String,
+    pub(crate) total_offers: u64,
+    pub(crate) offers_with_errors: u64,
+    /// Offers that are not written in the incremental mode
+    pub(crate) unchanged_offers: u64,
+    /// Offers skipped by the filter
+    pub(crate) filtered_offers: u64,
+    pub(crate) warnings: u64,
+    /// `Last-Modified` header of a downloaded feed
+    pub(crate) last_modified: Option<String>,
+    /// `ETag` header of a downloaded feed
+    pub(crate) etag: Option<String>,
+    /// Milliseconds since the unix epoch
+    pub(crate) started_at: u64,
+    pub(crate) finished_at: u64,
+    pub(crate) files: Vec<ManifestFile>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub(crate) struct ManifestFile {
+    pub(crate) name: String,
+    /// Either `offers` or a name of a message like `yml_catalog`
+    #[serde(rename = "type")]
+    pub(crate) file_type: String,
+    pub(crate) records: u64,
+    pub(crate) bytes: u64,
+    pub(crate) sha256: String,
+}
+
+impl Manifest {
+    /// Reads a manifest of an output directory
+    pub(crate) fn read(out_dir: &Path) -> io::Result<Self> {
+        let file = File::open(out_dir.join(MANIFEST_FILE_NAME))?;
+        Ok(serde_json::from_reader(io::BufReader::new(file))?)
+    }
+}
+
+impl ManifestFile {
+    pub(crate) fn new(name: &str, file_type: &str, records: u64, digest: FileDigest) -> Self {
+        Self {
+            name: name.to_string(),
+            file_type: file_type.to_string(),
+            records,
+            bytes: digest.bytes,
+            sha256: digest.sha256,
+        }
+    }
+}
+
+/// Size and checksum of a file
+#[derive(Debug, PartialEq)]
+pub(crate) struct FileDigest {
+    pub(crate) bytes: u64,
+    pub(crate) sha256: String,
+}
+
+impl FileDigest {
+    /// Reads a whole file, only for files that cannot be hashed while writing
+    pub(crate) fn read(file_path: &Path) -> io::Result<Self> {
+        let mut writer = HashingWriter::new(io::sink());
+        io::copy(&mut File::open(file_path)?, &mut writer)?;
+        Ok(writer.digest())
+    }
+}
+
+/// Counts and hashes all the bytes passed to an underlying writer
+pub(crate) struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    bytes: u64,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            bytes: 0,
+        }
+    }
+
+    /// Digest of the bytes written so far
+    pub(crate) fn digest(&self) -> FileDigest {
+        FileDigest {
+            bytes: self.bytes,
+            sha256: format!("{:x}", self.hasher.clone().finalize()),
+        }
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub(crate) fn timestamp_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as u64)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::{FileDigest, HashingWriter};
+
+    #[test]
+    fn test_hashing_writer() {
+        let mut data = vec!();
+        let mut writer = HashingWriter::new(&mut data);
+        writer.write_all(b"{\"id\":\"3\"}\n").unwrap();
+        assert_eq!(
+            writer.digest(),
+            FileDigest {
+                bytes: 11,
+                sha256: "201e50f87c7d235324a40ad703e79b3bc41aa26cd3e63ba262d56a3bb9450951".to_string(),
+            }
+        );
+        assert_eq!(data.len(), 11);
+    }
+}
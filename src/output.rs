@@ -0,0 +1,1108 @@
+use bytes::BytesMut;
+
+use flate2::write::GzEncoder;
+
+use parquet::errors::ParquetError;
+
+use prost::{EncodeError, Message};
+
+use serde::Serialize;
+
+use snafu::{OptionExt, ResultExt, Snafu};
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::incremental::OFFER_HASHES_FILE_NAME;
+use crate::manifest::{FileDigest, HashingWriter, Manifest, ManifestFile, MANIFEST_FILE_NAME};
+use crate::market_xml::{Errors, Offer, OfferHashes, OfferIds, Warnings, YmlCatalog};
+use crate::parquet_writer::{ParquetCompression, ParquetWriter};
+use crate::sqlite_writer::SqliteWriter;
+
+const SQLITE_FILE_NAME: &str = "market_xml.sqlite";
+
+#[derive(Debug, Snafu)]
+pub(crate) enum OutputError {
+    #[snafu(display("Output directory {:?} is not empty, use --overwrite to replace it", path))]
+    OutputDirExists { path: PathBuf },
+    #[snafu(display("Output directory {:?} must have a name", path))]
+    InvalidOutputDir { path: PathBuf },
+    #[snafu(display("Cannot read an output directory {:?}: {}", path, source))]
+    ReadDir { source: io::Error, path: PathBuf },
+    #[snafu(display("Cannot create an output directory {:?}: {}", path, source))]
+    CreateDir { source: io::Error, path: PathBuf },
+    #[snafu(display("Cannot replace an output directory {:?}: {}", path, source))]
+    ReplaceDir { source: io::Error, path: PathBuf },
+    #[snafu(display("Cannot open an output file {:?}: {}", path, source))]
+    OpenFile { source: io::Error, path: PathBuf },
+    #[snafu(display("Cannot write an output file {:?}: {}", path, source))]
+    WriteFile { source: io::Error, path: PathBuf },
+    #[snafu(display("Cannot read a written file {:?}: {}", path, source))]
+    ReadFile { source: io::Error, path: PathBuf },
+    #[snafu(display("Error when encoding to protobuf: {}", source))]
+    ProtobufEncode { source: EncodeError },
+    #[snafu(display("Error when encoding to json {:?}: {}", path, source))]
+    JsonEncode { source: serde_json::Error, path: PathBuf },
+    #[snafu(display("Cannot write a parquet file {:?}: {}", path, source))]
+    Parquet { source: ParquetError, path: PathBuf },
+    #[snafu(display("Cannot write a sqlite database {:?}: {}", path, source))]
+    Sqlite { source: rusqlite::Error, path: PathBuf },
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum OutputFormat {
+    Protobuf,
+    /// One json object per line using the canonical proto3 json mapping
+    Jsonl,
+    /// Offers as parquet files, other messages as protobuf
+    Parquet,
+    /// Offers as bodies of elasticsearch bulk requests, other messages as json
+    EsBulk,
+    /// Everything in a single sqlite database
+    Sqlite,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "protobuf" => Ok(OutputFormat::Protobuf),
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            "parquet" => Ok(OutputFormat::Parquet),
+            "es-bulk" => Ok(OutputFormat::EsBulk),
+            "sqlite" => Ok(OutputFormat::Sqlite),
+            _ => Err(format!("unknown output format: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum FileCompression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl FromStr for FileCompression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(FileCompression::None),
+            "gzip" => Ok(FileCompression::Gzip),
+            "zstd" => Ok(FileCompression::Zstd),
+            _ => Err(format!("unknown compression: {}", s)),
+        }
+    }
+}
+
+impl FileCompression {
+    fn extension(&self) -> &'static str {
+        match self {
+            FileCompression::None => "",
+            FileCompression::Gzip => ".gz",
+            FileCompression::Zstd => ".zst",
+        }
+    }
+}
+
+impl OutputFormat {
+    fn offers_file_name(&self, chunk_ix: u32) -> String {
+        match self {
+            OutputFormat::Protobuf => format!("offers-{}.protobuf-delimited", chunk_ix),
+            OutputFormat::Jsonl => format!("offers-{}.jsonl", chunk_ix),
+            OutputFormat::Parquet => format!("offers-{}.parquet", chunk_ix),
+            OutputFormat::EsBulk => format!("offers-{}.ndjson", chunk_ix),
+            OutputFormat::Sqlite => SQLITE_FILE_NAME.to_string(),
+        }
+    }
+
+    /// File name of a single message such as a catalog or a list of errors
+    fn message_file_name(&self, name: &str) -> String {
+        match self {
+            OutputFormat::Protobuf | OutputFormat::Parquet => format!("{}.protobuf", name),
+            OutputFormat::Jsonl | OutputFormat::EsBulk => format!("{}.json", name),
+            OutputFormat::Sqlite => SQLITE_FILE_NAME.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct OutputConfig {
+    pub(crate) format: OutputFormat,
+    /// Maximum number of offers in a chunk
+    pub(crate) offers_chunk_size: Option<u32>,
+    /// Maximum size of a chunk in bytes before compression
+    pub(crate) offers_chunk_bytes: Option<u64>,
+    /// Compression of offer chunks and messages, parquet files have their own compression
+    pub(crate) compression: FileCompression,
+    /// Default level of the compression when missing
+    pub(crate) compression_level: Option<i32>,
+    pub(crate) parquet_compression: ParquetCompression,
+    /// Maximum number of offers in a parquet row group
+    pub(crate) parquet_row_group_size: usize,
+    pub(crate) es_index: String,
+    /// Unavailable offers are written as delete actions
+    pub(crate) es_delete_unavailable: bool,
+}
+
+impl OutputConfig {
+    fn offers_file_name(&self, chunk_ix: u32) -> String {
+        match self.format {
+            OutputFormat::Parquet | OutputFormat::Sqlite => self.format.offers_file_name(chunk_ix),
+            _ => self.format.offers_file_name(chunk_ix) + self.compression.extension(),
+        }
+    }
+
+    fn message_file_name(&self, name: &str) -> String {
+        self.format.message_file_name(name) + self.compression.extension()
+    }
+
+    fn create_file_writer(&self, file_path: &Path) -> Result<FileWriter, OutputError> {
+        FileWriter::create(file_path, self.compression, self.compression_level)
+    }
+
+    fn open_offers_writer(
+        &self, out_dir: &Path, chunk_ix: u32
+    ) -> Result<Box<dyn OffersWriter>, OutputError> {
+        let file_path = out_dir.join(self.offers_file_name(chunk_ix));
+        Ok(match self.format {
+            OutputFormat::Protobuf => Box::new(DelimitedMessageWriter::open(self.create_file_writer(&file_path)?)),
+            OutputFormat::Jsonl => Box::new(JsonLinesWriter::open(self.create_file_writer(&file_path)?)),
+            OutputFormat::EsBulk => Box::new(EsBulkWriter::open(
+                self.create_file_writer(&file_path)?, &self.es_index, self.es_delete_unavailable
+            )),
+            OutputFormat::Parquet => {
+                let file = HashingWriter::new(create_file(&file_path)?);
+                let writer = ParquetWriter::new(
+                    file, &file_path, self.parquet_compression, self.parquet_row_group_size
+                )
+                    .context(ParquetSnafu { path: &file_path })?;
+                Box::new(writer)
+            }
+            OutputFormat::Sqlite => unreachable!("sqlite output is not chunked"),
+        })
+    }
+}
+
+/// A message that is written besides offers
+pub(crate) trait OutputMessage: Message + Serialize {
+    fn write_sqlite(&self, name: &str, writer: &mut SqliteWriter) -> rusqlite::Result<()>;
+
+    /// Number of records for a manifest
+    fn records(&self) -> usize;
+}
+
+impl OutputMessage for YmlCatalog {
+    fn records(&self) -> usize {
+        1
+    }
+
+    fn write_sqlite(&self, _name: &str, writer: &mut SqliteWriter) -> rusqlite::Result<()> {
+        writer.write_catalog(self)
+    }
+}
+
+impl OutputMessage for OfferIds {
+    // ids of existing offers can be selected from the offers table
+    fn write_sqlite(&self, name: &str, writer: &mut SqliteWriter) -> rusqlite::Result<()> {
+        if name == "offer-ids-deleted" {
+            writer.write_deleted_ids(&self.ids)?;
+        }
+        Ok(())
+    }
+
+    fn records(&self) -> usize {
+        self.ids.len()
+    }
+}
+
+impl OutputMessage for Errors {
+    fn write_sqlite(&self, _name: &str, writer: &mut SqliteWriter) -> rusqlite::Result<()> {
+        writer.write_errors("error", &self.errors)
+    }
+
+    fn records(&self) -> usize {
+        self.errors.len()
+    }
+}
+
+impl OutputMessage for Warnings {
+    fn write_sqlite(&self, _name: &str, writer: &mut SqliteWriter) -> rusqlite::Result<()> {
+        writer.write_errors("warning", &self.warnings)
+    }
+
+    fn records(&self) -> usize {
+        self.warnings.len()
+    }
+}
+
+/// Temporary sibling of an output directory that replaces the output directory
+/// once all the files are written, so consumers never see partial results.
+/// The temporary directory is removed when dropped before that
+pub(crate) struct StagingDir {
+    target: PathBuf,
+    path: PathBuf,
+    committed: bool,
+}
+
+impl StagingDir {
+    pub(crate) fn create(target: &Path, overwrite: bool) -> Result<Self, OutputError> {
+        let target = resolve_target(target)?;
+        let is_empty = match fs::read_dir(&target) {
+            Ok(mut entries) => entries.next().is_none(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => true,
+            Err(e) => return Err(e).context(ReadDirSnafu { path: &target }),
+        };
+        if !is_empty && !overwrite {
+            return OutputDirExistsSnafu { path: &target }.fail();
+        }
+        remove_leftovers(&target)?;
+        let path = sibling_dir(&target, "tmp");
+        if path.exists() {
+            fs::remove_dir_all(&path)
+                .context(CreateDirSnafu { path: &path })?;
+        }
+        fs::create_dir_all(&path)
+            .context(CreateDirSnafu { path: &path })?;
+        Ok(Self {
+            target,
+            path,
+            committed: false,
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Replaces the output directory with the staging one
+    pub(crate) fn commit(mut self) -> Result<(), OutputError> {
+        if !self.target.exists() {
+            fs::rename(&self.path, &self.target)
+                .context(ReplaceDirSnafu { path: &self.target })?;
+            self.committed = true;
+            return Ok(());
+        }
+        // after the exchange the staging path holds the previous results
+        let old_path = match exchange_dirs(&self.path, &self.target) {
+            Ok(()) => self.path.clone(),
+            // the platform or the file system cannot exchange directories,
+            // so the old directory is moved away first
+            Err(_) => {
+                let old_path = sibling_dir(&self.target, "old");
+                fs::rename(&self.target, &old_path)
+                    .context(ReplaceDirSnafu { path: &self.target })?;
+                fs::rename(&self.path, &self.target)
+                    .context(ReplaceDirSnafu { path: &self.target })?;
+                old_path
+            }
+        };
+        self.committed = true;
+        fs::remove_dir_all(&old_path)
+            .context(ReplaceDirSnafu { path: &old_path })
+    }
+}
+
+impl Drop for StagingDir {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+}
+
+// absolute path of a target that may not exist yet, so `.` and `..` are replaced with real names
+fn resolve_target(target: &Path) -> Result<PathBuf, OutputError> {
+    let path = match target.canonicalize() {
+        Ok(path) => path,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let name = target.file_name()
+                .context(InvalidOutputDirSnafu { path: target })?;
+            let parent = match target.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            parent.canonicalize()
+                .context(CreateDirSnafu { path: parent })?
+                .join(name)
+        }
+        Err(e) => return Err(e).context(CreateDirSnafu { path: target }),
+    };
+    // the root directory has no siblings
+    if path.file_name().is_none() {
+        return InvalidOutputDirSnafu { path: target }.fail();
+    }
+    Ok(path)
+}
+
+// hidden directory next to the target one like `.output.tmp-123`
+fn sibling_dir(target: &Path, kind: &str) -> PathBuf {
+    target.with_file_name(format!("{}{}-{}", sibling_prefix(target), kind, std::process::id()))
+}
+
+fn sibling_prefix(target: &Path) -> String {
+    let name = target.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    format!(".{}.", name)
+}
+
+// removes staging directories of runs that crashed before committing
+fn remove_leftovers(target: &Path) -> Result<(), OutputError> {
+    let parent = match target.parent() {
+        Some(parent) => parent,
+        None => return Ok(()),
+    };
+    let prefix = sibling_prefix(target);
+    let entries = fs::read_dir(parent)
+        .context(ReadDirSnafu { path: parent })?;
+    for entry in entries {
+        let entry = entry.context(ReadDirSnafu { path: parent })?;
+        let file_name = entry.file_name();
+        let pid = file_name.to_str()
+            .and_then(|name| name.strip_prefix(&prefix))
+            .and_then(|kind| kind.strip_prefix("tmp-").or_else(|| kind.strip_prefix("old-")))
+            .and_then(|pid| pid.parse::<u32>().ok());
+        match pid {
+            Some(pid) if pid != std::process::id() && !is_running(pid) => {
+                let path = entry.path();
+                fs::remove_dir_all(&path)
+                    .context(CreateDirSnafu { path: &path })?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    use std::convert::TryFrom;
+
+    // zero and negative values address groups of processes
+    let pid = match libc::pid_t::try_from(pid) {
+        Ok(pid) if pid > 0 => pid,
+        _ => return false,
+    };
+    // the null signal only checks that a process exists
+    let res = unsafe { libc::kill(pid, 0) };
+    res == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+// leftovers cannot be told apart from directories of running processes
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    true
+}
+
+/// Atomically exchanges two existing directories
+#[cfg(target_os = "linux")]
+fn exchange_dirs(from: &Path, to: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    // not every libc exports a wrapper of the syscall
+    const RENAME_EXCHANGE: libc::c_uint = 1 << 1;
+
+    let from = CString::new(from.as_os_str().as_bytes())?;
+    let to = CString::new(to.as_os_str().as_bytes())?;
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_renameat2,
+            libc::AT_FDCWD, from.as_ptr(),
+            libc::AT_FDCWD, to.as_ptr(),
+            RENAME_EXCHANGE,
+        )
+    };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn exchange_dirs(_from: &Path, _to: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "directories cannot be exchanged atomically"))
+}
+
+enum Sink {
+    Files(Box<dyn OffersWriter>),
+    Sqlite(SqliteWriter),
+}
+
+/// Writes results of a parsing into an output directory splitting offers by chunks,
+/// names of the written files are printed to stdout and listed in a manifest
+pub(crate) struct Output {
+    config: OutputConfig,
+    out_dir: PathBuf,
+    chunk_ix: u32,
+    chunk_offers: u32,
+    total_offers: u64,
+    sink: Sink,
+    files: Vec<ManifestFile>,
+}
+
+impl Output {
+    pub(crate) fn create(config: OutputConfig, out_dir: &Path) -> Result<Self, OutputError> {
+        let sink = match config.format {
+            OutputFormat::Sqlite => {
+                let file_path = out_dir.join(SQLITE_FILE_NAME);
+                create_file(&file_path)?;
+                Sink::Sqlite(
+                    SqliteWriter::create(&file_path)
+                        .context(SqliteSnafu { path: &file_path })?
+                )
+            }
+            _ => Sink::Files(config.open_offers_writer(out_dir, 0)?),
+        };
+        Ok(Self {
+            config,
+            out_dir: out_dir.to_path_buf(),
+            chunk_ix: 0,
+            chunk_offers: 0,
+            total_offers: 0,
+            sink,
+            files: vec!(),
+        })
+    }
+
+    pub(crate) fn write_offer(&mut self, offer: &Offer) -> Result<(), OutputError> {
+        let max_bytes = self.config.offers_chunk_bytes;
+        let chunk_bytes = match self.sink {
+            Sink::Files(ref mut offers_writer) => {
+                if !offers_writer.write(offer, max_bytes)? {
+                    self.next_chunk()?;
+                    return self.write_offer(offer);
+                }
+                offers_writer.bytes_written()
+            }
+            Sink::Sqlite(ref mut writer) => {
+                writer.write_offer(offer)
+                    .context(SqliteSnafu { path: writer.file_path() })?;
+                0
+            }
+        };
+        self.chunk_offers += 1;
+        self.total_offers += 1;
+
+        let chunk_is_full = max_bytes.is_some_and(|max_bytes| chunk_bytes >= max_bytes);
+        if Some(self.chunk_offers) == self.config.offers_chunk_size || chunk_is_full {
+            self.next_chunk()?;
+        }
+        Ok(())
+    }
+
+    fn next_chunk(&mut self) -> Result<(), OutputError> {
+        match self.sink {
+            Sink::Files(ref mut offers_writer) => {
+                let digest = offers_writer.finish()?;
+                self.add_file(&self.config.offers_file_name(self.chunk_ix), "offers", self.chunk_offers as u64, digest);
+                self.chunk_ix += 1;
+                let offers_writer = self.config.open_offers_writer(&self.out_dir, self.chunk_ix)?;
+                self.sink = Sink::Files(offers_writer);
+            }
+            // a chunk is a transaction for the sqlite format
+            Sink::Sqlite(ref mut writer) => {
+                writer.commit()
+                    .context(SqliteSnafu { path: writer.file_path() })?;
+            }
+        }
+        self.chunk_offers = 0;
+        Ok(())
+    }
+
+    /// Prints a name of a finished file and adds it to the manifest
+    fn add_file(&mut self, file_name: &str, file_type: &str, records: u64, digest: FileDigest) {
+        self.files.push(ManifestFile::new(file_name, file_type, records, digest));
+        println!("{}", file_name);
+    }
+
+    pub(crate) fn write_message<M: OutputMessage>(&mut self, name: &str, msg: &M) -> Result<(), OutputError> {
+        let writer = match self.sink {
+            Sink::Files(_) => {
+                let file_name = self.config.message_file_name(name);
+                let writer = self.config.create_file_writer(&self.out_dir.join(&file_name))?;
+                let digest = write_message_file(self.config.format, writer, msg)?;
+                self.add_file(&file_name, name, msg.records() as u64, digest);
+                return Ok(());
+            }
+            Sink::Sqlite(ref mut writer) => writer,
+        };
+        msg.write_sqlite(name, writer)
+            .context(SqliteSnafu { path: writer.file_path() })
+    }
+
+    /// Offer hashes are always written as an uncompressed protobuf file
+    /// so any output directory can be used as a previous one
+    pub(crate) fn write_offer_hashes(&mut self, hashes: &OfferHashes) -> Result<(), OutputError> {
+        let file_path = self.out_dir.join(OFFER_HASHES_FILE_NAME);
+        let mut writer = FileWriter::create(&file_path, FileCompression::None, None)?;
+        writer.write_all(&hashes.encode_to_vec())?;
+        let digest = writer.finish()?;
+        self.add_file(OFFER_HASHES_FILE_NAME, "offer-hashes", hashes.hashes.len() as u64, digest);
+        Ok(())
+    }
+
+    /// Writes an uncompressed text file
+    pub(crate) fn write_text(&mut self, name: &str, file_type: &str, text: &str) -> Result<(), OutputError> {
+        let file_path = self.out_dir.join(name);
+        let mut writer = HashingWriter::new(create_file(&file_path)?);
+        writer.write_all(text.as_bytes())
+            .context(WriteFileSnafu { path: &file_path })?;
+        self.add_file(name, file_type, 1, writer.digest());
+        Ok(())
+    }
+
+    /// Finishes the last chunk of offers and writes the manifest listing all the files
+    pub(crate) fn finish(mut self, mut manifest: Manifest) -> Result<(), OutputError> {
+        match self.sink {
+            Sink::Files(ref mut offers_writer) => {
+                let digest = offers_writer.finish()?;
+                self.add_file(&self.config.offers_file_name(self.chunk_ix), "offers", self.chunk_offers as u64, digest);
+            }
+            // sqlite writes pages in place so the database can only be hashed after closing
+            Sink::Sqlite(ref mut writer) => {
+                writer.finish()
+                    .context(SqliteSnafu { path: writer.file_path() })?;
+                let digest = FileDigest::read(writer.file_path())
+                    .context(ReadFileSnafu { path: writer.file_path() })?;
+                self.add_file(SQLITE_FILE_NAME, "sqlite", self.total_offers, digest);
+            }
+        }
+
+        manifest.files = self.files;
+        let manifest_path = self.out_dir.join(MANIFEST_FILE_NAME);
+        let mut writer = BufWriter::new(create_file(&manifest_path)?);
+        serde_json::to_writer_pretty(&mut writer, &manifest)
+            .context(JsonEncodeSnafu { path: &manifest_path })?;
+        writer.flush()
+            .context(WriteFileSnafu { path: &manifest_path })?;
+        println!("{}", MANIFEST_FILE_NAME);
+        Ok(())
+    }
+}
+
+fn write_message_file<M: Message + Serialize>(
+    format: OutputFormat, mut writer: FileWriter, msg: &M
+) -> Result<FileDigest, OutputError> {
+    match format {
+        OutputFormat::Protobuf | OutputFormat::Parquet => {
+            let mut buf = BytesMut::new();
+            msg.encode(&mut buf).context(ProtobufEncodeSnafu)?;
+            writer.write_all(&buf)?;
+        }
+        OutputFormat::Jsonl | OutputFormat::EsBulk | OutputFormat::Sqlite => {
+            serde_json::to_writer(&mut writer.encoder, msg)
+                .context(JsonEncodeSnafu { path: &writer.file_path })?;
+        }
+    }
+    writer.finish()
+}
+
+type OutputFile = BufWriter<HashingWriter<File>>;
+
+enum Encoder {
+    Plain(OutputFile),
+    Gzip(GzEncoder<OutputFile>),
+    Zstd(zstd::Encoder<'static, OutputFile>),
+}
+
+impl Encoder {
+    fn file(&self) -> &HashingWriter<File> {
+        match self {
+            Encoder::Plain(writer) => writer.get_ref(),
+            Encoder::Gzip(writer) => writer.get_ref().get_ref(),
+            Encoder::Zstd(writer) => writer.get_ref().get_ref(),
+        }
+    }
+}
+
+impl Write for Encoder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::Plain(writer) => writer.write(buf),
+            Encoder::Gzip(writer) => writer.write(buf),
+            Encoder::Zstd(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Plain(writer) => writer.flush(),
+            Encoder::Gzip(writer) => writer.flush(),
+            Encoder::Zstd(writer) => writer.flush(),
+        }
+    }
+}
+
+/// Buffered and optionally compressed output file
+struct FileWriter {
+    file_path: PathBuf,
+    encoder: Encoder,
+}
+
+impl FileWriter {
+    fn create(file_path: &Path, compression: FileCompression, level: Option<i32>) -> Result<Self, OutputError> {
+        let writer = BufWriter::new(HashingWriter::new(create_file(file_path)?));
+        let encoder = match compression {
+            FileCompression::None => Encoder::Plain(writer),
+            FileCompression::Gzip => {
+                let level = level.map_or_else(flate2::Compression::default, |l| flate2::Compression::new(l as u32));
+                Encoder::Gzip(GzEncoder::new(writer, level))
+            }
+            FileCompression::Zstd => {
+                // zero means a default level of zstd
+                let encoder = zstd::Encoder::new(writer, level.unwrap_or(0))
+                    .context(OpenFileSnafu { path: file_path })?;
+                Encoder::Zstd(encoder)
+            }
+        };
+        Ok(Self {
+            file_path: file_path.to_path_buf(),
+            encoder,
+        })
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), OutputError> {
+        self.encoder.write_all(buf)
+            .context(WriteFileSnafu { path: &self.file_path })
+    }
+
+    /// Writes a trailer of a compressed stream and flushes the file
+    fn finish(&mut self) -> Result<FileDigest, OutputError> {
+        let res = match self.encoder {
+            Encoder::Plain(ref mut writer) => writer.flush(),
+            Encoder::Gzip(ref mut writer) => writer.try_finish().and_then(|_| writer.get_mut().flush()),
+            Encoder::Zstd(ref mut writer) => writer.do_finish().and_then(|_| writer.get_mut().flush()),
+        };
+        res.context(WriteFileSnafu { path: &self.file_path })?;
+        Ok(self.encoder.file().digest())
+    }
+}
+
+/// Writes a chunk of offers
+pub(crate) trait OffersWriter {
+    /// Writes an offer unless the chunk would exceed `max_bytes` of uncompressed data,
+    /// an offer is always written into an empty chunk
+    fn write(&mut self, offer: &Offer, max_bytes: Option<u64>) -> Result<bool, OutputError>;
+
+    /// Number of uncompressed bytes written so far
+    fn bytes_written(&self) -> u64;
+
+    /// Flushes written offers and returns a digest of the file,
+    /// the writer must not be used after that
+    fn finish(&mut self) -> Result<FileDigest, OutputError>;
+}
+
+struct DelimitedMessageWriter {
+    writer: FileWriter,
+    buf: BytesMut,
+    bytes_written: u64,
+}
+
+impl DelimitedMessageWriter {
+    fn open(writer: FileWriter) -> Self {
+        Self {
+            writer,
+            buf: BytesMut::new(),
+            bytes_written: 0,
+        }
+    }
+}
+
+impl OffersWriter for DelimitedMessageWriter {
+    fn write(&mut self, offer: &Offer, max_bytes: Option<u64>) -> Result<bool, OutputError> {
+        offer.encode_length_delimited(&mut self.buf).context(ProtobufEncodeSnafu)?;
+        if exceeds(self.bytes_written, self.buf.len(), max_bytes) {
+            self.buf.clear();
+            return Ok(false);
+        }
+        self.writer.write_all(&self.buf)?;
+        self.bytes_written += self.buf.len() as u64;
+        self.buf.clear();
+
+        Ok(true)
+    }
+
+    fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    fn finish(&mut self) -> Result<FileDigest, OutputError> {
+        self.writer.finish()
+    }
+}
+
+struct JsonLinesWriter {
+    writer: FileWriter,
+    line: Vec<u8>,
+    bytes_written: u64,
+}
+
+impl JsonLinesWriter {
+    fn open(writer: FileWriter) -> Self {
+        Self {
+            writer,
+            line: vec!(),
+            bytes_written: 0,
+        }
+    }
+
+    fn encode_line<T: Serialize>(&mut self, value: &T) -> Result<(), OutputError> {
+        serde_json::to_writer(&mut self.line, value)
+            .context(JsonEncodeSnafu { path: &self.writer.file_path })?;
+        self.line.push(b'\n');
+        Ok(())
+    }
+
+    /// Writes encoded lines unless they exceed `max_bytes`
+    fn write_lines(&mut self, max_bytes: Option<u64>) -> Result<bool, OutputError> {
+        let fits = !exceeds(self.bytes_written, self.line.len(), max_bytes);
+        if fits {
+            self.writer.write_all(&self.line)?;
+            self.bytes_written += self.line.len() as u64;
+        }
+        self.line.clear();
+        Ok(fits)
+    }
+}
+
+impl OffersWriter for JsonLinesWriter {
+    fn write(&mut self, offer: &Offer, max_bytes: Option<u64>) -> Result<bool, OutputError> {
+        self.encode_line(offer)?;
+        self.write_lines(max_bytes)
+    }
+
+    fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    fn finish(&mut self) -> Result<FileDigest, OutputError> {
+        self.writer.finish()
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum EsBulkAction<'a> {
+    Index(EsBulkMeta<'a>),
+    Delete(EsBulkMeta<'a>),
+}
+
+#[derive(Serialize)]
+struct EsBulkMeta<'a> {
+    _index: &'a str,
+    _id: &'a str,
+}
+
+/// Writes an action line followed by a source line for every offer
+struct EsBulkWriter {
+    lines: JsonLinesWriter,
+    index: String,
+    delete_unavailable: bool,
+}
+
+impl EsBulkWriter {
+    fn open(writer: FileWriter, index: &str, delete_unavailable: bool) -> Self {
+        Self {
+            lines: JsonLinesWriter::open(writer),
+            index: index.to_string(),
+            delete_unavailable,
+        }
+    }
+}
+
+impl OffersWriter for EsBulkWriter {
+    fn write(&mut self, offer: &Offer, max_bytes: Option<u64>) -> Result<bool, OutputError> {
+        let meta = EsBulkMeta { _index: &self.index, _id: &offer.id };
+        if self.delete_unavailable && offer.available == Some(false) {
+            self.lines.encode_line(&EsBulkAction::Delete(meta))?;
+        } else {
+            self.lines.encode_line(&EsBulkAction::Index(meta))?;
+            self.lines.encode_line(offer)?;
+        }
+        self.lines.write_lines(max_bytes)
+    }
+
+    fn bytes_written(&self) -> u64 {
+        self.lines.bytes_written
+    }
+
+    fn finish(&mut self) -> Result<FileDigest, OutputError> {
+        self.lines.writer.finish()
+    }
+}
+
+impl OffersWriter for ParquetWriter<HashingWriter<File>> {
+    // size of buffered offers is unknown so a chunk can exceed the limit up to a row group
+    fn write(&mut self, offer: &Offer, _max_bytes: Option<u64>) -> Result<bool, OutputError> {
+        ParquetWriter::write(self, offer)
+            .context(ParquetSnafu { path: self.file_path() })?;
+        Ok(true)
+    }
+
+    // only flushed row groups are taken into account
+    fn bytes_written(&self) -> u64 {
+        ParquetWriter::bytes_written(self)
+    }
+
+    fn finish(&mut self) -> Result<FileDigest, OutputError> {
+        ParquetWriter::finish(self)
+            .context(ParquetSnafu { path: self.file_path() })?;
+        Ok(self.inner().digest())
+    }
+}
+
+fn exceeds(bytes_written: u64, len: usize, max_bytes: Option<u64>) -> bool {
+    bytes_written > 0 && max_bytes.is_some_and(|max_bytes| bytes_written + len as u64 > max_bytes)
+}
+
+fn create_file(file_path: &Path) -> Result<File, OutputError> {
+    OpenOptions::new().create_new(true).write(true)
+        .open(file_path)
+        .context(OpenFileSnafu { path: file_path })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::Read;
+    use std::path::Path;
+
+    use failure::Error;
+
+    use flate2::read::GzDecoder;
+
+    use prost::Message;
+
+    use crate::manifest::Manifest;
+    use crate::market_xml::{Offer, OfferIds, Price};
+    use crate::parquet_writer::ParquetCompression;
+    use super::{resolve_target, FileCompression, Output, OutputConfig, OutputError, OutputFormat, StagingDir};
+
+    fn output_config(format: OutputFormat) -> OutputConfig {
+        OutputConfig {
+            format,
+            offers_chunk_size: Some(50000),
+            offers_chunk_bytes: None,
+            compression: FileCompression::None,
+            compression_level: None,
+            parquet_compression: ParquetCompression::Snappy,
+            parquet_row_group_size: 10000,
+            es_index: "offers".to_string(),
+            es_delete_unavailable: true,
+        }
+    }
+
+    #[test]
+    fn test_writing_jsonl() -> Result<(), Error> {
+        let out_dir = std::env::temp_dir().join(format!("market-xml-jsonl-{}", std::process::id()));
+        fs::create_dir_all(&out_dir)?;
+        let offer = Offer {
+            id: "1".to_string(),
+            available: Some(false),
+            category_id: 10,
+            price: Some(Price { price: 8990.0, from: false }),
+            ..Default::default()
+        };
+
+        let output = output_config(OutputFormat::Jsonl);
+        let mut writer = output.open_offers_writer(&out_dir, 0)?;
+        writer.write(&offer, None)?;
+        writer.write(&Offer { id: "2".to_string(), ..Default::default() }, None)?;
+        writer.finish()?;
+
+        let content = fs::read_to_string(out_dir.join("offers-0.jsonl"))?;
+        fs::remove_dir_all(&out_dir)?;
+        assert_eq!(
+            content,
+            "{\"id\":\"1\",\"available\":false,\"categoryId\":\"10\",\"price\":{\"price\":8990.0}}\n\
+             {\"id\":\"2\"}\n"
+        );
+        let offer_from_json: Offer = serde_json::from_str(content.lines().next().unwrap())?;
+        assert_eq!(offer_from_json, offer);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_writing_es_bulk() -> Result<(), Error> {
+        let out_dir = std::env::temp_dir().join(format!("market-xml-es-bulk-{}", std::process::id()));
+        fs::create_dir_all(&out_dir)?;
+
+        let mut writer = output_config(OutputFormat::EsBulk).open_offers_writer(&out_dir, 0)?;
+        writer.write(&Offer { id: "1".to_string(), available: Some(true), ..Default::default() }, None)?;
+        writer.write(&Offer { id: "2".to_string(), available: Some(false), ..Default::default() }, None)?;
+        writer.finish()?;
+
+        let content = fs::read_to_string(out_dir.join("offers-0.ndjson"))?;
+        fs::remove_dir_all(&out_dir)?;
+        assert_eq!(
+            content,
+            "{\"index\":{\"_index\":\"offers\",\"_id\":\"1\"}}\n\
+             {\"id\":\"1\",\"available\":true}\n\
+             {\"delete\":{\"_index\":\"offers\",\"_id\":\"2\"}}\n"
+        );
+        assert_eq!(writer.bytes_written(), content.len() as u64);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_writing_compressed() -> Result<(), Error> {
+        let out_dir = std::env::temp_dir().join(format!("market-xml-compressed-{}", std::process::id()));
+        fs::create_dir_all(&out_dir)?;
+        let offer = Offer { id: "1".to_string(), available: Some(true), ..Default::default() };
+
+        let mut config = output_config(OutputFormat::Protobuf);
+        config.compression = FileCompression::Gzip;
+        let mut output = Output::create(config, &out_dir)?;
+        output.write_offer(&offer)?;
+        output.write_message("offer-ids-available", &OfferIds { ids: vec!("1".to_string()) })?;
+        output.finish(Manifest::default())?;
+
+        let mut config = output_config(OutputFormat::Jsonl);
+        config.compression = FileCompression::Zstd;
+        config.compression_level = Some(19);
+        let mut writer = config.open_offers_writer(&out_dir, 1)?;
+        writer.write(&offer, None)?;
+        writer.finish()?;
+
+        let mut chunk = vec!();
+        GzDecoder::new(File::open(out_dir.join("offers-0.protobuf-delimited.gz"))?).read_to_end(&mut chunk)?;
+        assert_eq!(Offer::decode_length_delimited(chunk.as_slice())?, offer);
+        let mut ids = vec!();
+        GzDecoder::new(File::open(out_dir.join("offer-ids-available.protobuf.gz"))?).read_to_end(&mut ids)?;
+        assert_eq!(OfferIds::decode(ids.as_slice())?.ids, vec!("1"));
+        let lines = zstd::decode_all(File::open(out_dir.join("offers-1.jsonl.zst"))?)?;
+        fs::remove_dir_all(&out_dir)?;
+        assert_eq!(String::from_utf8(lines)?, "{\"id\":\"1\",\"available\":true}\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_chunking_by_bytes() -> Result<(), Error> {
+        let out_dir = std::env::temp_dir().join(format!("market-xml-chunk-bytes-{}", std::process::id()));
+        fs::create_dir_all(&out_dir)?;
+
+        let mut config = output_config(OutputFormat::Jsonl);
+        config.offers_chunk_size = None;
+        config.offers_chunk_bytes = Some(25);
+        let mut output = Output::create(config, &out_dir)?;
+        // every line takes 11 bytes, the last offer doesn't fit into any chunk
+        for id in &["1", "2", "3", "4", "5", "big offer id"] {
+            output.write_offer(&Offer { id: id.to_string(), ..Default::default() })?;
+        }
+        output.finish(Manifest::default())?;
+
+        let chunks = (0..4)
+            .map(|ix| fs::read_to_string(out_dir.join(format!("offers-{}.jsonl", ix))))
+            .collect::<Result<Vec<_>, _>>()?;
+        fs::remove_dir_all(&out_dir)?;
+        assert_eq!(
+            chunks,
+            vec!(
+                "{\"id\":\"1\"}\n{\"id\":\"2\"}\n",
+                "{\"id\":\"3\"}\n{\"id\":\"4\"}\n",
+                "{\"id\":\"5\"}\n",
+                "{\"id\":\"big offer id\"}\n",
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_writing_manifest() -> Result<(), Error> {
+        let out_dir = std::env::temp_dir().join(format!("market-xml-manifest-{}", std::process::id()));
+        fs::create_dir_all(&out_dir)?;
+
+        let mut config = output_config(OutputFormat::Jsonl);
+        config.offers_chunk_size = Some(2);
+        let mut output = Output::create(config, &out_dir)?;
+        for id in &["1", "2", "3"] {
+            output.write_offer(&Offer { id: id.to_string(), ..Default::default() })?;
+        }
+        output.write_message("offer-ids-available", &OfferIds { ids: vec!("1".to_string(), "3".to_string()) })?;
+        output.finish(Manifest { shop_name: "Shop".to_string(), total_offers: 3, ..Default::default() })?;
+
+        let manifest: serde_json::Value = serde_json::from_reader(File::open(out_dir.join("manifest.json"))?)?;
+        fs::remove_dir_all(&out_dir)?;
+        assert_eq!(manifest["shop_name"], "Shop");
+        assert_eq!(manifest["total_offers"], 3);
+        let files = manifest["files"].as_array().unwrap().iter()
+            .map(|file| (file["name"].as_str().unwrap(), file["type"].as_str().unwrap(), file["records"].as_u64().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            files,
+            vec!(
+                ("offers-0.jsonl", "offers", 2),
+                ("offer-ids-available.json", "offer-ids-available", 2),
+                ("offers-1.jsonl", "offers", 1),
+            )
+        );
+        let last_chunk = &manifest["files"][2];
+        assert_eq!(last_chunk["bytes"], 11);
+        // sha256 of `{"id":"3"}\n`
+        assert_eq!(last_chunk["sha256"], "201e50f87c7d235324a40ad703e79b3bc41aa26cd3e63ba262d56a3bb9450951");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_staging_dir() -> Result<(), Error> {
+        let out_dir = std::env::temp_dir().join(format!("market-xml-staging-{}", std::process::id()));
+        fs::create_dir_all(&out_dir)?;
+        fs::write(out_dir.join("offers-0.jsonl"), "old")?;
+        // left by a crashed run of a process that doesn't exist
+        let leftover_path = out_dir.with_file_name(format!(
+            ".market-xml-staging-{}.tmp-999999999", std::process::id()
+        ));
+        fs::create_dir_all(&leftover_path)?;
+
+        assert!(matches!(StagingDir::create(&out_dir, false), Err(OutputError::OutputDirExists { .. })));
+
+        let staging_dir = StagingDir::create(&out_dir, true)?;
+        assert!(!leftover_path.exists());
+        let staging_path = staging_dir.path().to_path_buf();
+        fs::write(staging_path.join("offers-0.jsonl"), "failed")?;
+        drop(staging_dir);
+        assert!(!staging_path.exists());
+        assert_eq!(fs::read_to_string(out_dir.join("offers-0.jsonl"))?, "old");
+
+        let staging_dir = StagingDir::create(&out_dir, true)?;
+        fs::write(staging_dir.path().join("offers-1.jsonl"), "new")?;
+        staging_dir.commit()?;
+        let files = fs::read_dir(&out_dir)?
+            .map(|entry| entry.map(|e| e.file_name()))
+            .collect::<Result<Vec<_>, _>>()?;
+        fs::remove_dir_all(&out_dir)?;
+        assert_eq!(files, vec!("offers-1.jsonl"));
+        assert!(!staging_path.exists());
+
+        let file_path = out_dir.with_extension("jsonl");
+        fs::write(&file_path, "")?;
+        let res = StagingDir::create(&file_path, true);
+        fs::remove_file(&file_path)?;
+        assert!(matches!(res, Err(OutputError::ReadDir { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolving_target() -> Result<(), Error> {
+        let current_dir = std::env::current_dir()?.canonicalize()?;
+        assert_eq!(resolve_target(Path::new("."))?, current_dir);
+        assert_eq!(resolve_target(Path::new("out"))?, current_dir.join("out"));
+        assert_eq!(resolve_target(Path::new("src/.."))?, current_dir);
+        assert!(matches!(resolve_target(Path::new("/")), Err(OutputError::InvalidOutputDir { .. })));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,387 @@
+use arrow_array::{
+    Array, ArrayRef, BooleanArray, Float32Array, ListArray, MapArray, RecordBatch, StringArray, StructArray,
+    UInt32Array, UInt64Array,
+};
+use arrow_array::builder::{ListBuilder, StringBuilder};
+use arrow_schema::{ArrowError, DataType, Field, Fields};
+
+use arrow_buffer::OffsetBuffer;
+
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, GzipLevel, ZstdLevel};
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::market_xml::{DeliveryOption, Offer};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum ParquetCompression {
+    None,
+    Snappy,
+    Gzip,
+    Zstd,
+}
+
+impl FromStr for ParquetCompression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(ParquetCompression::None),
+            "snappy" => Ok(ParquetCompression::Snappy),
+            "gzip" => Ok(ParquetCompression::Gzip),
+            "zstd" => Ok(ParquetCompression::Zstd),
+            _ => Err(format!("unknown parquet compression: {}", s)),
+        }
+    }
+}
+
+impl From<ParquetCompression> for Compression {
+    fn from(compression: ParquetCompression) -> Self {
+        match compression {
+            ParquetCompression::None => Compression::UNCOMPRESSED,
+            ParquetCompression::Snappy => Compression::SNAPPY,
+            ParquetCompression::Gzip => Compression::GZIP(GzipLevel::default()),
+            ParquetCompression::Zstd => Compression::ZSTD(ZstdLevel::default()),
+        }
+    }
+}
+
+/// Writes offers into a parquet file with a flattened schema,
+/// every `row_group_size` offers are buffered and written as a single row group
+pub(crate) struct ParquetWriter<W: Write + Send> {
+    file_path: PathBuf,
+    writer: ArrowWriter<W>,
+    row_group_size: usize,
+    offers: Vec<Offer>,
+}
+
+impl<W: Write + Send> ParquetWriter<W> {
+    pub(crate) fn new(
+        file: W, file_path: &Path, compression: ParquetCompression, row_group_size: usize
+    ) -> Result<Self, ParquetError> {
+        let props = WriterProperties::builder()
+            .set_compression(compression.into())
+            .set_max_row_group_size(row_group_size)
+            .build();
+        let schema = offers_batch(&[])?.schema();
+        Ok(Self {
+            file_path: file_path.to_path_buf(),
+            writer: ArrowWriter::try_new(file, schema, Some(props))?,
+            row_group_size,
+            offers: Vec::with_capacity(row_group_size),
+        })
+    }
+
+    pub(crate) fn file_path(&self) -> &Path {
+        &self.file_path
+    }
+
+    pub(crate) fn bytes_written(&self) -> u64 {
+        self.writer.bytes_written() as u64
+    }
+
+    /// Underlying writer, all the data is flushed into it after finishing
+    pub(crate) fn inner(&self) -> &W {
+        self.writer.inner()
+    }
+
+    pub(crate) fn write(&mut self, offer: &Offer) -> Result<(), ParquetError> {
+        self.offers.push(offer.clone());
+        if self.offers.len() >= self.row_group_size {
+            self.write_row_group()?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn finish(&mut self) -> Result<(), ParquetError> {
+        self.write_row_group()?;
+        self.writer.finish()?;
+        Ok(())
+    }
+
+    fn write_row_group(&mut self) -> Result<(), ParquetError> {
+        if self.offers.is_empty() {
+            return Ok(());
+        }
+        let batch = offers_batch(&self.offers)?;
+        self.writer.write(&batch)?;
+        self.writer.flush()?;
+        self.offers.clear();
+        Ok(())
+    }
+}
+
+fn offers_batch(offers: &[Offer]) -> Result<RecordBatch, ArrowError> {
+    RecordBatch::try_from_iter_with_nullable(vec!(
+        ("id", strings(offers, |o| &o.id), false),
+        ("type", strings(offers, |o| &o.r#type), false),
+        ("available", optional_bools(offers.iter().map(|o| o.available)), true),
+        ("name", strings(offers, |o| &o.name), false),
+        ("category_id", Arc::new(UInt64Array::from_iter_values(offers.iter().map(|o| o.category_id))) as ArrayRef, false),
+        ("category_raw_id", strings(offers, |o| &o.category_raw_id), false),
+        ("price", optional_floats(offers.iter().map(|o| o.price.as_ref().map(|p| p.price))), true),
+        ("price_from", optional_bools(offers.iter().map(|o| o.price.as_ref().map(|p| p.from))), true),
+        ("old_price", optional_floats(offers.iter().map(|o| o.old_price.as_ref().map(|p| p.price))), true),
+        ("old_price_from", optional_bools(offers.iter().map(|o| o.old_price.as_ref().map(|p| p.from))), true),
+        ("currency_id", strings(offers, |o| &o.currency_id), false),
+        ("url", strings(offers, |o| &o.url), false),
+        ("vendor", strings(offers, |o| &o.vendor), false),
+        ("vendor_code", strings(offers, |o| &o.vendor_code), false),
+        ("model", strings(offers, |o| &o.model), false),
+        ("type_prefix", strings(offers, |o| &o.type_prefix), false),
+        ("bid", uints(offers.iter().map(|o| o.bid)), false),
+        ("cbid", uints(offers.iter().map(|o| o.cbid)), false),
+        ("enable_auto_discounts", bools(offers.iter().map(|o| o.enable_auto_discounts)), false),
+        ("pictures", string_lists(offers, |o| &o.pictures), false),
+        ("delivery", optional_bools(offers.iter().map(|o| o.delivery)), true),
+        ("pickup", optional_bools(offers.iter().map(|o| o.pickup)), true),
+        ("delivery_options", delivery_options(offers, |o| &o.delivery_options)?, false),
+        ("pickup_options", delivery_options(offers, |o| &o.pickup_options)?, false),
+        ("store", optional_bools(offers.iter().map(|o| o.store)), true),
+        ("description", strings(offers, |o| &o.description), false),
+        ("description_text", strings(offers, |o| &o.description_text), false),
+        ("sales_notes", strings(offers, |o| &o.sales_notes), false),
+        ("min_quantity", optional_uints(offers.iter().map(|o| o.min_quantity)), true),
+        ("stock_quantity", optional_uints(offers.iter().map(|o| o.stock_quantity)), true),
+        ("manufacturer_warranty", bools(offers.iter().map(|o| o.manufacturer_warranty)), false),
+        ("country_of_origin", strings(offers, |o| &o.country_of_origin), false),
+        ("adult", bools(offers.iter().map(|o| o.adult)), false),
+        ("barcodes", string_lists(offers, |o| &o.barcodes), false),
+        ("keywords", string_lists(offers, |o| &o.keywords), false),
+        ("params", params(offers)?, false),
+        ("condition_type", optional_strings(offers.iter().map(|o| o.condition.as_ref().map(|c| c.r#type.as_str()))), true),
+        ("condition_reason", optional_strings(offers.iter().map(|o| o.condition.as_ref().map(|c| c.reason.as_str()))), true),
+        ("credit_template_id", strings(offers, |o| &o.credit_template_id), false),
+        ("expiry", strings(offers, |o| &o.expiry), false),
+        ("weight", Arc::new(Float32Array::from_iter_values(offers.iter().map(|o| o.weight))) as ArrayRef, false),
+        ("dimensions", strings(offers, |o| &o.dimensions), false),
+        ("downloadable", bools(offers.iter().map(|o| o.downloadable)), false),
+        ("age_unit", optional_strings(offers.iter().map(|o| o.age.as_ref().map(|a| a.unit.as_str()))), true),
+        ("age_value", optional_uints(offers.iter().map(|o| o.age.as_ref().map(|a| a.value))), true),
+        ("group_id", uints(offers.iter().map(|o| o.group_id)), false),
+        ("extra_fields", extra_fields(offers)?, false),
+        ("localized", localized(offers)?, false),
+    ))
+}
+
+fn strings<'a>(offers: &'a [Offer], f: impl Fn(&'a Offer) -> &'a String) -> ArrayRef {
+    string_values(offers.iter().map(f))
+}
+
+fn string_values<'a>(values: impl Iterator<Item = &'a String>) -> ArrayRef {
+    Arc::new(StringArray::from_iter_values(values))
+}
+
+fn optional_strings<'a>(values: impl Iterator<Item = Option<&'a str>>) -> ArrayRef {
+    Arc::new(values.collect::<StringArray>())
+}
+
+fn bools(values: impl Iterator<Item = bool>) -> ArrayRef {
+    Arc::new(values.map(Some).collect::<BooleanArray>())
+}
+
+fn optional_bools(values: impl Iterator<Item = Option<bool>>) -> ArrayRef {
+    Arc::new(values.collect::<BooleanArray>())
+}
+
+fn uints(values: impl Iterator<Item = u32>) -> ArrayRef {
+    Arc::new(UInt32Array::from_iter_values(values))
+}
+
+fn optional_uints(values: impl Iterator<Item = Option<u32>>) -> ArrayRef {
+    Arc::new(values.collect::<UInt32Array>())
+}
+
+fn optional_floats(values: impl Iterator<Item = Option<f32>>) -> ArrayRef {
+    Arc::new(values.collect::<Float32Array>())
+}
+
+fn string_lists<'a>(offers: &'a [Offer], f: impl Fn(&'a Offer) -> &'a Vec<String>) -> ArrayRef {
+    let mut builder = ListBuilder::new(StringBuilder::new())
+        .with_field(Field::new("item", DataType::Utf8, false));
+    for offer in offers {
+        builder.append_value(f(offer).iter().map(Some));
+    }
+    Arc::new(builder.finish())
+}
+
+fn list(lengths: impl Iterator<Item = usize>, values: StructArray) -> Result<ArrayRef, ArrowError> {
+    let field = Field::new("item", values.data_type().clone(), false);
+    Ok(Arc::new(ListArray::try_new(
+        Arc::new(field), OffsetBuffer::from_lengths(lengths), Arc::new(values), None
+    )?))
+}
+
+fn struct_array(columns: Vec<(&str, ArrayRef, bool)>) -> Result<StructArray, ArrowError> {
+    let (fields, arrays): (Vec<_>, Vec<_>) = columns.into_iter()
+        .map(|(name, array, nullable)| (Field::new(name, array.data_type().clone(), nullable), array))
+        .unzip();
+    StructArray::try_new(Fields::from(fields), arrays, None)
+}
+
+fn delivery_options(
+    offers: &[Offer], f: impl Fn(&Offer) -> &Vec<DeliveryOption>
+) -> Result<ArrayRef, ArrowError> {
+    let options = offers.iter().flat_map(&f).collect::<Vec<_>>();
+    let values = struct_array(vec!(
+        ("cost", uints(options.iter().map(|o| o.cost)), false),
+        ("days", string_values(options.iter().map(|o| &o.days)), false),
+        ("order_before", optional_uints(options.iter().map(|o| o.order_before)), true),
+    ))?;
+    list(offers.iter().map(|o| f(o).len()), values)
+}
+
+fn params(offers: &[Offer]) -> Result<ArrayRef, ArrowError> {
+    let params = offers.iter().flat_map(|o| &o.params).collect::<Vec<_>>();
+    let values = struct_array(vec!(
+        ("name", string_values(params.iter().map(|p| &p.name)), false),
+        ("unit", string_values(params.iter().map(|p| &p.unit)), false),
+        ("value", string_values(params.iter().map(|p| &p.value)), false),
+        ("id", string_values(params.iter().map(|p| &p.id)), false),
+        ("value_id", string_values(params.iter().map(|p| &p.value_id)), false),
+    ))?;
+    list(offers.iter().map(|o| o.params.len()), values)
+}
+
+fn extra_fields(offers: &[Offer]) -> Result<ArrayRef, ArrowError> {
+    let mut keys = StringBuilder::new();
+    let mut values = ListBuilder::new(StringBuilder::new())
+        .with_field(Field::new("item", DataType::Utf8, false));
+    for offer in offers {
+        // keeps an order of entries stable
+        let mut fields = offer.extra_fields.iter().collect::<Vec<_>>();
+        fields.sort_unstable_by_key(|(name, _)| name.as_str());
+        for (name, field) in fields {
+            keys.append_value(name);
+            values.append_value(field.values.iter().map(Some));
+        }
+    }
+    map(
+        offers.iter().map(|o| o.extra_fields.len()),
+        Arc::new(keys.finish()),
+        Arc::new(values.finish()),
+    )
+}
+
+fn localized(offers: &[Offer]) -> Result<ArrayRef, ArrowError> {
+    let texts = offers.iter()
+        .flat_map(|offer| {
+            // keeps an order of entries stable
+            let mut texts = offer.localized.iter().collect::<Vec<_>>();
+            texts.sort_unstable_by_key(|(lang, _)| lang.as_str());
+            texts
+        })
+        .collect::<Vec<_>>();
+    let values = struct_array(vec!(
+        ("name", string_values(texts.iter().map(|(_, text)| &text.name)), false),
+        ("description", string_values(texts.iter().map(|(_, text)| &text.description)), false),
+        ("description_text", string_values(texts.iter().map(|(_, text)| &text.description_text)), false),
+    ))?;
+    map(
+        offers.iter().map(|o| o.localized.len()),
+        string_values(texts.iter().map(|(lang, _)| *lang)),
+        Arc::new(values),
+    )
+}
+
+fn map(lengths: impl Iterator<Item = usize>, keys: ArrayRef, values: ArrayRef) -> Result<ArrayRef, ArrowError> {
+    let entries = struct_array(vec!(
+        ("key", keys, false),
+        ("value", values, false),
+    ))?;
+    let field = Field::new("entries", entries.data_type().clone(), false);
+    Ok(Arc::new(MapArray::try_new(
+        Arc::new(field),
+        OffsetBuffer::from_lengths(lengths),
+        entries,
+        None,
+        false,
+    )?))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs::{self, File};
+
+    use arrow_array::{Array, ListArray, MapArray, StringArray, StructArray, UInt64Array};
+    use arrow_array::cast::AsArray;
+
+    use failure::Error;
+
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    use crate::market_xml::{LocalizedText, Offer, OfferExtraField, Param, Price};
+    use super::{ParquetCompression, ParquetWriter};
+
+    #[test]
+    fn test_writing_parquet() -> Result<(), Error> {
+        let file_path = std::env::temp_dir().join(format!("market-xml-{}.parquet", std::process::id()));
+        let offers = vec!(
+            Offer {
+                id: "1".to_string(),
+                available: Some(true),
+                category_id: 10,
+                pictures: vec!("http://example.com/1.jpg".to_string()),
+                params: vec!(Param { name: "Цвет".to_string(), value: "белый".to_string(), ..Default::default() }),
+                extra_fields: HashMap::from([
+                    ("supplier".to_string(), OfferExtraField { values: vec!("ACME".to_string()) }),
+                ]),
+                old_price: Some(Price { price: 100.0, from: true }),
+                localized: HashMap::from([
+                    ("uk".to_string(), LocalizedText { name: "Каструля".to_string(), ..Default::default() }),
+                ]),
+                ..Default::default()
+            },
+            Offer { id: "2".to_string(), ..Default::default() },
+            Offer { id: "3".to_string(), category_id: 12, ..Default::default() },
+        );
+
+        let mut writer = ParquetWriter::new(File::create(&file_path)?, &file_path, ParquetCompression::Zstd, 2)?;
+        for offer in &offers {
+            writer.write(offer)?;
+        }
+        writer.finish()?;
+
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(&file_path)?)?;
+        assert_eq!(builder.metadata().num_row_groups(), 2);
+        let batches = builder.build()?.collect::<Result<Vec<_>, _>>()?;
+        fs::remove_file(&file_path)?;
+        let batch = &batches[0];
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 3);
+
+        let ids = batch.column_by_name("id").unwrap().as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(ids.value(1), "2");
+        let available = batch.column_by_name("available").unwrap().as_boolean();
+        assert!(available.value(0));
+        assert!(available.is_null(1));
+        let category_ids = batch.column_by_name("category_id").unwrap().as_any().downcast_ref::<UInt64Array>().unwrap();
+        assert_eq!(category_ids.value(0), 10);
+        let pictures = batch.column_by_name("pictures").unwrap().as_any().downcast_ref::<ListArray>().unwrap();
+        assert_eq!(pictures.value(0).as_string::<i32>().value(0), "http://example.com/1.jpg");
+        assert_eq!(pictures.value(1).len(), 0);
+        let params = batch.column_by_name("params").unwrap().as_any().downcast_ref::<ListArray>().unwrap();
+        let param = params.value(0);
+        let param = param.as_any().downcast_ref::<StructArray>().unwrap();
+        assert_eq!(param.column_by_name("name").unwrap().as_string::<i32>().value(0), "Цвет");
+        assert_eq!(param.column_by_name("value").unwrap().as_string::<i32>().value(0), "белый");
+        let extra_fields = batch.column_by_name("extra_fields").unwrap().as_any().downcast_ref::<MapArray>().unwrap();
+        assert_eq!(extra_fields.keys().as_string::<i32>().value(0), "supplier");
+        assert_eq!(extra_fields.value_length(1), 0);
+        assert!(batch.column_by_name("old_price_from").unwrap().as_boolean().value(0));
+        let localized = batch.column_by_name("localized").unwrap().as_any().downcast_ref::<MapArray>().unwrap();
+        assert_eq!(localized.keys().as_string::<i32>().value(0), "uk");
+        let texts = localized.values().as_any().downcast_ref::<StructArray>().unwrap();
+        assert_eq!(texts.column_by_name("name").unwrap().as_string::<i32>().value(0), "Каструля");
+        assert_eq!(localized.value_length(1), 0);
+
+        Ok(())
+    }
+}
@@ -4,15 +4,18 @@ use quick_xml::events::attributes::Attributes;
 
 use snafu::{ResultExt, Snafu};
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::collections::hash_map::Entry;
 use std::io::prelude::BufRead;
 use std::fmt::Display;
+use std::mem;
 use std::str::{self, FromStr};
 
+use crate::html;
+use crate::lenient;
 use crate::market_xml::{
-    Category, Condition, Currency, DeliveryOption, Offer, OfferExtraField, Param, Price, Shop,
-    YmlCatalog,
+    Category, Condition, Currency, DeliveryOption, LocalizedText, Offer, OfferExtraField, Param,
+    Price, Shop, YmlCatalog,
 };
 
 #[derive(Debug, Snafu)]
@@ -42,7 +45,12 @@ pub(crate) enum MarketXmlError {
         line: usize,
         column: usize,
         value: String,
-    }
+    },
+    #[snafu(display("Csv error: {}", source))]
+    Csv {
+        source: csv::Error,
+        line: usize,
+    },
 }
 
 impl MarketXmlError {
@@ -54,6 +62,7 @@ impl MarketXmlError {
             UnexpectedTag { line, .. } => line,
             InvalidUtf8 { line, .. } => line,
             Validation { line, .. } => line,
+            Csv { line, .. } => line,
         }
     }
 
@@ -65,6 +74,7 @@ impl MarketXmlError {
             UnexpectedTag { column, .. } => column,
             InvalidUtf8 { column, .. } => column,
             Validation { column, .. } => column,
+            Csv { .. } => 0,
         }
     }
 
@@ -75,31 +85,270 @@ impl MarketXmlError {
             Xml { .. } => None,
             UnexpectedTag { tag, .. } => Some(tag),
             InvalidUtf8 { .. } => None,
-            Validation { value, .. } => Some(value)
+            Validation { value, .. } => Some(value),
+            Csv { .. } => None,
+        }
+    }
+}
+
+/// Non-fatal problem found while parsing, the offer is still emitted
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct MarketXmlWarning {
+    pub(crate) msg: String,
+    pub(crate) line: usize,
+    pub(crate) column: usize,
+    pub(crate) value: String,
+}
+
+/// How whitespaces of a text field value are treated
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum WhitespacePolicy {
+    /// Keep the value as is
+    Preserve,
+    /// Strip leading and trailing whitespaces
+    Trim,
+    /// Strip leading and trailing whitespaces and replace inner ones with a single space
+    Collapse,
+}
+
+impl WhitespacePolicy {
+    pub(crate) fn apply(self, s: String) -> String {
+        match self {
+            WhitespacePolicy::Preserve => s,
+            WhitespacePolicy::Trim => {
+                let trimmed = s.trim();
+                if trimmed.len() == s.len() {
+                    s
+                } else {
+                    trimmed.to_string()
+                }
+            }
+            WhitespacePolicy::Collapse => {
+                s.split_whitespace().collect::<Vec<_>>().join(" ")
+            }
+        }
+    }
+}
+
+impl FromStr for WhitespacePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "preserve" => Ok(WhitespacePolicy::Preserve),
+            "trim" => Ok(WhitespacePolicy::Trim),
+            "collapse" => Ok(WhitespacePolicy::Collapse),
+            _ => Err(format!("unknown whitespace policy: {}", s)),
+        }
+    }
+}
+
+/// Flavour of a YML-like feed
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum Dialect {
+    Yml,
+    Prom,
+    Rozetka,
+    Hotline,
+}
+
+impl FromStr for Dialect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "yml" => Ok(Dialect::Yml),
+            "prom" => Ok(Dialect::Prom),
+            "rozetka" => Ok(Dialect::Rozetka),
+            "hotline" => Ok(Dialect::Hotline),
+            _ => Err(format!("unknown dialect: {}", s)),
         }
     }
 }
 
 pub(crate) struct MarketXmlConfig {
+    catalog_tags: HashSet<Vec<u8>>,
+    // empty when shop fields are placed right into a catalog element
+    shop_tags: HashSet<Vec<u8>>,
+    offers_tags: HashSet<Vec<u8>>,
     offer_tags: HashSet<Vec<u8>>,
+    // alias -> name of a field from the yml specification
+    shop_field_aliases: HashMap<Vec<u8>, Vec<u8>>,
+    offer_field_aliases: HashMap<Vec<u8>, Vec<u8>>,
+    // offer id is a child element instead of an attribute
+    offer_id_tag: Option<Vec<u8>>,
+    // elements with a text availability: `В наличии`, `out_of_stock` and so on
+    presence_tags: HashSet<Vec<u8>>,
+    // category id, parent id and name are child elements instead of attributes and text
+    category_child_elements: bool,
+    // category ids are not necessarily numbers
+    string_category_ids: bool,
+    // `stock_quantity` and comma separated `keywords` are offer fields instead of extra ones
+    stock_and_keywords_fields: bool,
+    /// Applied to a whole text value after all its text and cdata chunks are joined
+    pub(crate) whitespace: WhitespacePolicy,
+    /// Leave only whitelisted tags in a description and extract its plain text
+    pub(crate) sanitize_description: bool,
+    /// Maximum length of a description plain text, longer descriptions are truncated
+    pub(crate) description_max_length: Option<usize>,
+    /// Offer fields whose child markup is kept in the value instead of failing
+    pub(crate) inner_xml_fields: HashSet<Vec<u8>>,
+    /// Accept localized booleans and numbers with decimal commas and group separators
+    pub(crate) lenient: bool,
+    /// Maps a suffix of localized fields to a language: `ua` -> `uk` for `name_ua`
+    pub(crate) localized_suffixes: HashMap<Vec<u8>, String>,
+    /// Attribute holding a language of a field: `lang` for `<name lang="en">`
+    pub(crate) lang_attr: Option<Vec<u8>>,
+    /// Fields in this language are stored as ordinary ones instead of localized
+    pub(crate) default_lang: Option<String>,
+    // namespaces of yml elements besides the namespace of a catalog element
+    pub(crate) yml_namespaces: HashSet<Vec<u8>>,
 }
 
 impl Default for MarketXmlConfig {
     fn default() -> Self {
-        let mut offer_tags = HashSet::new();
-        offer_tags.insert(b"offer".to_vec());
-        Self {
-            offer_tags,
+        Self::for_dialect(Dialect::Yml)
+    }
+}
+
+impl MarketXmlConfig {
+    pub(crate) fn for_dialect(dialect: Dialect) -> Self {
+        let mut inner_xml_fields = HashSet::new();
+        inner_xml_fields.insert(b"description".to_vec());
+        inner_xml_fields.insert(b"sales_notes".to_vec());
+        let mut config = Self {
+            catalog_tags: tag_set(&["yml_catalog"]),
+            shop_tags: tag_set(&["shop"]),
+            offers_tags: tag_set(&["offers"]),
+            offer_tags: tag_set(&["offer"]),
+            shop_field_aliases: HashMap::new(),
+            offer_field_aliases: HashMap::new(),
+            offer_id_tag: None,
+            presence_tags: HashSet::new(),
+            category_child_elements: false,
+            string_category_ids: false,
+            stock_and_keywords_fields: false,
+            whitespace: WhitespacePolicy::Trim,
+            sanitize_description: false,
+            description_max_length: None,
+            inner_xml_fields,
+            lenient: false,
+            localized_suffixes: HashMap::new(),
+            lang_attr: None,
+            default_lang: None,
+            yml_namespaces: HashSet::new(),
+        };
+
+        match dialect {
+            Dialect::Yml => {}
+            Dialect::Prom => {
+                config.offers_tags.insert(b"items".to_vec());
+                config.offer_tags.insert(b"item".to_vec());
+                config.add_shop_field_aliases(&[("catalog", "categories")]);
+                config.add_offer_field_aliases(&[
+                    ("price_old", "oldprice"),
+                    ("priceuah", "price"),
+                    ("image", "picture"),
+                    ("quantity_in_stock", "stock_quantity"),
+                ]);
+                config.presence_tags = tag_set(&["presence", "available"]);
+                config.string_category_ids = true;
+                config.stock_and_keywords_fields = true;
+                config.localized_suffixes.insert(b"ua".to_vec(), "uk".to_string());
+            }
+            Dialect::Rozetka => {
+                config.add_offer_field_aliases(&[("price_old", "oldprice")]);
+                config.presence_tags = tag_set(&["presence"]);
+                config.stock_and_keywords_fields = true;
+                config.localized_suffixes.insert(b"ua".to_vec(), "uk".to_string());
+            }
+            Dialect::Hotline => {
+                config.catalog_tags = tag_set(&["price"]);
+                config.shop_tags.clear();
+                config.offers_tags = tag_set(&["items"]);
+                config.offer_tags = tag_set(&["item"]);
+                config.add_shop_field_aliases(&[("firmName", "name")]);
+                config.add_offer_field_aliases(&[
+                    ("code", "vendorCode"),
+                    ("image", "picture"),
+                    ("priceRUAH", "price"),
+                    ("price_old", "oldprice"),
+                ]);
+                config.offer_id_tag = Some(b"id".to_vec());
+                config.presence_tags = tag_set(&["stock", "presence"]);
+                config.category_child_elements = true;
+                config.string_category_ids = true;
+                config.stock_and_keywords_fields = true;
+                config.localized_suffixes.insert(b"ua".to_vec(), "uk".to_string());
+            }
+        }
+        config
+    }
+
+    fn add_shop_field_aliases(&mut self, aliases: &[(&str, &str)]) {
+        for (alias, field) in aliases {
+            self.shop_field_aliases.insert(alias.as_bytes().to_vec(), field.as_bytes().to_vec());
+        }
+    }
+
+    fn add_offer_field_aliases(&mut self, aliases: &[(&str, &str)]) {
+        for (alias, field) in aliases {
+            self.offer_field_aliases.insert(alias.as_bytes().to_vec(), field.as_bytes().to_vec());
         }
     }
 }
 
+fn tag_set(tags: &[&str]) -> HashSet<Vec<u8>> {
+    tags.iter().map(|tag| tag.as_bytes().to_vec()).collect()
+}
+
 pub(crate) struct MarketXmlParser<B: BufRead> {
     config: MarketXmlConfig,
     xml_reader: XmlReader<B, PositionWithLine>,
     buf: Vec<u8>,
+    ns: Namespaces,
     state: State,
     yml_catalog: YmlCatalog,
+    warnings: Vec<MarketXmlWarning>,
+}
+
+/// Resolves namespaces of elements. Elements without a namespace
+/// and elements of the known namespaces are treated as yml ones.
+#[derive(Default)]
+pub(crate) struct Namespaces {
+    buf: Vec<u8>,
+    known: HashSet<Vec<u8>>,
+    // namespace of the last read element when it is a foreign one
+    foreign: Option<Vec<u8>>,
+    // number of currently open elements
+    depth: usize,
+}
+
+impl Namespaces {
+    pub(crate) fn new(known: HashSet<Vec<u8>>) -> Self {
+        Self { known, ..Default::default() }
+    }
+
+    fn is_foreign(&self) -> bool {
+        self.foreign.is_some()
+    }
+
+    /// Namespace of the last read element unless it is a known one
+    pub(crate) fn foreign(&self) -> Option<&[u8]> {
+        self.foreign.as_deref()
+    }
+
+    /// Number of currently open elements including the last started one
+    pub(crate) fn depth(&self) -> usize {
+        self.depth
+    }
+
+    // Makes the namespace of the last read element a known one
+    fn adopt_foreign(&mut self) {
+        if let Some(ns) = self.foreign.take() {
+            self.known.insert(ns);
+        }
+    }
 }
 
 #[derive(PartialEq, Clone, Copy, Debug)]
@@ -111,6 +360,12 @@ enum State {
     End,
 }
 
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum LocalizedField {
+    Name,
+    Description,
+}
+
 #[derive(PartialEq, Debug)]
 pub(crate) enum ParsedItem {
     Offer(Offer),
@@ -118,57 +373,22 @@ pub(crate) enum ParsedItem {
     Eof,
 }
 
-impl<B: BufRead> MarketXmlParser<B> {
-    pub(crate) fn new(config: MarketXmlConfig, reader: B) -> Self {
-        let mut xml_reader = XmlReader::from_reader_with_position_tracker(
-            reader, PositionWithLine::default()
-        );
-        xml_reader.trim_text(true);
-        Self {
-            config,
-            xml_reader,
-            buf: vec!(),
-            state: State::Begin,
-            yml_catalog: YmlCatalog::default(),
-        }
-    }
-
-    fn cur_line(&self) -> usize {
-        self.xml_reader.position().line()
-    }
-
-    fn cur_column(&self) -> usize {
-        self.xml_reader.position().column()
-    }
+/// Parser of an input feed that produces a stream of parsed items
+pub(crate) trait FeedParser {
+    fn next_item(&mut self) -> Result<ParsedItem, MarketXmlError>;
 
-    pub(crate) fn buffer_position(&self) -> usize {
-        self.xml_reader.buffer_position()
-    }
+    /// Returns warnings collected since the previous call
+    fn take_warnings(&mut self) -> Vec<MarketXmlWarning>;
 
-    fn xml_err_ctx(&self) -> XmlSnafu<usize, usize> {
-        XmlSnafu {
-            line: self.cur_line(),
-            column: self.cur_column(),
-        }
-    }
+    /// Checks if the last error was found in an offer, such an offer is skipped
+    /// while other errors belong to the catalog
+    fn in_offer(&self) -> bool;
 
-    fn next_event(&mut self) -> Result<Event, MarketXmlError> {
-        let line = self.cur_line();
-        let column = self.cur_column();
-        let event_res = self.xml_reader.read_event(&mut self.buf);
-        match event_res {
-            Ok(event) => Ok(event),
-            Err(error) => {
-                Err(MarketXmlError::Xml {
-                    source: error,
-                    line,
-                    column,
-                })
-            }
-        }
-    }
+    fn buffer_position(&self) -> usize;
+}
 
-    pub(crate) fn next_item(&mut self) -> Result<ParsedItem, MarketXmlError> {
+impl<B: BufRead> FeedParser for MarketXmlParser<B> {
+    fn next_item(&mut self) -> Result<ParsedItem, MarketXmlError> {
         loop {
             match self.state {
                 State::Begin => {
@@ -182,6 +402,10 @@ impl<B: BufRead> MarketXmlParser<B> {
                 }
                 State::Shop => {
                     self.state = self.parse_shop()?;
+                    // shop fields can be placed right into a catalog element
+                    if self.state == State::End {
+                        return Ok(ParsedItem::YmlCatalog(self.yml_catalog.clone()));
+                    }
                 }
                 State::Offers => {
                     match self.parse_offers()? {
@@ -200,13 +424,79 @@ impl<B: BufRead> MarketXmlParser<B> {
         }
     }
 
+    fn take_warnings(&mut self) -> Vec<MarketXmlWarning> {
+        mem::take(&mut self.warnings)
+    }
+
+    // an error stops parsing of an offer without leaving the state
+    fn in_offer(&self) -> bool {
+        self.state == State::Offers
+    }
+
+    fn buffer_position(&self) -> usize {
+        self.xml_reader.buffer_position()
+    }
+}
+
+impl<B: BufRead> MarketXmlParser<B> {
+    pub(crate) fn new(config: MarketXmlConfig, reader: B) -> Self {
+        let mut xml_reader = XmlReader::from_reader_with_position_tracker(
+            reader, PositionWithLine::default()
+        );
+        // whitespaces are handled by the whitespace policy
+        xml_reader.trim_text(false);
+        let ns = Namespaces::new(config.yml_namespaces.clone());
+        Self {
+            config,
+            xml_reader,
+            buf: vec!(),
+            ns,
+            state: State::Begin,
+            yml_catalog: YmlCatalog::default(),
+            warnings: vec!(),
+        }
+    }
+
+    fn cur_line(&self) -> usize {
+        self.xml_reader.position().line()
+    }
+
+    fn cur_column(&self) -> usize {
+        self.xml_reader.position().column()
+    }
+
+    fn warn(&mut self, msg: String, value: String) {
+        self.warnings.push(MarketXmlWarning {
+            msg,
+            line: self.cur_line(),
+            column: self.cur_column(),
+            value,
+        });
+    }
+
+    fn xml_err_ctx(&self) -> XmlSnafu<usize, usize> {
+        XmlSnafu {
+            line: self.cur_line(),
+            column: self.cur_column(),
+        }
+    }
+
+    fn next_event(&mut self) -> Result<Event, MarketXmlError> {
+        read_event(&mut self.xml_reader, &mut self.buf, &mut self.ns)
+    }
+
     fn begin(&mut self) -> Result<State, MarketXmlError> {
         loop {
-            match self.next_event()? {
+            match read_event(&mut self.xml_reader, &mut self.buf, &mut self.ns)? {
                 Event::Start(tag) => {
-                    if tag.name() == b"yml_catalog" {
+                    if self.config.catalog_tags.contains(tag.local_name()) {
+                        // a namespace of the catalog is a yml one whatever it is
+                        self.ns.adopt_foreign();
                         let tag = tag.to_owned();
                         self.parse_yml_catalog_attrs(&mut tag.attributes())?;
+                        if self.config.shop_tags.is_empty() {
+                            return Ok(State::Shop);
+                        }
                         return Ok(State::YmlCatalog);
                     }
                     return Err(MarketXmlError::UnexpectedTag {
@@ -226,14 +516,14 @@ impl<B: BufRead> MarketXmlParser<B> {
 
     fn parse_yml_catalog(&mut self) -> Result<State, MarketXmlError> {
         loop {
-            match self.next_event()? {
+            match read_event(&mut self.xml_reader, &mut self.buf, &mut self.ns)? {
                 Event::Start(tag) => {
-                    if tag.name() == b"shop" {
+                    if !self.ns.is_foreign() && self.config.shop_tags.contains(tag.local_name()) {
                         return Ok(State::Shop);
                     }
                 }
                 Event::End(tag) => {
-                    if tag.name() == b"yml_catalog" {
+                    if !self.ns.is_foreign() && self.config.catalog_tags.contains(tag.local_name()) {
                         return Ok(State::End);
                     }
                 }
@@ -261,19 +551,39 @@ impl<B: BufRead> MarketXmlParser<B> {
 
     fn parse_shop(&mut self) -> Result<State, MarketXmlError> {
         loop {
-            match self.next_event()? {
-                Event::Start(tag) |
+            match read_event(&mut self.xml_reader, &mut self.buf, &mut self.ns)? {
+                Event::Start(tag) => {
+                    if self.ns.is_foreign() {
+                        // shop extensions are not supported
+                        self.read_inner_xml()?;
+                        continue;
+                    }
+                    if self.config.offers_tags.contains(tag.local_name()) {
+                        return Ok(State::Offers);
+                    }
+                    let tag = tag.to_owned();
+                    self.parse_shop_field(tag)?;
+                }
                 Event::Empty(tag) => {
-                    if tag.name() == b"offers" {
+                    if self.ns.is_foreign() {
+                        continue;
+                    }
+                    if self.config.offers_tags.contains(tag.local_name()) {
                         return Ok(State::Offers);
                     }
                     let tag = tag.to_owned();
                     self.parse_shop_field(tag)?;
                 }
                 Event::End(tag) => {
-                    if tag.name() == b"shop" {
+                    if self.ns.is_foreign() {
+                        continue;
+                    }
+                    if self.config.shop_tags.contains(tag.local_name()) {
                         return Ok(State::YmlCatalog);
                     }
+                    if self.config.catalog_tags.contains(tag.local_name()) {
+                        return Ok(State::End);
+                    }
                 }
                 Event::Eof => {
                     return Err(XmlError::UnexpectedEof("shop".to_string()))
@@ -288,7 +598,12 @@ impl<B: BufRead> MarketXmlParser<B> {
         fn get_shop(yml_catalog: &mut YmlCatalog) -> &mut Shop {
             yml_catalog.shop.get_or_insert(Shop::default())
         }
-        match tag.name() {
+        let aliased = self.config.shop_field_aliases.get(tag.local_name()).cloned();
+        let field_name = aliased.as_deref().unwrap_or_else(|| tag.local_name());
+        match field_name {
+            b"date" if self.config.shop_tags.is_empty() => {
+                self.yml_catalog.date = self.read_text()?;
+            }
             b"name" => {
                 get_shop(&mut self.yml_catalog).name = self.read_text()?;
             }
@@ -302,7 +617,7 @@ impl<B: BufRead> MarketXmlParser<B> {
                 get_shop(&mut self.yml_catalog).currencies = self.parse_currencies()?;
             }
             b"categories" => {
-                get_shop(&mut self.yml_catalog).categories = self.parse_categories()?;
+                get_shop(&mut self.yml_catalog).categories = self.parse_categories(tag.local_name())?;
             }
             b"delivery-options" => {
                 get_shop(&mut self.yml_catalog).delivery_options = self.parse_delivery_options()?;
@@ -318,13 +633,13 @@ impl<B: BufRead> MarketXmlParser<B> {
             match self.next_event()? {
                 Event::Start(tag) |
                 Event::Empty(tag) => {
-                    if tag.name() == b"currency" {
+                    if tag.local_name() == b"currency" {
                         let tag = tag.into_owned();
                         currencies.push(self.parse_currency(&mut tag.attributes())?);
                     }
                 }
                 Event::End(tag) => {
-                    if tag.name() == b"currencies" {
+                    if tag.local_name() == b"currencies" {
                         return Ok(currencies);
                     }
                 }
@@ -358,19 +673,24 @@ impl<B: BufRead> MarketXmlParser<B> {
         Ok(currency)
     }
 
-    fn parse_categories(&mut self) -> Result<Vec<Category>, MarketXmlError> {
+    fn parse_categories(&mut self, end_tag: &[u8]) -> Result<Vec<Category>, MarketXmlError> {
         let mut categories = vec!();
         loop {
             match self.next_event()? {
-                Event::Start(tag) |
+                Event::Start(tag) => {
+                    if tag.local_name() == b"category" {
+                        let tag = tag.into_owned();
+                        categories.push(self.parse_category(&mut tag.attributes(), true)?);
+                    }
+                }
                 Event::Empty(tag) => {
-                    if tag.name() == b"category" {
+                    if tag.local_name() == b"category" {
                         let tag = tag.into_owned();
-                        categories.push(self.parse_category(&mut tag.attributes())?);
+                        categories.push(self.parse_category(&mut tag.attributes(), false)?);
                     }
                 }
                 Event::End(tag) => {
-                    if tag.name() == b"categories" {
+                    if tag.local_name() == end_tag {
                         return Ok(categories);
                     }
                 }
@@ -383,35 +703,92 @@ impl<B: BufRead> MarketXmlParser<B> {
         }
     }
 
-    fn parse_category(&mut self, attrs: &mut Attributes) -> Result<Category, MarketXmlError> {
+    fn parse_category(
+        &mut self, attrs: &mut Attributes, has_content: bool
+    ) -> Result<Category, MarketXmlError> {
         let mut category = Category::default();
         for attr_res in attrs {
             let attr = attr_res.context(self.xml_err_ctx())?;
             match attr.key {
                 b"id" => {
-                    category.id = self.parse_value(&attr.value)?;
+                    let (id, raw_id) = self.parse_category_id(&attr.value)?;
+                    category.id = id;
+                    category.raw_id = raw_id;
                 }
-                b"parentId" => {
-                    category.parent_id = self.parse_value(&attr.value)?;
+                b"parentId" | b"parentID" => {
+                    let (parent_id, raw_parent_id) = self.parse_category_id(&attr.value)?;
+                    category.parent_id = parent_id;
+                    category.raw_parent_id = raw_parent_id;
                 }
                 _ => {}
             }
         }
-        category.name = self.read_text()?;
+        if !has_content {
+            return Ok(category);
+        }
+        if self.config.category_child_elements {
+            self.parse_category_elements(&mut category)?;
+        } else {
+            category.name = self.read_text()?;
+        }
         Ok(category)
     }
 
-    fn parse_offers(&mut self) -> Result<Option<Offer>, MarketXmlError> {
+    fn parse_category_elements(&mut self, category: &mut Category) -> Result<(), MarketXmlError> {
         loop {
             match self.next_event()? {
                 Event::Start(tag) => {
-                    if tag.name() == b"offer" {
+                    let tag = tag.into_owned();
+                    let value = self.read_text()?;
+                    match tag.local_name() {
+                        b"id" => {
+                            let (id, raw_id) = self.parse_category_id(value.as_bytes())?;
+                            category.id = id;
+                            category.raw_id = raw_id;
+                        }
+                        b"parentId" | b"parentID" => {
+                            let (parent_id, raw_parent_id) = self.parse_category_id(value.as_bytes())?;
+                            category.parent_id = parent_id;
+                            category.raw_parent_id = raw_parent_id;
+                        }
+                        b"name" => {
+                            category.name = value;
+                        }
+                        _ => {}
+                    }
+                }
+                Event::End(_) => break,
+                Event::Eof => {
+                    return Err(XmlError::UnexpectedEof("Category".to_string()))
+                        .context(self.xml_err_ctx());
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // Returns a numeric id and a raw one that is filled only when string ids are allowed
+    fn parse_category_id(&mut self, v: &[u8]) -> Result<(u64, String), MarketXmlError> {
+        if self.config.string_category_ids {
+            let raw_id = self.decode_value(v)?.trim().to_string();
+            Ok((raw_id.parse().unwrap_or(0), raw_id))
+        } else {
+            Ok((self.parse_value(v)?, String::new()))
+        }
+    }
+
+    fn parse_offers(&mut self) -> Result<Option<Offer>, MarketXmlError> {
+        loop {
+            match read_event(&mut self.xml_reader, &mut self.buf, &mut self.ns)? {
+                Event::Start(tag) => {
+                    if !self.ns.is_foreign() && self.config.offer_tags.contains(tag.local_name()) {
                         let tag = tag.to_owned();
                         return Ok(Some(self.parse_offer(&mut tag.attributes())?));
                     }
                 }
                 Event::End(tag) => {
-                    if tag.name() == b"offers" {
+                    if !self.ns.is_foreign() && self.config.offers_tags.contains(tag.local_name()) {
                         return Ok(None)
                     }
                 }
@@ -434,7 +811,7 @@ impl<B: BufRead> MarketXmlParser<B> {
     }
 
     fn parse_offer_attributes(
-        &self, attrs: &mut Attributes, offer: &mut Offer
+        &mut self, attrs: &mut Attributes, offer: &mut Offer
     ) -> Result<(), MarketXmlError> {
         for attr_res in attrs {
             let attr = attr_res.context(self.xml_err_ctx())?;
@@ -456,12 +833,21 @@ impl<B: BufRead> MarketXmlParser<B> {
                         b"false" | b"0" => Some(false),
                         b"true" | b"1" => Some(true),
                         b"" => None,
-                        _ => return Err(MarketXmlError::Validation {
-                            msg: "parse bool".to_string(),
-                            line: self.cur_line(),
-                            column: self.cur_column(),
-                            value: self.decode_value(&attr.value)?.to_string(),
-                        }),
+                        _ => {
+                            let value = self.decode_value(&attr.value)?.to_string();
+                            match lenient::parse_bool(&value).filter(|_| self.config.lenient) {
+                                Some(available) => {
+                                    self.warn_coerced(Some(value));
+                                    Some(available)
+                                }
+                                None => return Err(MarketXmlError::Validation {
+                                    msg: "parse bool".to_string(),
+                                    line: self.cur_line(),
+                                    column: self.cur_column(),
+                                    value,
+                                }),
+                            }
+                        }
                     }
                 }
                 _ => {}
@@ -472,15 +858,24 @@ impl<B: BufRead> MarketXmlParser<B> {
 
     fn parse_offer_fields(&mut self, offer: &mut Offer) -> Result<(), MarketXmlError> {
         loop {
-            let event = self.next_event()?;
+            let event = read_event(&mut self.xml_reader, &mut self.buf, &mut self.ns)?;
             match event {
-                Event::Start(tag) |
+                Event::Start(tag) => {
+                    let tag = tag.into_owned();
+                    match self.ns.foreign.take() {
+                        Some(ns) => self.parse_foreign_field(&ns, &tag, true, offer)?,
+                        None => self.parse_offer_field(tag, offer)?,
+                    }
+                }
                 Event::Empty(tag) => {
                     let tag = tag.into_owned();
-                    self.parse_offer_field(tag, offer)?;
+                    match self.ns.foreign.take() {
+                        Some(ns) => self.parse_foreign_field(&ns, &tag, false, offer)?,
+                        None => self.parse_offer_field(tag, offer)?,
+                    }
                 }
                 Event::End(tag) => {
-                    if tag.name() == b"offer" {
+                    if self.config.offer_tags.contains(tag.local_name()) {
                         break;
                     }
                 }
@@ -495,7 +890,20 @@ impl<B: BufRead> MarketXmlParser<B> {
     }
 
     fn parse_offer_field(&mut self, tag: BytesStart, offer: &mut Offer) -> Result<(), MarketXmlError> {
-        match tag.name() {
+        let aliased = self.config.offer_field_aliases.get(tag.local_name()).cloned();
+        let field_name = aliased.as_deref().unwrap_or_else(|| tag.local_name());
+        if self.config.offer_id_tag.as_deref() == Some(field_name) {
+            offer.id = self.read_text()?;
+            return Ok(());
+        }
+        if self.config.presence_tags.contains(field_name) {
+            offer.available = self.read_presence()?;
+            return Ok(());
+        }
+        if let Some((field, lang)) = self.localized_field(&tag)? {
+            return self.parse_localized_field(field, lang, offer);
+        }
+        match field_name {
             b"name" => {
                 offer.name = self.read_text()?;
             }
@@ -523,13 +931,20 @@ impl<B: BufRead> MarketXmlParser<B> {
                 offer.currency_id = self.read_text()?;
             }
             b"categoryId" => {
-                offer.category_id = self.read_value()?;
+                if self.config.string_category_ids {
+                    let category_raw_id = self.read_text()?;
+                    offer.category_id = category_raw_id.parse().unwrap_or(0);
+                    offer.category_raw_id = category_raw_id;
+                } else {
+                    offer.category_id = self.read_value()?;
+                }
             }
             b"description" => {
-                offer.description = self.read_text()?;
+                let description = self.read_field_text(b"description")?;
+                self.set_description(offer, description);
             }
             b"sales_notes" => {
-                offer.sales_notes = self.read_text()?;
+                offer.sales_notes = self.read_field_text(b"sales_notes")?;
             }
             b"delivery" => {
                 offer.delivery = self.read_opt()?;
@@ -583,8 +998,20 @@ impl<B: BufRead> MarketXmlParser<B> {
             b"pickup-options" => {
                 offer.pickup_options = self.parse_delivery_options()?;
             }
+            b"stock_quantity" if self.config.stock_and_keywords_fields => {
+                offer.stock_quantity = self.read_opt()?;
+            }
+            b"keywords" if self.config.stock_and_keywords_fields => {
+                let keywords = self.read_text()?;
+                offer.keywords.extend(
+                    keywords.split(',')
+                        .map(|keyword| keyword.trim())
+                        .filter(|keyword| !keyword.is_empty())
+                        .map(|keyword| keyword.to_string())
+                );
+            }
             field_name => {
-                let field_value = self.read_text()?;
+                let field_value = self.read_field_text(field_name)?;
                 match offer.extra_fields.entry(self.decode_value(field_name)?.to_string()) {
                     Entry::Occupied(mut entry) => {
                         entry.get_mut().values.push(field_value);
@@ -598,13 +1025,108 @@ impl<B: BufRead> MarketXmlParser<B> {
         Ok(())
     }
 
+    // Fields from foreign namespaces are kept as is under a `{namespace}name` key
+    fn parse_foreign_field(
+        &mut self, ns: &[u8], tag: &BytesStart, has_content: bool, offer: &mut Offer
+    ) -> Result<(), MarketXmlError> {
+        let value = if has_content {
+            self.read_inner_xml()?
+        } else {
+            String::new()
+        };
+        let key = format!("{{{}}}{}", self.decode_value(ns)?, self.decode_value(tag.local_name())?);
+        offer.extra_fields.entry(key).or_default().values.push(value);
+        Ok(())
+    }
+
+    // Finds out if the field is a localized name or description and returns its language
+    fn localized_field(
+        &self, tag: &BytesStart
+    ) -> Result<Option<(LocalizedField, String)>, MarketXmlError> {
+        let field = match tag.local_name() {
+            b"name" => LocalizedField::Name,
+            b"description" => LocalizedField::Description,
+            tag_name => {
+                for (suffix, lang) in &self.config.localized_suffixes {
+                    let field = match strip_lang_suffix(tag_name, suffix) {
+                        Some(b"name") => LocalizedField::Name,
+                        Some(b"description") => LocalizedField::Description,
+                        _ => continue,
+                    };
+                    return Ok(Some((field, lang.clone())));
+                }
+                return Ok(None);
+            }
+        };
+
+        let lang_attr = match self.config.lang_attr {
+            Some(ref lang_attr) => lang_attr,
+            None => return Ok(None),
+        };
+        for attr_res in tag.attributes() {
+            let attr = attr_res.context(self.xml_err_ctx())?;
+            if attr.key != lang_attr.as_slice() {
+                continue;
+            }
+            let lang = self.decode_value(&attr.value)?;
+            if lang.is_empty() || self.config.default_lang.as_deref() == Some(lang) {
+                return Ok(None);
+            }
+            return Ok(Some((field, lang.to_string())));
+        }
+        Ok(None)
+    }
+
+    fn parse_localized_field(
+        &mut self, field: LocalizedField, lang: String, offer: &mut Offer
+    ) -> Result<(), MarketXmlError> {
+        match field {
+            LocalizedField::Name => {
+                let name = self.read_text()?;
+                get_localized(offer, lang).name = name;
+            }
+            LocalizedField::Description => {
+                let description = self.read_field_text(b"description")?;
+                let (description, description_text) = self.process_description(description);
+                let localized = get_localized(offer, lang);
+                localized.description = description;
+                localized.description_text = description_text;
+            }
+        }
+        Ok(())
+    }
+
+    fn set_description(&mut self, offer: &mut Offer, description: String) {
+        let (description, description_text) = self.process_description(description);
+        offer.description = description;
+        offer.description_text = description_text;
+    }
+
+    // Returns a description and its plain text when the sanitizing is enabled
+    fn process_description(&mut self, description: String) -> (String, String) {
+        if !self.config.sanitize_description {
+            return (description, String::new());
+        }
+        let sanitized = html::sanitize(&description, self.config.description_max_length);
+        if sanitized.truncated {
+            self.warn(
+                format!(
+                    "Description is too long, truncated to {} characters",
+                    self.config.description_max_length.unwrap_or(0)
+                ),
+                sanitized.text_length.to_string(),
+            );
+        }
+        (sanitized.html, sanitized.text)
+    }
+
     fn parse_delivery_options(&mut self) -> Result<Vec<DeliveryOption>, MarketXmlError> {
         let mut options = vec!();
         loop {
             match self.next_event()? {
                 Event::Start(tag) |
                 Event::Empty(tag) => {
-                    if tag.name() == b"option" {
+                    if tag.local_name() == b"option" {
                         let tag = tag.into_owned();
                         options.push(self.parse_delivery_option(&mut tag.attributes())?);
                     }
@@ -616,6 +1138,7 @@ impl<B: BufRead> MarketXmlParser<B> {
                     return Err(XmlError::UnexpectedEof("Delivery options".to_string()))
                         .context(self.xml_err_ctx());
                 }
+                Event::Text(ref text) if is_whitespace(text) => {}
                 _ => {
                     return Err(XmlError::TextNotFound)
                         .context(self.xml_err_ctx())
@@ -625,7 +1148,7 @@ impl<B: BufRead> MarketXmlParser<B> {
         Ok(options)
     }
 
-    fn parse_delivery_option(&self, tag_attrs: &mut Attributes) -> Result<DeliveryOption, MarketXmlError> {
+    fn parse_delivery_option(&mut self, tag_attrs: &mut Attributes) -> Result<DeliveryOption, MarketXmlError> {
         let mut option = DeliveryOption::default();
         for attr_res in tag_attrs {
             let attr = attr_res.context(self.xml_err_ctx())?;
@@ -696,7 +1219,7 @@ impl<B: BufRead> MarketXmlParser<B> {
         loop {
             match self.next_event()? {
                 Event::Start(ref tag) => {
-                    let tag_name = tag.name();
+                    let tag_name = tag.local_name();
                     match tag_name {
                         b"reason" => {
                             condition.reason = self.read_text()?;
@@ -709,6 +1232,7 @@ impl<B: BufRead> MarketXmlParser<B> {
                     return Err(XmlError::UnexpectedEof("Condition".to_string()))
                         .context(self.xml_err_ctx());
                 },
+                Event::Text(ref text) if is_whitespace(text) => {}
                 _ => {
                     return Err(XmlError::TextNotFound)
                         .context(self.xml_err_ctx());
@@ -741,66 +1265,143 @@ impl<B: BufRead> MarketXmlParser<B> {
     }
 
     fn read_text(&mut self) -> Result<String, MarketXmlError> {
-        self.read_text_and_parse(|s, _, _| Ok(s.to_string()))
+        let whitespace = self.config.whitespace;
+        self.read_text_and_parse(|s, _, _| Ok(whitespace.apply(s.to_string())))
     }
 
-    fn read_value<T>(&mut self) -> Result<T, MarketXmlError>
-    where
-        T: FromStr,
-        T::Err: Display,
-    {
-        self.read_text_and_parse(|s, line, column| {
-            s.parse().map_err(|e| {
-                MarketXmlError::Validation {
-                    msg: format!("{}", e),
-                    line,
-                    column,
-                    value: s.to_string(),
-                }
-            })
-        })
+    fn read_presence(&mut self) -> Result<Option<bool>, MarketXmlError> {
+        let presence = self.read_text()?;
+        if presence.is_empty() {
+            return Ok(None);
+        }
+        match parse_presence(&presence) {
+            Some(available) => Ok(Some(available)),
+            None => Err(MarketXmlError::Validation {
+                msg: "unknown presence".to_string(),
+                line: self.cur_line(),
+                column: self.cur_column(),
+                value: presence,
+            }),
+        }
     }
 
-    fn read_opt<T>(&mut self) -> Result<Option<T>, MarketXmlError>
-    where
-        T: FromStr,
-        T::Err: Display,
+    fn read_field_text(&mut self, field_name: &[u8]) -> Result<String, MarketXmlError> {
+        if self.config.inner_xml_fields.contains(field_name) {
+            self.read_inner_xml()
+        } else {
+            self.read_text()
+        }
+    }
+
+    /// Reads a text of the current element like `read_text` does but does not fail
+    /// on child elements. When there are any the whole inner markup is returned.
+    fn read_inner_xml(&mut self) -> Result<String, MarketXmlError> {
+        let mut text = String::new();
+        let mut markup = String::new();
+        let mut has_markup = false;
+        let mut depth = 0;
+        let preserve = self.config.whitespace == WhitespacePolicy::Preserve;
+        loop {
+            match self.next_event()?.into_owned() {
+                Event::Text(tag_text) => {
+                    let bytes = tag_text.unescaped().context(self.xml_err_ctx())?;
+                    text.push_str(self.decode_value(&bytes)?);
+                    let escaped = self.decode_value(tag_text.escaped())?;
+                    // indentation between child elements is not a part of the markup,
+                    // but a space between inline elements is
+                    if preserve || !escaped.trim().is_empty() || !escaped.contains('\n') {
+                        markup.push_str(escaped);
+                    }
+                }
+                Event::CData(tag_text) => {
+                    let bytes = tag_text.unescaped().context(self.xml_err_ctx())?;
+                    let s = self.decode_value(&bytes)?;
+                    text.push_str(s);
+                    markup.push_str(s);
+                }
+                Event::Start(tag) => {
+                    has_markup = true;
+                    depth += 1;
+                    markup.push('<');
+                    markup.push_str(self.decode_value(&tag)?);
+                    markup.push('>');
+                }
+                Event::Empty(tag) => {
+                    has_markup = true;
+                    markup.push('<');
+                    markup.push_str(self.decode_value(&tag)?);
+                    markup.push_str("/>");
+                }
+                Event::End(tag) => {
+                    if depth == 0 {
+                        break;
+                    }
+                    depth -= 1;
+                    markup.push_str("</");
+                    markup.push_str(self.decode_value(tag.name())?);
+                    markup.push('>');
+                }
+                Event::Eof => return Err(MarketXmlError::Xml {
+                    source: XmlError::UnexpectedEof("Text".to_string()),
+                    line: self.cur_line(),
+                    column: self.cur_column(),
+                }),
+                _ => {}
+            }
+        }
+        if has_markup {
+            Ok(self.config.whitespace.apply(markup))
+        } else {
+            Ok(self.config.whitespace.apply(text))
+        }
+    }
+
+    fn read_value<T>(&mut self) -> Result<T, MarketXmlError>
+    where
+        T: FromStr,
+        T::Err: Display,
     {
-        self.read_text_and_parse(|s, line, column| {
+        let lenient = self.config.lenient;
+        let (value, coerced_from) = self.read_text_and_parse(|s, line, column| {
+            parse_str(s.trim(), lenient, line, column)
+        })?;
+        self.warn_coerced(coerced_from);
+        Ok(value)
+    }
+
+    fn read_opt<T>(&mut self) -> Result<Option<T>, MarketXmlError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let lenient = self.config.lenient;
+        let (value, coerced_from) = self.read_text_and_parse(|s, line, column| {
+            let s = s.trim();
             if s == "" {
-                Ok(None)
+                Ok((None, None))
             } else {
-                Some(
-                    s.parse().map_err(|e| {
-                        MarketXmlError::Validation {
-                            msg: format!("{}", e),
-                            line,
-                            column,
-                            value: s.to_string(),
-                        }
-                    })
-                ).transpose()
+                parse_str(s, lenient, line, column)
+                    .map(|(v, coerced_from)| (Some(v), coerced_from))
             }
-        })
+        })?;
+        self.warn_coerced(coerced_from);
+        Ok(value)
     }
 
-    fn parse_value<T>(&self, v: &[u8]) -> Result<T, MarketXmlError>
+    fn parse_value<T>(&mut self, v: &[u8]) -> Result<T, MarketXmlError>
     where
         T: FromStr,
         T::Err: Display,
     {
         let s = self.decode_value(v)?;
-        s.parse().map_err(|e| {
-            MarketXmlError::Validation {
-                msg: format!("{}", e),
-                line: self.cur_line(),
-                column: self.cur_column(),
-                value: s.to_string(),
-            }
-        })
+        let (value, coerced_from) = parse_str(
+            s, self.config.lenient, self.cur_line(), self.cur_column()
+        )?;
+        self.warn_coerced(coerced_from);
+        Ok(value)
     }
 
-    fn parse_opt<T>(&self, v: &[u8]) -> Result<Option<T>, MarketXmlError>
+    fn parse_opt<T>(&mut self, v: &[u8]) -> Result<Option<T>, MarketXmlError>
     where
         T: FromStr,
         T::Err: Display,
@@ -808,17 +1409,13 @@ impl<B: BufRead> MarketXmlParser<B> {
         if v == b"" {
             Ok(None)
         } else {
-            let s = self.decode_value(v)?;
-            Some(
-                s.parse().map_err(|e| {
-                    MarketXmlError::Validation {
-                        msg: format!("{}", e),
-                        line: self.cur_line(),
-                        column: self.cur_column(),
-                        value: s.to_string(),
-                    }
-                })
-            ).transpose()
+            self.parse_value(v).map(Some)
+        }
+    }
+
+    fn warn_coerced(&mut self, coerced_from: Option<String>) {
+        if let Some(value) = coerced_from {
+            self.warn("Non-standard value was coerced".to_string(), value);
         }
     }
 
@@ -826,52 +1423,214 @@ impl<B: BufRead> MarketXmlParser<B> {
     where
         F: FnOnce(&str, usize, usize) -> Result<T, MarketXmlError>,
     {
-        let mut text = String::new();
-        loop {
-            match self.next_event()? {
-                Event::Text(tag_text) |
-                Event::CData(tag_text) => {
-                    let bytes = match tag_text.unescaped() {
-                        Ok(bytes) => bytes,
-                        Err(e) => {
-                            return Err(MarketXmlError::Xml {
-                                source: e,
-                                line: self.cur_line(),
-                                column: self.cur_column(),
-                            });
-                        }
-                    };
-                    match str::from_utf8(&bytes) {
-                        Ok(s) => text.push_str(s.trim()),
-                        Err(e) => {
-                            return Err(MarketXmlError::InvalidUtf8 {
-                                msg: format!("{}", e),
-                                value: String::from_utf8_lossy(&bytes).to_string(),
-                                line: self.cur_line(),
-                                column: self.cur_column(),
-                            });
-                        }
+        let text = read_element_text(&mut self.xml_reader, &mut self.buf, &mut self.ns)?;
+        f(&text, self.cur_line(), self.cur_column())
+    }
+}
+
+// Does not borrow the whole parser so a config can be used while an event is alive.
+// All the events must be read here to keep namespace scopes in sync.
+pub(crate) fn read_event<'a, B: BufRead>(
+    xml_reader: &mut XmlReader<B, PositionWithLine>, buf: &'a mut Vec<u8>, ns: &mut Namespaces
+) -> Result<Event<'a>, MarketXmlError> {
+    let line = xml_reader.position().line();
+    let column = xml_reader.position().column();
+    let event_res = xml_reader.read_namespaced_event(buf, &mut ns.buf);
+    match event_res {
+        Ok((event_ns, event)) => {
+            ns.foreign = match (event_ns, &event) {
+                (Some(event_ns), Event::Start(_)) |
+                (Some(event_ns), Event::Empty(_)) |
+                (Some(event_ns), Event::End(_)) if !ns.known.contains(event_ns) => {
+                    Some(event_ns.to_vec())
+                }
+                _ => None,
+            };
+            match event {
+                Event::Start(_) => ns.depth += 1,
+                Event::End(_) => ns.depth = ns.depth.saturating_sub(1),
+                _ => {}
+            }
+            Ok(event)
+        }
+        Err(error) => {
+            Err(MarketXmlError::Xml {
+                source: error,
+                line,
+                column,
+            })
+        }
+    }
+}
+
+/// Reads a text of the current element up to its end tag
+pub(crate) fn read_element_text<B: BufRead>(
+    xml_reader: &mut XmlReader<B, PositionWithLine>, buf: &mut Vec<u8>, ns: &mut Namespaces
+) -> Result<String, MarketXmlError> {
+    let mut text = String::new();
+    loop {
+        let event = read_event(xml_reader, buf, ns)?;
+        let line = xml_reader.position().line();
+        let column = xml_reader.position().column();
+        match event {
+            Event::Text(tag_text) |
+            Event::CData(tag_text) => {
+                let bytes = match tag_text.unescaped() {
+                    Ok(bytes) => bytes,
+                    Err(e) => {
+                        return Err(MarketXmlError::Xml { source: e, line, column });
+                    }
+                };
+                match str::from_utf8(&bytes) {
+                    Ok(s) => text.push_str(s),
+                    Err(e) => {
+                        return Err(MarketXmlError::InvalidUtf8 {
+                            msg: format!("{}", e),
+                            value: String::from_utf8_lossy(&bytes).to_string(),
+                            line,
+                            column,
+                        });
                     }
                 }
-                Event::End(_) => {
-                    break;
+            }
+            Event::End(_) => {
+                break;
+            }
+            Event::Eof => return Err(MarketXmlError::Xml {
+                source: XmlError::UnexpectedEof("Text".to_string()),
+                line,
+                column,
+            }),
+            _ => return Err(MarketXmlError::Xml {
+                source: XmlError::TextNotFound,
+                line,
+                column,
+            }),
+        }
+    }
+    Ok(text)
+}
+
+const PRESENT: &[&str] = &[
+    "true", "1", "available", "in_stock", "instock", "in stock",
+    "в наличии", "есть в наличии", "на складе", "есть",
+    "в наявності", "є в наявності", "на складі", "є",
+];
+
+// YML treats an offer that can be ordered but is absent at the moment as unavailable
+const ABSENT: &[&str] = &[
+    "false", "0", "not_available", "out_of_stock", "outofstock", "out of stock",
+    "order", "preorder", "backorder", "под заказ", "предзаказ", "ожидается", "нет в наличии", "отсутствует",
+    "під замовлення", "передзамовлення", "очікується", "немає в наявності", "відсутній",
+];
+
+pub(crate) fn parse_presence(presence: &str) -> Option<bool> {
+    let presence = presence.trim().to_lowercase();
+    if PRESENT.contains(&presence.as_str()) {
+        Some(true)
+    } else if ABSENT.contains(&presence.as_str()) {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Builds a category tree from paths like `Home > Kitchen > Pans`
+/// for feeds that have no separate list of categories
+#[derive(Default)]
+pub(crate) struct CategoryPaths {
+    // full path -> category id
+    ids: HashMap<String, u64>,
+    categories: Vec<Category>,
+}
+
+impl CategoryPaths {
+    /// Returns an id of the last category of the path, missing categories are added
+    pub(crate) fn category_id(&mut self, path: &str) -> u64 {
+        let mut cur_path = String::new();
+        let mut parent_id = 0;
+        for name in path.split('>').map(|name| name.trim()).filter(|name| !name.is_empty()) {
+            if !cur_path.is_empty() {
+                cur_path.push_str(" > ");
+            }
+            cur_path.push_str(name);
+            let next_id = self.categories.len() as u64 + 1;
+            parent_id = match self.ids.entry(cur_path.clone()) {
+                Entry::Occupied(entry) => *entry.get(),
+                Entry::Vacant(entry) => {
+                    entry.insert(next_id);
+                    self.categories.push(Category {
+                        id: next_id,
+                        parent_id,
+                        name: name.to_string(),
+                        ..Default::default()
+                    });
+                    next_id
+                }
+            };
+        }
+        parent_id
+    }
+
+    pub(crate) fn categories(&self) -> &[Category] {
+        &self.categories
+    }
+}
+
+fn get_localized(offer: &mut Offer, lang: String) -> &mut LocalizedText {
+    offer.localized.entry(lang).or_default()
+}
+
+// `name_ua` -> `name`
+fn strip_lang_suffix<'a>(tag_name: &'a [u8], suffix: &[u8]) -> Option<&'a [u8]> {
+    if tag_name.len() > suffix.len() + 1 && tag_name.ends_with(suffix) {
+        let field_len = tag_name.len() - suffix.len() - 1;
+        if tag_name[field_len] == b'_' {
+            return Some(&tag_name[..field_len]);
+        }
+    }
+    None
+}
+
+// Parses a value, in the lenient mode also tries to coerce it.
+// Returns the original string along with the value when coercion took place
+pub(crate) fn parse_str<T>(
+    s: &str, lenient: bool, line: usize, column: usize
+) -> Result<(T, Option<String>), MarketXmlError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match s.parse() {
+        Ok(v) => Ok((v, None)),
+        Err(e) => {
+            let mut msg = format!("{}", e);
+            if lenient {
+                // `1,000` is a valid float after coercion but could mean a thousand as well
+                if lenient::has_ambiguous_separator(s) {
+                    msg = "Ambiguous separator, it can be either decimal or thousands one".to_string();
+                } else {
+                    for candidate in lenient::coercion_candidates(s) {
+                        if let Ok(v) = candidate.parse() {
+                            return Ok((v, Some(s.to_string())));
+                        }
+                    }
                 }
-                Event::Eof => return Err(MarketXmlError::Xml {
-                    source: XmlError::UnexpectedEof("Text".to_string()),
-                    line: self.cur_line(),
-                    column: self.cur_column(),
-                }),
-                _ => return Err(MarketXmlError::Xml {
-                    source: XmlError::TextNotFound,
-                    line: self.cur_line(),
-                    column: self.cur_column(),
-                }),
             }
+            Err(MarketXmlError::Validation {
+                msg,
+                line,
+                column,
+                value: s.to_string(),
+            })
         }
-        f(&text, self.cur_line(), self.cur_column())
     }
 }
 
+pub(crate) fn is_whitespace(text: &[u8]) -> bool {
+    text.iter().all(|b| b.is_ascii_whitespace())
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -880,8 +1639,13 @@ mod tests {
 
     use failure::{bail, Error};
 
-    use crate::market_xml::{Category, Condition, Currency, DeliveryOption, OfferExtraField, Param};
-    use super::{MarketXmlConfig, MarketXmlParser, ParsedItem};
+    use crate::market_xml::{
+        Category, Condition, Currency, DeliveryOption, LocalizedText, OfferExtraField, Param,
+    };
+    use super::{
+        Dialect, FeedParser, MarketXmlConfig, MarketXmlError, MarketXmlParser, MarketXmlWarning, ParsedItem,
+        WhitespacePolicy,
+    };
 
     #[test]
     fn test_parsing_shop() -> Result<(), Error> {
@@ -930,8 +1694,10 @@ mod tests {
         assert_eq!(
             s.categories,
             vec!(
-                Category { id: 1, parent_id: 0, name: "Бытовая техника".to_string() },
-                Category { id: 10, parent_id: 1, name: "Мелкая техника для кухни".to_string() }
+                Category { id: 1, parent_id: 0, name: "Бытовая техника".to_string(), ..Default::default() },
+                Category {
+                    id: 10, parent_id: 1, name: "Мелкая техника для кухни".to_string(), ..Default::default()
+                }
             )
         );
         assert_eq!(
@@ -944,6 +1710,19 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_locating_errors() {
+        let next_error = |xml: &str| {
+            let mut parser = MarketXmlParser::new(MarketXmlConfig::default(), xml.as_bytes());
+            match parser.next_item() {
+                Err(MarketXmlError::Validation { .. }) => parser.in_offer(),
+                res => panic!("Expected validation error, got {:?}", res),
+            }
+        };
+        assert!(!next_error(r#"<yml_catalog><shop><categories><category id="x">Pans</category></categories></shop></yml_catalog>"#));
+        assert!(next_error(r#"<yml_catalog><shop><offers><offer id="1"><price>bad</price></offer></offers></shop></yml_catalog>"#));
+    }
+
     #[test]
     fn test_parsing_simplified_offer() -> Result<(), Error> {
         let xml = r#"
@@ -1078,4 +1857,468 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_parsing_sanitized_description() -> Result<(), Error> {
+        let xml = r#"
+        <yml_catalog>
+          <shop>
+            <offers>
+              <offer id="1">
+                <description><![CDATA[<p onclick="x()">Short &amp; sweet</p>]]></description>
+              </offer>
+              <offer id="2">
+                <description><![CDATA[<h3>Long</h3><p>Very <b>long</b> description</p>]]></description>
+              </offer>
+            </offers>
+          </shop>
+        </yml_catalog>
+        "#;
+        let reader = BufReader::new(xml.as_bytes());
+        let config = MarketXmlConfig {
+            sanitize_description: true,
+            description_max_length: Some(15),
+            ..Default::default()
+        };
+        let mut parser = MarketXmlParser::new(config, reader);
+
+        let o = match parser.next_item()? {
+            ParsedItem::Offer(offer) => offer,
+            _ => bail!("Expected offer"),
+        };
+        assert_eq!(&o.description, "<p>Short &amp; sweet</p>");
+        assert_eq!(&o.description_text, "Short & sweet");
+        assert_eq!(parser.take_warnings(), vec!());
+
+        let o = match parser.next_item()? {
+            ParsedItem::Offer(offer) => offer,
+            _ => bail!("Expected offer"),
+        };
+        assert_eq!(&o.description, "<h3>Long</h3><p>Very <b>long</b> </p>");
+        assert_eq!(&o.description_text, "Long\nVery long");
+        let warnings = parser.take_warnings();
+        assert_eq!(warnings.len(), 1);
+        let MarketXmlWarning { msg, line, value, .. } = &warnings[0];
+        assert_eq!(msg, "Description is too long, truncated to 15 characters");
+        assert_eq!(*line, 9);
+        assert_eq!(value, "26");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parsing_unescaped_markup() -> Result<(), Error> {
+        let xml = r#"
+        <yml_catalog>
+          <shop>
+            <offers>
+              <offer id="1">
+                <description>
+                  <p>Первая строка<br/><b>вторая</b> <i>третья</i> &amp; четвёртая</p> <![CDATA[<i>курсив</i>]]>
+                  <p>Пятая</p>
+                </description>
+                <sales_notes>Только &lt;предоплата&gt;</sales_notes>
+                <name>Мороженица</name>
+              </offer>
+            </offers>
+          </shop>
+        </yml_catalog>
+        "#;
+        let reader = BufReader::new(xml.as_bytes());
+        let mut parser = MarketXmlParser::new(MarketXmlConfig::default(), reader);
+        let o = match parser.next_item()? {
+            ParsedItem::Offer(offer) => offer,
+            _ => bail!("Expected offer"),
+        };
+        assert_eq!(
+            &o.description,
+            "<p>Первая строка<br/><b>вторая</b> <i>третья</i> &amp; четвёртая</p> <i>курсив</i><p>Пятая</p>"
+        );
+        assert_eq!(&o.sales_notes, "Только <предоплата>");
+        assert_eq!(&o.name, "Мороженица");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_whitespace_policy() -> Result<(), Error> {
+        let xml = r#"
+        <yml_catalog>
+          <shop>
+            <offers>
+              <offer id="1">
+                <name>  Мороженица   Brand <![CDATA[3811]]> </name>
+                <description><![CDATA[
+    def f():
+        pass
+]]></description>
+                <price> 8990 </price>
+              </offer>
+            </offers>
+          </shop>
+        </yml_catalog>
+        "#;
+
+        let parse = |whitespace| -> Result<_, Error> {
+            let config = MarketXmlConfig { whitespace, ..Default::default() };
+            let mut parser = MarketXmlParser::new(config, BufReader::new(xml.as_bytes()));
+            match parser.next_item()? {
+                ParsedItem::Offer(offer) => Ok(offer),
+                _ => bail!("Expected offer"),
+            }
+        };
+
+        let o = parse(WhitespacePolicy::Trim)?;
+        assert_eq!(&o.name, "Мороженица   Brand 3811");
+        assert_eq!(&o.description, "def f():\n        pass");
+        assert_eq!(o.price.unwrap().price, 8990.0);
+
+        let o = parse(WhitespacePolicy::Collapse)?;
+        assert_eq!(&o.name, "Мороженица Brand 3811");
+        assert_eq!(&o.description, "def f(): pass");
+
+        let o = parse(WhitespacePolicy::Preserve)?;
+        assert_eq!(&o.name, "  Мороженица   Brand 3811 ");
+        assert_eq!(&o.description, "\n    def f():\n        pass\n");
+        assert_eq!(o.price.unwrap().price, 8990.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parsing_lenient() -> Result<(), Error> {
+        let xml = r#"
+        <yml_catalog>
+          <shop>
+            <offers>
+              <offer id="1" available="Да" bid="1 000">
+                <price>8 990,00</price>
+                <oldprice>1.234,50</oldprice>
+                <delivery>yes</delivery>
+                <pickup>Нет</pickup>
+                <store>true</store>
+                <min_quantity>2,00</min_quantity>
+                <weight>3.6</weight>
+              </offer>
+            </offers>
+          </shop>
+        </yml_catalog>
+        "#;
+
+        let reader = BufReader::new(xml.as_bytes());
+        let mut parser = MarketXmlParser::new(MarketXmlConfig::default(), reader);
+        assert!(parser.next_item().is_err());
+
+        let reader = BufReader::new(xml.as_bytes());
+        let config = MarketXmlConfig { lenient: true, ..Default::default() };
+        let mut parser = MarketXmlParser::new(config, reader);
+        let o = match parser.next_item()? {
+            ParsedItem::Offer(offer) => offer,
+            _ => bail!("Expected offer"),
+        };
+        assert_eq!(o.available, Some(true));
+        assert_eq!(o.bid, 1000);
+        assert_eq!(o.price.unwrap().price, 8990.0);
+        assert_eq!(o.old_price.unwrap().price, 1234.5);
+        assert_eq!(o.delivery, Some(true));
+        assert_eq!(o.pickup, Some(false));
+        assert_eq!(o.store, Some(true));
+        assert_eq!(o.min_quantity, Some(2));
+        assert_eq!(o.weight, 3.6);
+        let coerced_values = parser.take_warnings()
+            .into_iter()
+            .map(|w| w.value)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            coerced_values,
+            vec!("Да", "1 000", "8 990,00", "1.234,50", "yes", "Нет", "2,00")
+        );
+
+        let xml = r#"
+        <yml_catalog>
+          <shop>
+            <offers>
+              <offer id="2"><min_quantity>1.000</min_quantity></offer>
+            </offers>
+          </shop>
+        </yml_catalog>
+        "#;
+        let config = MarketXmlConfig { lenient: true, ..Default::default() };
+        let mut parser = MarketXmlParser::new(config, BufReader::new(xml.as_bytes()));
+        match parser.next_item() {
+            Err(MarketXmlError::Validation { value, .. }) => assert_eq!(value, "1.000"),
+            res => bail!("Expected validation error, got {:?}", res),
+        }
+
+        let xml = r#"
+        <yml_catalog>
+          <shop>
+            <offers>
+              <offer id="3"><price>1,000</price></offer>
+            </offers>
+          </shop>
+        </yml_catalog>
+        "#;
+        let config = MarketXmlConfig { lenient: true, ..Default::default() };
+        let mut parser = MarketXmlParser::new(config, BufReader::new(xml.as_bytes()));
+        match parser.next_item() {
+            Err(MarketXmlError::Validation { value, .. }) => assert_eq!(value, "1,000"),
+            res => bail!("Expected validation error, got {:?}", res),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parsing_localized_fields() -> Result<(), Error> {
+        let xml = r#"
+        <yml_catalog>
+          <shop>
+            <offers>
+              <offer id="1">
+                <name>Мороженица</name>
+                <name_ua>Морозивниця</name_ua>
+                <name lang="en">Ice cream maker</name>
+                <name lang="ru">Мороженица Brand</name>
+                <description><![CDATA[<p>Описание</p>]]></description>
+                <description_ua><![CDATA[<p>Опис</p>]]></description_ua>
+                <vendor_ua>Бренд</vendor_ua>
+              </offer>
+            </offers>
+          </shop>
+        </yml_catalog>
+        "#;
+        let reader = BufReader::new(xml.as_bytes());
+        let mut config = MarketXmlConfig {
+            lang_attr: Some(b"lang".to_vec()),
+            default_lang: Some("ru".to_string()),
+            ..Default::default()
+        };
+        config.localized_suffixes.insert(b"ua".to_vec(), "uk".to_string());
+        let mut parser = MarketXmlParser::new(config, reader);
+        let o = match parser.next_item()? {
+            ParsedItem::Offer(offer) => offer,
+            _ => bail!("Expected offer"),
+        };
+        assert_eq!(&o.name, "Мороженица Brand");
+        assert_eq!(&o.description, "<p>Описание</p>");
+        let mut expected_localized = HashMap::new();
+        expected_localized.insert(
+            "uk".to_string(),
+            LocalizedText {
+                name: "Морозивниця".to_string(),
+                description: "<p>Опис</p>".to_string(),
+                ..Default::default()
+            }
+        );
+        expected_localized.insert(
+            "en".to_string(),
+            LocalizedText { name: "Ice cream maker".to_string(), ..Default::default() }
+        );
+        assert_eq!(o.localized, expected_localized);
+        assert_eq!(o.extra_fields.keys().collect::<Vec<_>>(), vec!("vendor_ua"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_yml_dialect_keeps_stock_and_keywords_in_extra_fields() -> Result<(), Error> {
+        let xml = r#"
+        <yml_catalog>
+          <shop>
+            <offers>
+              <offer id="7">
+                <name>Кастрюля</name>
+                <stock_quantity>3</stock_quantity>
+                <keywords>кастрюля, посуда</keywords>
+              </offer>
+            </offers>
+          </shop>
+        </yml_catalog>
+        "#;
+        let reader = BufReader::new(xml.as_bytes());
+        let mut parser = MarketXmlParser::new(MarketXmlConfig::for_dialect(Dialect::Yml), reader);
+        let o = match parser.next_item()? {
+            ParsedItem::Offer(offer) => offer,
+            _ => bail!("Expected offer"),
+        };
+        assert_eq!(o.stock_quantity, None);
+        assert!(o.keywords.is_empty());
+        assert_eq!(o.extra_fields["stock_quantity"].values, vec!("3".to_string()));
+        assert_eq!(o.extra_fields["keywords"].values, vec!("кастрюля, посуда".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parsing_prom_dialect() -> Result<(), Error> {
+        let xml = r#"
+        <yml_catalog date="2020-01-01 12:00">
+          <shop>
+            <catalog>
+              <category id="a1">Посуда</category>
+              <category id="2" parentID="a1">Кастрюли</category>
+            </catalog>
+            <items>
+              <item id="7" available="true">
+                <name>Кастрюля</name>
+                <name_ua>Каструля</name_ua>
+                <categoryId>2</categoryId>
+                <priceuah>499</priceuah>
+                <price_old>599</price_old>
+                <image>http://example.com/1.jpg</image>
+                <presence>под заказ</presence>
+                <quantity_in_stock>3</quantity_in_stock>
+                <keywords>кастрюля, посуда,</keywords>
+              </item>
+            </items>
+          </shop>
+        </yml_catalog>
+        "#;
+        let reader = BufReader::new(xml.as_bytes());
+        let mut parser = MarketXmlParser::new(MarketXmlConfig::for_dialect(Dialect::Prom), reader);
+        let o = match parser.next_item()? {
+            ParsedItem::Offer(offer) => offer,
+            _ => bail!("Expected offer"),
+        };
+        assert_eq!(&o.id, "7");
+        assert_eq!(o.category_id, 2);
+        assert_eq!(&o.category_raw_id, "2");
+        assert_eq!(o.price.as_ref().map(|p| p.price), Some(499.0));
+        assert_eq!(o.old_price.as_ref().map(|p| p.price), Some(599.0));
+        assert_eq!(o.pictures, vec!("http://example.com/1.jpg".to_string()));
+        assert_eq!(o.available, Some(false));
+        assert_eq!(o.stock_quantity, Some(3));
+        assert_eq!(o.keywords, vec!("кастрюля".to_string(), "посуда".to_string()));
+        assert_eq!(&o.localized["uk"].name, "Каструля");
+
+        let s = match parser.next_item()? {
+            ParsedItem::YmlCatalog(yml_catalog) => yml_catalog.shop.unwrap(),
+            _ => bail!("Expected yml catalog"),
+        };
+        assert_eq!(
+            s.categories,
+            vec!(
+                Category { name: "Посуда".to_string(), raw_id: "a1".to_string(), ..Default::default() },
+                Category {
+                    id: 2,
+                    name: "Кастрюли".to_string(),
+                    raw_id: "2".to_string(),
+                    raw_parent_id: "a1".to_string(),
+                    ..Default::default()
+                },
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parsing_hotline_dialect() -> Result<(), Error> {
+        let xml = r#"
+        <price>
+          <date>2020-01-01 12:00</date>
+          <firmName>Магазин</firmName>
+          <firmId>42</firmId>
+          <categories>
+            <category>
+              <id>10</id>
+              <name>Ноутбуки</name>
+            </category>
+            <category>
+              <id>11</id>
+              <parentId>10</parentId>
+              <name>Игровые</name>
+            </category>
+          </categories>
+          <items>
+            <item>
+              <id>A-1</id>
+              <categoryId>11</categoryId>
+              <code>X15</code>
+              <vendor>Acme</vendor>
+              <name>Ноутбук</name>
+              <image>http://example.com/1.jpg</image>
+              <priceRUAH>30000</priceRUAH>
+              <stock>В наличии</stock>
+            </item>
+          </items>
+        </price>
+        "#;
+        let reader = BufReader::new(xml.as_bytes());
+        let mut parser = MarketXmlParser::new(MarketXmlConfig::for_dialect(Dialect::Hotline), reader);
+        let o = match parser.next_item()? {
+            ParsedItem::Offer(offer) => offer,
+            _ => bail!("Expected offer"),
+        };
+        assert_eq!(&o.id, "A-1");
+        assert_eq!(o.category_id, 11);
+        assert_eq!(&o.vendor_code, "X15");
+        assert_eq!(o.price.as_ref().map(|p| p.price), Some(30000.0));
+        assert_eq!(o.available, Some(true));
+
+        let c = match parser.next_item()? {
+            ParsedItem::YmlCatalog(yml_catalog) => yml_catalog,
+            _ => bail!("Expected yml catalog"),
+        };
+        assert_eq!(&c.date, "2020-01-01 12:00");
+        let s = c.shop.unwrap();
+        assert_eq!(&s.name, "Магазин");
+        assert_eq!(s.categories.len(), 2);
+        assert_eq!((s.categories[1].id, s.categories[1].parent_id), (11, 10));
+        assert_eq!(&s.categories[1].name, "Игровые");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parsing_namespaces() -> Result<(), Error> {
+        let xml = r#"
+        <ym:yml_catalog xmlns:ym="urn:yml" xmlns:ext="urn:ext" date="2020-01-01 12:00">
+          <ym:shop>
+            <ym:name>Shop</ym:name>
+            <ext:name>Extension</ext:name>
+            <ym:offers>
+              <ext:offer id="0"><ext:name>Not an offer</ext:name></ext:offer>
+              <ym:offer id="1">
+                <ym:name>Мороженица</ym:name>
+                <price xmlns="urn:yml">8990</price>
+                <ext:name>Ice cream maker</ext:name>
+                <ext:rating><ext:value>4.5</ext:value></ext:rating>
+                <ext:hidden/>
+              </ym:offer>
+            </ym:offers>
+          </ym:shop>
+        </ym:yml_catalog>
+        "#;
+        let reader = BufReader::new(xml.as_bytes());
+        let mut parser = MarketXmlParser::new(MarketXmlConfig::default(), reader);
+        let o = match parser.next_item()? {
+            ParsedItem::Offer(offer) => offer,
+            _ => bail!("Expected offer"),
+        };
+        assert_eq!(&o.id, "1");
+        assert_eq!(&o.name, "Мороженица");
+        assert_eq!(o.price.as_ref().map(|p| p.price), Some(8990.0));
+        let mut extra_fields = o.extra_fields.into_iter()
+            .map(|(k, v)| (k, v.values))
+            .collect::<Vec<_>>();
+        extra_fields.sort();
+        assert_eq!(
+            extra_fields,
+            vec!(
+                ("{urn:ext}hidden".to_string(), vec!("".to_string())),
+                ("{urn:ext}name".to_string(), vec!("Ice cream maker".to_string())),
+                ("{urn:ext}rating".to_string(), vec!("<ext:value>4.5</ext:value>".to_string())),
+            )
+        );
+
+        let s = match parser.next_item()? {
+            ParsedItem::YmlCatalog(yml_catalog) => yml_catalog.shop.unwrap(),
+            _ => bail!("Expected yml catalog"),
+        };
+        assert_eq!(&s.name, "Shop");
+
+        Ok(())
+    }
 }
@@ -0,0 +1,427 @@
+use rusqlite::{params, Connection};
+
+use std::path::{Path, PathBuf};
+
+use crate::market_xml::{Error, Offer, YmlCatalog};
+
+const SCHEMA: &str = "
+CREATE TABLE shop (
+    date TEXT NOT NULL,
+    name TEXT NOT NULL,
+    company TEXT NOT NULL,
+    url TEXT NOT NULL,
+    platform TEXT NOT NULL,
+    version TEXT NOT NULL,
+    agency TEXT NOT NULL,
+    email TEXT NOT NULL
+);
+CREATE TABLE currencies (
+    id TEXT NOT NULL,
+    rate TEXT NOT NULL,
+    plus TEXT NOT NULL
+);
+CREATE TABLE categories (
+    id INTEGER NOT NULL,
+    parent_id INTEGER,
+    name TEXT NOT NULL,
+    raw_id TEXT NOT NULL,
+    raw_parent_id TEXT NOT NULL
+);
+CREATE TABLE offers (
+    id TEXT NOT NULL,
+    type TEXT NOT NULL,
+    available INTEGER,
+    name TEXT NOT NULL,
+    category_id INTEGER NOT NULL,
+    category_raw_id TEXT NOT NULL,
+    price REAL,
+    price_from INTEGER,
+    old_price REAL,
+    old_price_from INTEGER,
+    currency_id TEXT NOT NULL,
+    url TEXT NOT NULL,
+    vendor TEXT NOT NULL,
+    vendor_code TEXT NOT NULL,
+    model TEXT NOT NULL,
+    type_prefix TEXT NOT NULL,
+    bid INTEGER NOT NULL,
+    cbid INTEGER NOT NULL,
+    enable_auto_discounts INTEGER NOT NULL,
+    delivery INTEGER,
+    pickup INTEGER,
+    store INTEGER,
+    description TEXT NOT NULL,
+    description_text TEXT NOT NULL,
+    sales_notes TEXT NOT NULL,
+    country_of_origin TEXT NOT NULL,
+    manufacturer_warranty INTEGER NOT NULL,
+    adult INTEGER NOT NULL,
+    min_quantity INTEGER,
+    stock_quantity INTEGER,
+    condition_type TEXT,
+    condition_reason TEXT,
+    credit_template_id TEXT NOT NULL,
+    expiry TEXT NOT NULL,
+    weight REAL NOT NULL,
+    dimensions TEXT NOT NULL,
+    downloadable INTEGER NOT NULL,
+    age_unit TEXT,
+    age_value INTEGER,
+    group_id INTEGER NOT NULL,
+    barcodes TEXT NOT NULL,
+    keywords TEXT NOT NULL
+);
+CREATE TABLE offer_pictures (
+    offer_id TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    url TEXT NOT NULL
+);
+CREATE TABLE offer_params (
+    offer_id TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    unit TEXT NOT NULL,
+    value TEXT NOT NULL,
+    id TEXT NOT NULL,
+    value_id TEXT NOT NULL
+);
+-- kind is either `delivery` or `pickup`
+CREATE TABLE offer_delivery_options (
+    offer_id TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    cost INTEGER NOT NULL,
+    days TEXT NOT NULL,
+    order_before INTEGER
+);
+CREATE TABLE offer_localized (
+    offer_id TEXT NOT NULL,
+    lang TEXT NOT NULL,
+    name TEXT NOT NULL,
+    description TEXT NOT NULL,
+    description_text TEXT NOT NULL
+);
+CREATE TABLE offer_extra_fields (
+    offer_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    value TEXT NOT NULL
+);
+-- offers missing since a previous run
+CREATE TABLE deleted_offers (
+    id TEXT NOT NULL
+);
+CREATE TABLE errors (
+    level TEXT NOT NULL,
+    line INTEGER NOT NULL,
+    column INTEGER NOT NULL,
+    message TEXT NOT NULL,
+    value TEXT NOT NULL
+);
+";
+
+// creating indexes after loading all the rows is much faster
+const INDEXES: &str = "
+CREATE INDEX offers_id ON offers (id);
+CREATE INDEX offers_category_id ON offers (category_id);
+CREATE INDEX offer_pictures_offer_id ON offer_pictures (offer_id);
+CREATE INDEX offer_params_offer_id ON offer_params (offer_id);
+CREATE INDEX offer_extra_fields_offer_id ON offer_extra_fields (offer_id);
+CREATE INDEX offer_delivery_options_offer_id ON offer_delivery_options (offer_id);
+CREATE INDEX offer_localized_offer_id ON offer_localized (offer_id);
+CREATE INDEX categories_id ON categories (id);
+";
+
+/// Writes a catalog, offers and errors into a single sqlite database,
+/// all the writes happen inside a transaction that is committed by chunks
+pub(crate) struct SqliteWriter {
+    file_path: PathBuf,
+    conn: Connection,
+}
+
+impl SqliteWriter {
+    /// Creates tables in a new database
+    pub(crate) fn create(file_path: &Path) -> rusqlite::Result<Self> {
+        let conn = Connection::open(file_path)?;
+        conn.execute_batch("PRAGMA journal_mode = OFF; PRAGMA synchronous = OFF;")?;
+        conn.execute_batch(SCHEMA)?;
+        conn.execute_batch("BEGIN")?;
+        Ok(Self {
+            file_path: file_path.to_path_buf(),
+            conn,
+        })
+    }
+
+    pub(crate) fn file_path(&self) -> &Path {
+        &self.file_path
+    }
+
+    pub(crate) fn commit(&mut self) -> rusqlite::Result<()> {
+        self.conn.execute_batch("COMMIT; BEGIN")
+    }
+
+    pub(crate) fn finish(&mut self) -> rusqlite::Result<()> {
+        self.conn.execute_batch("COMMIT")?;
+        self.conn.execute_batch(INDEXES)
+    }
+
+    pub(crate) fn write_offer(&mut self, offer: &Offer) -> rusqlite::Result<()> {
+        let mut insert_offer = self.conn.prepare_cached(
+            "INSERT INTO offers VALUES (
+                ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?,
+                ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?
+            )"
+        )?;
+        insert_offer.execute(params!(
+            offer.id,
+            offer.r#type,
+            offer.available,
+            offer.name,
+            offer.category_id as i64,
+            offer.category_raw_id,
+            offer.price.as_ref().map(|p| p.price),
+            offer.price.as_ref().map(|p| p.from),
+            offer.old_price.as_ref().map(|p| p.price),
+            offer.old_price.as_ref().map(|p| p.from),
+            offer.currency_id,
+            offer.url,
+            offer.vendor,
+            offer.vendor_code,
+            offer.model,
+            offer.type_prefix,
+            offer.bid,
+            offer.cbid,
+            offer.enable_auto_discounts,
+            offer.delivery,
+            offer.pickup,
+            offer.store,
+            offer.description,
+            offer.description_text,
+            offer.sales_notes,
+            offer.country_of_origin,
+            offer.manufacturer_warranty,
+            offer.adult,
+            offer.min_quantity,
+            offer.stock_quantity,
+            offer.condition.as_ref().map(|c| &c.r#type),
+            offer.condition.as_ref().map(|c| &c.reason),
+            offer.credit_template_id,
+            offer.expiry,
+            offer.weight,
+            offer.dimensions,
+            offer.downloadable,
+            offer.age.as_ref().map(|a| &a.unit),
+            offer.age.as_ref().map(|a| a.value),
+            offer.group_id,
+            json_list(&offer.barcodes),
+            json_list(&offer.keywords),
+        ))?;
+
+        let mut insert_picture = self.conn.prepare_cached(
+            "INSERT INTO offer_pictures VALUES (?, ?, ?)"
+        )?;
+        for (position, url) in offer.pictures.iter().enumerate() {
+            insert_picture.execute(params!(offer.id, position, url))?;
+        }
+
+        let mut insert_param = self.conn.prepare_cached(
+            "INSERT INTO offer_params VALUES (?, ?, ?, ?, ?, ?, ?)"
+        )?;
+        for (position, param) in offer.params.iter().enumerate() {
+            insert_param.execute(params!(
+                offer.id, position, param.name, param.unit, param.value, param.id, param.value_id
+            ))?;
+        }
+
+        let mut insert_delivery_option = self.conn.prepare_cached(
+            "INSERT INTO offer_delivery_options VALUES (?, ?, ?, ?, ?, ?)"
+        )?;
+        for (kind, options) in &[("delivery", &offer.delivery_options), ("pickup", &offer.pickup_options)] {
+            for (position, option) in options.iter().enumerate() {
+                insert_delivery_option.execute(params!(
+                    offer.id, kind, position, option.cost, option.days, option.order_before
+                ))?;
+            }
+        }
+
+        let mut insert_localized = self.conn.prepare_cached(
+            "INSERT INTO offer_localized VALUES (?, ?, ?, ?, ?)"
+        )?;
+        for (lang, text) in &offer.localized {
+            insert_localized.execute(params!(
+                offer.id, lang, text.name, text.description, text.description_text
+            ))?;
+        }
+
+        let mut insert_extra_field = self.conn.prepare_cached(
+            "INSERT INTO offer_extra_fields VALUES (?, ?, ?, ?)"
+        )?;
+        for (name, field) in &offer.extra_fields {
+            for (position, value) in field.values.iter().enumerate() {
+                insert_extra_field.execute(params!(offer.id, name, position, value))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn write_catalog(&mut self, yml_catalog: &YmlCatalog) -> rusqlite::Result<()> {
+        let shop = yml_catalog.shop.clone().unwrap_or_default();
+        self.conn.execute(
+            "INSERT INTO shop VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+            params!(
+                yml_catalog.date, shop.name, shop.company, shop.url,
+                shop.platform, shop.version, shop.agency, shop.email
+            ),
+        )?;
+        for currency in &shop.currencies {
+            self.conn.execute(
+                "INSERT INTO currencies VALUES (?, ?, ?)",
+                params!(currency.id, currency.rate, currency.plus),
+            )?;
+        }
+        for category in &shop.categories {
+            // zero means a root category
+            let parent_id = Some(category.parent_id as i64).filter(|&id| id != 0);
+            self.conn.execute(
+                "INSERT INTO categories VALUES (?, ?, ?, ?, ?)",
+                params!(
+                    category.id as i64, parent_id, category.name,
+                    category.raw_id, category.raw_parent_id
+                ),
+            )?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn write_deleted_ids(&mut self, ids: &[String]) -> rusqlite::Result<()> {
+        let mut insert_id = self.conn.prepare_cached("INSERT INTO deleted_offers VALUES (?)")?;
+        for id in ids {
+            insert_id.execute(params!(id))?;
+        }
+        Ok(())
+    }
+
+    /// Level is either `error` or `warning`
+    pub(crate) fn write_errors(&mut self, level: &str, errors: &[Error]) -> rusqlite::Result<()> {
+        let mut insert_error = self.conn.prepare_cached(
+            "INSERT INTO errors VALUES (?, ?, ?, ?, ?)"
+        )?;
+        for error in errors {
+            insert_error.execute(params!(
+                level, error.line as i64, error.column as i64, error.message, error.value
+            ))?;
+        }
+        Ok(())
+    }
+}
+
+fn json_list(values: &[String]) -> String {
+    serde_json::to_string(values).unwrap_or_default()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+
+    use failure::Error;
+
+    use rusqlite::Connection;
+
+    use crate::market_xml::{
+        self, Category, DeliveryOption, LocalizedText, Offer, OfferExtraField, Param, Price, Shop, YmlCatalog,
+    };
+    use super::SqliteWriter;
+
+    #[test]
+    fn test_writing_sqlite() -> Result<(), Error> {
+        let file_path = std::env::temp_dir().join(format!("market-xml-{}.sqlite", std::process::id()));
+        let mut writer = SqliteWriter::create(&file_path)?;
+        writer.write_offer(&Offer {
+            id: "1".to_string(),
+            available: Some(true),
+            name: "Pan".to_string(),
+            category_id: 2,
+            price: Some(Price { price: 899.0, from: false }),
+            pictures: vec!("http://example.com/1.jpg".to_string(), "http://example.com/2.jpg".to_string()),
+            barcodes: vec!("4601234567890".to_string()),
+            params: vec!(Param { name: "Color".to_string(), value: "red".to_string(), ..Default::default() }),
+            extra_fields: HashMap::from([
+                ("supplier".to_string(), OfferExtraField { values: vec!("ACME".to_string()) }),
+            ]),
+            bid: 80,
+            pickup: Some(false),
+            delivery_options: vec!(DeliveryOption { cost: 300, days: "1-3".to_string(), order_before: Some(18) }),
+            localized: HashMap::from([
+                ("uk".to_string(), LocalizedText { name: "Сковорідка".to_string(), ..Default::default() }),
+            ]),
+            ..Default::default()
+        })?;
+        writer.commit()?;
+        writer.write_offer(&Offer { id: "2".to_string(), category_id: 1, ..Default::default() })?;
+        writer.write_catalog(&YmlCatalog {
+            date: "2020-01-01 00:00".to_string(),
+            shop: Some(Shop {
+                name: "Shop".to_string(),
+                categories: vec!(
+                    Category { id: 1, name: "Kitchen".to_string(), ..Default::default() },
+                    Category { id: 2, parent_id: 1, name: "Pans".to_string(), ..Default::default() },
+                ),
+                ..Default::default()
+            }),
+        })?;
+        writer.write_errors("error", &[market_xml::Error {
+            line: 10, column: 5, message: "Invalid price".to_string(), value: "abc".to_string()
+        }])?;
+        writer.finish()?;
+
+        let conn = Connection::open(&file_path)?;
+        let (name, price, available, barcodes): (String, f64, bool, String) = conn.query_row(
+            "SELECT name, price, available, barcodes FROM offers WHERE id = '1'", [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )?;
+        assert_eq!((name.as_str(), price, available), ("Pan", 899.0, true));
+        assert_eq!(barcodes, r#"["4601234567890"]"#);
+        let (bid, pickup, delivery): (u32, Option<bool>, Option<bool>) = conn.query_row(
+            "SELECT bid, pickup, delivery FROM offers WHERE id = '1'", [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+        assert_eq!((bid, pickup, delivery), (80, Some(false), None));
+        let (kind, cost, order_before): (String, u32, Option<u32>) = conn.query_row(
+            "SELECT kind, cost, order_before FROM offer_delivery_options WHERE offer_id = '1'", [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+        assert_eq!((kind.as_str(), cost, order_before), ("delivery", 300, Some(18)));
+        let localized_name: String = conn.query_row(
+            "SELECT name FROM offer_localized WHERE offer_id = '1' AND lang = 'uk'", [], |row| row.get(0)
+        )?;
+        assert_eq!(localized_name, "Сковорідка");
+        let available: Option<bool> = conn.query_row(
+            "SELECT available FROM offers WHERE id = '2'", [], |row| row.get(0)
+        )?;
+        assert_eq!(available, None);
+        let pictures = conn.prepare("SELECT url FROM offer_pictures WHERE offer_id = '1' ORDER BY position")?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        assert_eq!(pictures, vec!("http://example.com/1.jpg", "http://example.com/2.jpg"));
+        let category: String = conn.query_row(
+            "SELECT c.name FROM offers o JOIN categories c ON c.id = o.category_id
+             JOIN offer_params p ON p.offer_id = o.id
+             JOIN offer_extra_fields f ON f.offer_id = o.id
+             WHERE p.name = 'Color' AND f.value = 'ACME'", [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(category, "Pans");
+        let root_parent_id: Option<i64> = conn.query_row(
+            "SELECT parent_id FROM categories WHERE id = 1", [], |row| row.get(0)
+        )?;
+        assert_eq!(root_parent_id, None);
+        let message: String = conn.query_row("SELECT message FROM errors WHERE level = 'error'", [], |row| row.get(0))?;
+        assert_eq!(message, "Invalid price");
+        fs::remove_file(&file_path)?;
+
+        Ok(())
+    }
+}
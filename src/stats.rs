@@ -0,0 +1,411 @@
+use serde::Serialize;
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::market_xml::{Offer, YmlCatalog};
+
+// the last bucket holds offers with this number of pictures and more
+const MAX_PICTURES: usize = 10;
+
+// lower bounds of description length buckets in chars
+const DESCRIPTION_LENGTHS: [usize; 7] = [0, 1, 100, 500, 1000, 3000, 10000];
+
+type FieldIsFilled = fn(&Offer) -> bool;
+
+/// All the offer fields in the snake case with checks whether they are filled
+pub(crate) const OFFER_FIELDS: [(&str, FieldIsFilled); 44] = [
+    ("id", |o| !o.id.is_empty()),
+    ("type", |o| !o.r#type.is_empty()),
+    ("available", |o| o.available.is_some()),
+    ("name", |o| !o.name.is_empty()),
+    ("category_id", |o| o.category_id != 0),
+    ("price", |o| o.price.is_some()),
+    ("old_price", |o| o.old_price.is_some()),
+    ("currency_id", |o| !o.currency_id.is_empty()),
+    ("url", |o| !o.url.is_empty()),
+    ("vendor", |o| !o.vendor.is_empty()),
+    ("vendor_code", |o| !o.vendor_code.is_empty()),
+    ("model", |o| !o.model.is_empty()),
+    ("type_prefix", |o| !o.type_prefix.is_empty()),
+    ("bid", |o| o.bid != 0),
+    ("cbid", |o| o.cbid != 0),
+    ("enable_auto_discounts", |o| o.enable_auto_discounts),
+    ("pictures", |o| !o.pictures.is_empty()),
+    ("delivery", |o| o.delivery.is_some()),
+    ("pickup", |o| o.pickup.is_some()),
+    ("delivery_options", |o| !o.delivery_options.is_empty()),
+    ("pickup_options", |o| !o.pickup_options.is_empty()),
+    ("store", |o| o.store.is_some()),
+    ("description", |o| !o.description.is_empty()),
+    ("sales_notes", |o| !o.sales_notes.is_empty()),
+    ("min_quantity", |o| o.min_quantity.is_some()),
+    ("manufacturer_warranty", |o| o.manufacturer_warranty),
+    ("country_of_origin", |o| !o.country_of_origin.is_empty()),
+    ("adult", |o| o.adult),
+    ("barcodes", |o| !o.barcodes.is_empty()),
+    ("params", |o| !o.params.is_empty()),
+    ("condition", |o| o.condition.is_some()),
+    ("credit_template_id", |o| !o.credit_template_id.is_empty()),
+    ("expiry", |o| !o.expiry.is_empty()),
+    ("weight", |o| o.weight != 0.0),
+    ("dimensions", |o| !o.dimensions.is_empty()),
+    ("downloadable", |o| o.downloadable),
+    ("age", |o| o.age.is_some()),
+    ("group_id", |o| o.group_id != 0),
+    ("extra_fields", |o| !o.extra_fields.is_empty()),
+    ("description_text", |o| !o.description_text.is_empty()),
+    ("localized", |o| !o.localized.is_empty()),
+    ("stock_quantity", |o| o.stock_quantity.is_some()),
+    ("keywords", |o| !o.keywords.is_empty()),
+    ("category_raw_id", |o| !o.category_raw_id.is_empty()),
+];
+
+#[derive(Serialize, Debug, PartialEq)]
+pub(crate) struct Count {
+    pub(crate) name: String,
+    pub(crate) count: u64,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub(crate) struct CategoryCount {
+    pub(crate) id: String,
+    pub(crate) name: String,
+    pub(crate) offers: u64,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub(crate) struct PriceStats {
+    pub(crate) currency: String,
+    pub(crate) offers: u64,
+    pub(crate) min: f32,
+    pub(crate) median: f32,
+    pub(crate) max: f32,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub(crate) struct FillRate {
+    pub(crate) field: String,
+    pub(crate) offers: u64,
+    /// Share of offers with the filled field from 0 to 1
+    pub(crate) rate: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct StatsReport {
+    pub(crate) total_offers: u64,
+    pub(crate) by_type: Vec<Count>,
+    pub(crate) by_availability: Vec<Count>,
+    pub(crate) by_category: Vec<CategoryCount>,
+    pub(crate) prices: Vec<PriceStats>,
+    pub(crate) fill_rates: Vec<FillRate>,
+    pub(crate) extra_field_fill_rates: Vec<FillRate>,
+    /// The most frequent param names
+    pub(crate) params: Vec<Count>,
+    /// Number of offers by a number of pictures
+    pub(crate) pictures: Vec<Count>,
+    /// Number of offers by a length of the description in chars
+    pub(crate) description_lengths: Vec<Count>,
+    /// Vendors with the most offers
+    pub(crate) vendors: Vec<Count>,
+}
+
+/// Aggregates metrics of offers without keeping them in memory,
+/// only their prices are kept to find exact medians
+pub(crate) struct FeedStats {
+    total_offers: u64,
+    types: HashMap<String, u64>,
+    available: u64,
+    unavailable: u64,
+    categories: HashMap<String, u64>,
+    category_names: HashMap<String, String>,
+    prices: HashMap<String, Vec<f32>>,
+    filled_fields: [u64; OFFER_FIELDS.len()],
+    extra_fields: HashMap<String, u64>,
+    params: HashMap<String, u64>,
+    pictures: [u64; MAX_PICTURES + 1],
+    description_lengths: [u64; DESCRIPTION_LENGTHS.len()],
+    vendors: HashMap<String, u64>,
+}
+
+impl FeedStats {
+    pub(crate) fn new() -> Self {
+        Self {
+            total_offers: 0,
+            types: HashMap::new(),
+            available: 0,
+            unavailable: 0,
+            categories: HashMap::new(),
+            category_names: HashMap::new(),
+            prices: HashMap::new(),
+            filled_fields: [0; OFFER_FIELDS.len()],
+            extra_fields: HashMap::new(),
+            params: HashMap::new(),
+            pictures: [0; MAX_PICTURES + 1],
+            description_lengths: [0; DESCRIPTION_LENGTHS.len()],
+            vendors: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn add_offer(&mut self, offer: &Offer) {
+        self.total_offers += 1;
+        let offer_type = if offer.r#type.is_empty() { "simplified" } else { &offer.r#type };
+        *self.types.entry(offer_type.to_string()).or_default() += 1;
+        match offer.available {
+            Some(true) => self.available += 1,
+            Some(false) => self.unavailable += 1,
+            None => {}
+        }
+        *self.categories.entry(category_key(offer.category_id, &offer.category_raw_id)).or_default() += 1;
+        if let Some(ref price) = offer.price {
+            self.prices.entry(offer.currency_id.clone()).or_default().push(price.price);
+        }
+        for ((_, is_filled), filled) in OFFER_FIELDS.iter().zip(self.filled_fields.iter_mut()) {
+            if is_filled(offer) {
+                *filled += 1;
+            }
+        }
+        for (name, field) in &offer.extra_fields {
+            if !field.values.is_empty() {
+                *self.extra_fields.entry(name.clone()).or_default() += 1;
+            }
+        }
+        for param in &offer.params {
+            *self.params.entry(param.name.clone()).or_default() += 1;
+        }
+        self.pictures[offer.pictures.len().min(MAX_PICTURES)] += 1;
+        let description_length = offer.description.chars().count();
+        let bucket = DESCRIPTION_LENGTHS.iter().rposition(|&min| description_length >= min).unwrap_or(0);
+        self.description_lengths[bucket] += 1;
+        if !offer.vendor.is_empty() {
+            *self.vendors.entry(offer.vendor.clone()).or_default() += 1;
+        }
+    }
+
+    pub(crate) fn add_catalog(&mut self, yml_catalog: &YmlCatalog) {
+        for category in yml_catalog.shop.iter().flat_map(|shop| &shop.categories) {
+            self.category_names.insert(category_key(category.id, &category.raw_id), category.name.clone());
+        }
+    }
+
+    /// Lists of params and vendors are limited by `top` entries
+    pub(crate) fn finish(self, top: usize) -> StatsReport {
+        let total_offers = self.total_offers;
+        let fill_rate = |field: String, offers: u64| FillRate {
+            field,
+            offers,
+            rate: if total_offers > 0 { offers as f64 / total_offers as f64 } else { 0.0 },
+        };
+
+        let category_names = self.category_names;
+        let mut by_category = self.categories.into_iter()
+            .map(|(id, offers)| CategoryCount {
+                name: category_names.get(&id).cloned().unwrap_or_default(),
+                id,
+                offers,
+            })
+            .collect::<Vec<_>>();
+        by_category.sort_by(|a, b| b.offers.cmp(&a.offers).then_with(|| a.id.cmp(&b.id)));
+
+        let mut prices = self.prices.into_iter()
+            .map(|(currency, mut prices)| {
+                prices.sort_by(|a, b| a.total_cmp(b));
+                PriceStats {
+                    currency,
+                    offers: prices.len() as u64,
+                    min: prices[0],
+                    median: median(&prices),
+                    max: prices[prices.len() - 1],
+                }
+            })
+            .collect::<Vec<_>>();
+        prices.sort_by(|a, b| a.currency.cmp(&b.currency));
+
+        let mut extra_field_fill_rates = self.extra_fields.into_iter()
+            .map(|(field, offers)| fill_rate(field, offers))
+            .collect::<Vec<_>>();
+        extra_field_fill_rates.sort_by(|a, b| b.offers.cmp(&a.offers).then_with(|| a.field.cmp(&b.field)));
+
+        let pictures = self.pictures.iter().enumerate()
+            .map(|(num, &count)| {
+                let name = if num == MAX_PICTURES { format!("{}+", num) } else { num.to_string() };
+                Count { name, count }
+            })
+            .collect();
+        let description_length_counts = self.description_lengths;
+        let description_lengths = DESCRIPTION_LENGTHS.iter().enumerate()
+            .map(|(ix, &min)| {
+                let name = match DESCRIPTION_LENGTHS.get(ix + 1) {
+                    _ if min == 0 => "0".to_string(),
+                    Some(next) => format!("{}-{}", min, next - 1),
+                    None => format!("{}+", min),
+                };
+                Count { name, count: description_length_counts[ix] }
+            })
+            .collect();
+
+        StatsReport {
+            total_offers,
+            by_type: sorted_counts(self.types, usize::MAX),
+            by_availability: vec!(
+                Count { name: "available".to_string(), count: self.available },
+                Count { name: "unavailable".to_string(), count: self.unavailable },
+                Count { name: "missing".to_string(), count: total_offers - self.available - self.unavailable },
+            ),
+            by_category,
+            prices,
+            fill_rates: OFFER_FIELDS.iter().zip(self.filled_fields)
+                .map(|((field, _), offers)| fill_rate(field.to_string(), offers))
+                .collect(),
+            extra_field_fill_rates,
+            params: sorted_counts(self.params, top),
+            pictures,
+            description_lengths,
+            vendors: sorted_counts(self.vendors, top),
+        }
+    }
+}
+
+/// Raw ids are preferred as numeric ids of categories are missing in some dialects
+pub(crate) fn category_key(id: u64, raw_id: &str) -> String {
+    if raw_id.is_empty() { id.to_string() } else { raw_id.to_string() }
+}
+
+fn median(sorted: &[f32]) -> f32 {
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[mid - 1] + sorted[mid]) / 2.0
+    } else {
+        sorted[mid]
+    }
+}
+
+/// The most frequent entries first
+fn sorted_counts(counts: HashMap<String, u64>, top: usize) -> Vec<Count> {
+    let mut counts = counts.into_iter()
+        .map(|(name, count)| Count { name, count })
+        .collect::<Vec<_>>();
+    counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+    counts.truncate(top);
+    counts
+}
+
+/// Writes a human readable report, categories are limited by `top` entries
+pub(crate) fn write_stats_report<W: Write>(report: &StatsReport, top: usize, mut writer: W) -> io::Result<()> {
+    writeln!(writer, "Offers: {}", report.total_offers)?;
+    write_counts(&mut writer, "Types", &report.by_type)?;
+    write_counts(&mut writer, "Availability", &report.by_availability)?;
+
+    writeln!(writer, "\nCategories:")?;
+    for category in report.by_category.iter().take(top) {
+        writeln!(writer, "  {} {}: {}", category.id, category.name, category.offers)?;
+    }
+    if report.by_category.len() > top {
+        writeln!(writer, "  ... {} more", report.by_category.len() - top)?;
+    }
+
+    writeln!(writer, "\nPrices:")?;
+    for price in &report.prices {
+        writeln!(
+            writer, "  {}: {} offers, min {}, median {}, max {}",
+            if price.currency.is_empty() { "no currency" } else { &price.currency },
+            price.offers, price.min, price.median, price.max,
+        )?;
+    }
+
+    write_fill_rates(&mut writer, "Field fill rates", &report.fill_rates)?;
+    write_fill_rates(&mut writer, "Extra field fill rates", &report.extra_field_fill_rates)?;
+    write_counts(&mut writer, "Params", &report.params)?;
+    write_counts(&mut writer, "Pictures per offer", &report.pictures)?;
+    write_counts(&mut writer, "Description length", &report.description_lengths)?;
+    write_counts(&mut writer, "Vendors", &report.vendors)?;
+    writer.flush()
+}
+
+fn write_counts<W: Write>(writer: &mut W, title: &str, counts: &[Count]) -> io::Result<()> {
+    writeln!(writer, "\n{}:", title)?;
+    for count in counts {
+        writeln!(writer, "  {}: {}", count.name, count.count)?;
+    }
+    Ok(())
+}
+
+fn write_fill_rates<W: Write>(writer: &mut W, title: &str, fill_rates: &[FillRate]) -> io::Result<()> {
+    writeln!(writer, "\n{}:", title)?;
+    for fill_rate in fill_rates {
+        writeln!(writer, "  {}: {} ({:.1}%)", fill_rate.field, fill_rate.offers, fill_rate.rate * 100.0)?;
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::market_xml::{Category, Offer, OfferExtraField, Param, Price, Shop, YmlCatalog};
+    use super::{Count, FeedStats, PriceStats};
+
+    fn offer(id: &str, price: f32, vendor: &str) -> Offer {
+        Offer {
+            id: id.to_string(),
+            available: Some(true),
+            category_id: 1,
+            price: Some(Price { price, from: false }),
+            currency_id: "UAH".to_string(),
+            vendor: vendor.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_feed_stats() {
+        let mut stats = FeedStats::new();
+        stats.add_catalog(&YmlCatalog {
+            shop: Some(Shop {
+                categories: vec!(Category { id: 1, name: "Pans".to_string(), ..Default::default() }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        let mut described_offer = offer("1", 30.0, "Tefal");
+        described_offer.description = "a".repeat(150);
+        described_offer.pictures = vec!("1.jpg".to_string(), "2.jpg".to_string());
+        described_offer.params.push(Param { name: "Color".to_string(), ..Default::default() });
+        described_offer.extra_fields = HashMap::from([
+            ("supplier".to_string(), OfferExtraField { values: vec!("ACME".to_string()) }),
+        ]);
+        stats.add_offer(&described_offer);
+        stats.add_offer(&offer("2", 10.0, "Tefal"));
+        stats.add_offer(&offer("3", 15.0, "Gorenje"));
+        let mut unavailable_offer = offer("4", 20.0, "");
+        unavailable_offer.available = Some(false);
+        unavailable_offer.r#type = "vendor.model".to_string();
+        unavailable_offer.category_id = 2;
+        stats.add_offer(&unavailable_offer);
+
+        let report = stats.finish(1);
+        assert_eq!(report.total_offers, 4);
+        assert_eq!(report.by_type[0], Count { name: "simplified".to_string(), count: 3 });
+        assert_eq!(report.by_availability[1], Count { name: "unavailable".to_string(), count: 1 });
+        assert_eq!((report.by_category[0].name.as_str(), report.by_category[0].offers), ("Pans", 3));
+        assert_eq!(
+            report.prices,
+            vec!(PriceStats { currency: "UAH".to_string(), offers: 4, min: 10.0, median: 17.5, max: 30.0 })
+        );
+        let vendor_fill_rate = report.fill_rates.iter().find(|f| f.field == "vendor").unwrap();
+        assert_eq!((vendor_fill_rate.offers, vendor_fill_rate.rate), (3, 0.75));
+        assert_eq!(report.extra_field_fill_rates[0].field, "supplier");
+        assert_eq!(report.params, vec!(Count { name: "Color".to_string(), count: 1 }));
+        assert_eq!((report.pictures[0].count, report.pictures[2].count), (3, 1));
+        assert_eq!(report.pictures[10].name, "10+");
+        let description_lengths = report.description_lengths.iter()
+            .map(|c| (c.name.as_str(), c.count))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            description_lengths,
+            vec!(("0", 3), ("1-99", 0), ("100-499", 1), ("500-999", 0), ("1000-2999", 0), ("3000-9999", 0), ("10000+", 0))
+        );
+        assert_eq!(report.vendors, vec!(Count { name: "Tefal".to_string(), count: 2 }));
+    }
+}
@@ -0,0 +1,562 @@
+use serde::Serialize;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+
+use crate::market_xml::{Offer, YmlCatalog};
+use crate::yml_writer::escape;
+
+// longer source lines are cut around an error
+const MAX_SNIPPET_LINE_LENGTH: usize = 200;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum ValidationReportFormat {
+    Text,
+    Json,
+    /// Standalone html page
+    Html,
+}
+
+impl FromStr for ValidationReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(ValidationReportFormat::Text),
+            "json" => Ok(ValidationReportFormat::Json),
+            "html" => Ok(ValidationReportFormat::Html),
+            _ => Err(format!("unknown report format: {}", s)),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct ValidationReport {
+    pub(crate) source: String,
+    /// A feed passes when there are no errors and no missing required fields
+    pub(crate) passed: bool,
+    pub(crate) total_offers: u64,
+    pub(crate) valid_offers: u64,
+    pub(crate) offers_with_errors: u64,
+    pub(crate) offers_with_missing_fields: u64,
+    pub(crate) warnings: u64,
+    /// Errors outside of offers, they don't skip any offer
+    pub(crate) catalog_errors: Vec<ErrorGroup>,
+    pub(crate) errors: Vec<ErrorGroup>,
+    pub(crate) warning_groups: Vec<ErrorGroup>,
+    pub(crate) missing_shop_fields: Vec<&'static str>,
+    pub(crate) missing_fields: Vec<MissingField>,
+}
+
+/// Errors with the same message found in the same xml element
+#[derive(Serialize, Debug)]
+pub(crate) struct ErrorGroup {
+    pub(crate) message: String,
+    pub(crate) field: String,
+    pub(crate) count: u64,
+    pub(crate) samples: Vec<ErrorSample>,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct ErrorSample {
+    pub(crate) line: usize,
+    pub(crate) column: usize,
+    pub(crate) offer_id: Option<String>,
+    pub(crate) value: String,
+    /// Numbered source lines around the error
+    pub(crate) snippet: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct MissingField {
+    pub(crate) field: &'static str,
+    pub(crate) offers: u64,
+    pub(crate) sample_offer_ids: Vec<String>,
+}
+
+struct SourceError {
+    line: usize,
+    column: usize,
+    message: String,
+    value: String,
+}
+
+/// Collects problems of a feed while it is parsed
+pub(crate) struct Validator {
+    max_samples: usize,
+    total_offers: u64,
+    offers_with_missing_fields: u64,
+    catalog_errors: Vec<SourceError>,
+    errors: Vec<SourceError>,
+    warnings: Vec<SourceError>,
+    missing_shop_fields: Vec<&'static str>,
+    missing_fields: BTreeMap<&'static str, MissingField>,
+}
+
+impl Validator {
+    pub(crate) fn new(max_samples: usize) -> Self {
+        Self {
+            max_samples,
+            total_offers: 0,
+            offers_with_missing_fields: 0,
+            catalog_errors: vec!(),
+            errors: vec!(),
+            warnings: vec!(),
+            missing_shop_fields: vec!(),
+            missing_fields: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn add_offer(&mut self, offer: &Offer) {
+        self.total_offers += 1;
+        let missing_fields = missing_offer_fields(offer);
+        if missing_fields.is_empty() {
+            return;
+        }
+        self.offers_with_missing_fields += 1;
+        for field in missing_fields {
+            let missing_field = self.missing_fields.entry(field)
+                .or_insert_with(|| MissingField { field, offers: 0, sample_offer_ids: vec!() });
+            missing_field.offers += 1;
+            if missing_field.sample_offer_ids.len() < self.max_samples {
+                missing_field.sample_offer_ids.push(offer.id.clone());
+            }
+        }
+    }
+
+    pub(crate) fn add_catalog(&mut self, yml_catalog: &YmlCatalog) {
+        let shop = yml_catalog.shop.clone().unwrap_or_default();
+        let required = [
+            ("name", shop.name.is_empty()),
+            ("company", shop.company.is_empty()),
+            ("url", shop.url.is_empty()),
+            ("currencies", shop.currencies.is_empty()),
+            ("categories", shop.categories.is_empty()),
+        ];
+        self.missing_shop_fields = required.iter()
+            .filter(|(_, missing)| *missing)
+            .map(|(field, _)| *field)
+            .collect();
+    }
+
+    /// An offer with an error is skipped by the parser
+    pub(crate) fn add_error(&mut self, line: usize, column: usize, message: String, value: String) {
+        self.total_offers += 1;
+        self.errors.push(SourceError { line, column, message, value });
+    }
+
+    /// An error in shop fields or one that stops parsing of the feed
+    pub(crate) fn add_catalog_error(&mut self, line: usize, column: usize, message: String, value: String) {
+        self.catalog_errors.push(SourceError { line, column, message, value });
+    }
+
+    pub(crate) fn add_warning(&mut self, line: usize, column: usize, message: String, value: String) {
+        self.warnings.push(SourceError { line, column, message, value });
+    }
+
+    /// Reads the source again to find fields, offer ids and snippets of errors
+    pub(crate) fn finish<R: BufRead>(self, source: &str, reader: Option<R>) -> io::Result<ValidationReport> {
+        let source_lines = match reader {
+            Some(reader) => SourceLines::read(
+                reader, self.catalog_errors.iter().chain(&self.errors).chain(&self.warnings)
+            )?,
+            None => SourceLines::default(),
+        };
+        let offers_with_errors = self.errors.len() as u64;
+        let catalog_errors = group_errors(self.catalog_errors, &source_lines, self.max_samples, false);
+        let errors = group_errors(self.errors, &source_lines, self.max_samples, true);
+        let warning_groups = group_errors(self.warnings, &source_lines, self.max_samples, true);
+        let warnings = warning_groups.iter().map(|group| group.count).sum();
+        let missing_fields = self.missing_fields.into_values().collect::<Vec<_>>();
+        Ok(ValidationReport {
+            source: source.to_string(),
+            passed: catalog_errors.is_empty() && errors.is_empty() && missing_fields.is_empty()
+                && self.missing_shop_fields.is_empty(),
+            total_offers: self.total_offers,
+            valid_offers: self.total_offers - offers_with_errors - self.offers_with_missing_fields,
+            offers_with_errors,
+            offers_with_missing_fields: self.offers_with_missing_fields,
+            warnings,
+            catalog_errors,
+            errors,
+            warning_groups,
+            missing_shop_fields: self.missing_shop_fields,
+            missing_fields,
+        })
+    }
+}
+
+/// Required fields of an offer using names of yml elements
+fn missing_offer_fields(offer: &Offer) -> Vec<&'static str> {
+    let mut missing = vec!();
+    if offer.id.is_empty() {
+        missing.push("id");
+    }
+    if offer.r#type == "vendor.model" {
+        if offer.vendor.is_empty() {
+            missing.push("vendor");
+        }
+        if offer.model.is_empty() {
+            missing.push("model");
+        }
+    } else if offer.name.is_empty() {
+        missing.push("name");
+    }
+    if offer.url.is_empty() {
+        missing.push("url");
+    }
+    if offer.price.is_none() {
+        missing.push("price");
+    }
+    if offer.currency_id.is_empty() {
+        missing.push("currencyId");
+    }
+    if offer.category_id == 0 && offer.category_raw_id.is_empty() {
+        missing.push("categoryId");
+    }
+    missing
+}
+
+/// Source lines around errors and ids of offers containing the errors
+#[derive(Default)]
+struct SourceLines {
+    lines: HashMap<usize, String>,
+    offer_ids: HashMap<usize, String>,
+}
+
+impl SourceLines {
+    fn read<'a, R: BufRead>(mut reader: R, errors: impl Iterator<Item = &'a SourceError>) -> io::Result<Self> {
+        let mut error_columns = HashMap::new();
+        let mut needed_lines = HashSet::new();
+        for error in errors {
+            error_columns.entry(error.line).or_insert_with(Vec::new).push(error.column);
+            needed_lines.extend(error.line.saturating_sub(1)..=error.line + 1);
+        }
+
+        let mut source_lines = SourceLines::default();
+        let mut current_offer_id = None;
+        let mut buf = vec!();
+        let mut line_no = 1;
+        while reader.read_until(b'\n', &mut buf)? > 0 {
+            let line = String::from_utf8_lossy(&buf);
+            let line = line.trim_end_matches(&['\r', '\n'][..]);
+            if let Some(columns) = error_columns.get(&line_no) {
+                // an error column is reported after the element where it was found
+                let column = columns.iter().copied().max().unwrap_or(0);
+                let offer_id = last_offer_id(&line[..char_boundary(line, column)])
+                    .or_else(|| current_offer_id.clone());
+                if let Some(offer_id) = offer_id {
+                    source_lines.offer_ids.insert(line_no, offer_id);
+                }
+            }
+            if let Some(offer_id) = last_offer_id(line) {
+                current_offer_id = Some(offer_id);
+            }
+            if needed_lines.contains(&line_no) {
+                source_lines.lines.insert(line_no, line.to_string());
+            }
+            buf.clear();
+            line_no += 1;
+        }
+        Ok(source_lines)
+    }
+
+    fn snippet(&self, line: usize, column: usize) -> Vec<String> {
+        (line.saturating_sub(1)..=line + 1)
+            .filter_map(|line_no| {
+                let text = self.lines.get(&line_no)?;
+                let text = if line_no == line { excerpt(text, column) } else { excerpt(text, 0) };
+                Some(format!("{:>6} | {}", line_no, text))
+            })
+            .collect()
+    }
+}
+
+fn group_errors(
+    errors: Vec<SourceError>, source_lines: &SourceLines, max_samples: usize, in_offers: bool
+) -> Vec<ErrorGroup> {
+    let mut groups = BTreeMap::new();
+    for error in errors {
+        let field = source_lines.lines.get(&error.line)
+            .map(|line| field_at(line, error.column))
+            .unwrap_or_default();
+        let group = groups.entry((error.message.clone(), field.clone()))
+            .or_insert_with(|| ErrorGroup { message: error.message.clone(), field, count: 0, samples: vec!() });
+        group.count += 1;
+        if group.samples.len() < max_samples {
+            group.samples.push(ErrorSample {
+                line: error.line,
+                column: error.column,
+                offer_id: source_lines.offer_ids.get(&error.line).filter(|_| in_offers).cloned(),
+                snippet: source_lines.snippet(error.line, error.column),
+                value: error.value,
+            });
+        }
+    }
+    let mut groups = groups.into_values().collect::<Vec<_>>();
+    groups.sort_by_key(|group| std::cmp::Reverse(group.count));
+    groups
+}
+
+/// Name of the last xml element started or ended before the column
+fn field_at(line: &str, column: usize) -> String {
+    let prefix = &line[..char_boundary(line, column)];
+    match prefix.rfind('<') {
+        Some(ix) => prefix[ix + 1..].trim_start_matches('/')
+            .chars()
+            .take_while(|&c| !c.is_whitespace() && c != '>' && c != '/')
+            .collect(),
+        None => String::new(),
+    }
+}
+
+/// Id attribute of the last offer element in the text
+fn last_offer_id(text: &str) -> Option<String> {
+    let start = text.rmatch_indices("<offer")
+        .map(|(ix, _)| ix + "<offer".len())
+        .find(|&ix| text[ix..].starts_with(char::is_whitespace))?;
+    let tag = &text[start..];
+    let tag = &tag[..tag.find('>').unwrap_or(tag.len())];
+    let value_start = tag.match_indices("id=")
+        .map(|(ix, _)| ix)
+        .find(|&ix| tag[..ix].ends_with(char::is_whitespace))? + "id=".len();
+    let quote = tag[value_start..].chars().next().filter(|&c| c == '"' || c == '\'')?;
+    let value = &tag[value_start + 1..];
+    Some(value[..value.find(quote)?].to_string())
+}
+
+/// Cuts a long line around the column
+fn excerpt(line: &str, column: usize) -> String {
+    if line.len() <= MAX_SNIPPET_LINE_LENGTH {
+        return line.to_string();
+    }
+    let end = char_boundary(line, column.max(MAX_SNIPPET_LINE_LENGTH / 2) + MAX_SNIPPET_LINE_LENGTH / 2);
+    let start = char_boundary(line, end.saturating_sub(MAX_SNIPPET_LINE_LENGTH));
+    format!(
+        "{}{}{}",
+        if start > 0 { "..." } else { "" },
+        &line[start..end],
+        if end < line.len() { "..." } else { "" },
+    )
+}
+
+fn char_boundary(s: &str, ix: usize) -> usize {
+    let mut ix = ix.min(s.len());
+    while !s.is_char_boundary(ix) {
+        ix -= 1;
+    }
+    ix
+}
+
+pub(crate) fn write_text_report<W: Write>(report: &ValidationReport, mut writer: W) -> io::Result<()> {
+    writeln!(writer, "Feed: {}", report.source)?;
+    writeln!(writer, "Result: {}", if report.passed { "PASSED" } else { "FAILED" })?;
+    writeln!(
+        writer, "Offers: {} total, {} valid, {} with errors, {} with missing fields",
+        report.total_offers, report.valid_offers, report.offers_with_errors, report.offers_with_missing_fields,
+    )?;
+    writeln!(writer, "Warnings: {}", report.warnings)?;
+
+    let sections = [
+        ("Catalog errors", &report.catalog_errors), ("Errors", &report.errors), ("Warnings", &report.warning_groups),
+    ];
+    for (title, groups) in sections {
+        if groups.is_empty() {
+            continue;
+        }
+        writeln!(writer, "\n{}:", title)?;
+        for group in groups {
+            writeln!(writer, "  {} ({}): {}", group.message, field_name(&group.field), group.count)?;
+            for sample in &group.samples {
+                write!(writer, "    line {}:{}", sample.line, sample.column)?;
+                if let Some(ref offer_id) = sample.offer_id {
+                    write!(writer, ", offer {}", offer_id)?;
+                }
+                writeln!(writer, ", value {:?}", sample.value)?;
+                for line in &sample.snippet {
+                    writeln!(writer, "      {}", line)?;
+                }
+            }
+        }
+    }
+
+    if !report.missing_shop_fields.is_empty() {
+        writeln!(writer, "\nMissing shop fields: {}", report.missing_shop_fields.join(", "))?;
+    }
+    if !report.missing_fields.is_empty() {
+        writeln!(writer, "\nMissing offer fields:")?;
+        for missing_field in &report.missing_fields {
+            writeln!(
+                writer, "  {}: {} offers ({})",
+                missing_field.field, missing_field.offers, missing_field.sample_offer_ids.join(", "),
+            )?;
+        }
+    }
+    writer.flush()
+}
+
+pub(crate) fn write_html_report<W: Write>(report: &ValidationReport, mut writer: W) -> io::Result<()> {
+    let source = escape(&report.source, true);
+    writeln!(writer, "<!DOCTYPE html>")?;
+    writeln!(writer, "<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Feed validation: {}</title>", source)?;
+    writeln!(
+        writer,
+        "<style>\n\
+         body {{ font-family: sans-serif; margin: 2em; }}\n\
+         table {{ border-collapse: collapse; margin-bottom: 1em; }}\n\
+         td, th {{ border: 1px solid #ccc; padding: 4px 8px; text-align: left; vertical-align: top; }}\n\
+         pre {{ background: #f6f6f6; padding: 4px; margin: 4px 0; }}\n\
+         .passed {{ color: #2a7d2a; }}\n\
+         .failed {{ color: #c0392b; }}\n\
+         </style>\n</head>\n<body>"
+    )?;
+    writeln!(writer, "<h1>Feed validation: {}</h1>", source)?;
+    if report.passed {
+        writeln!(writer, "<h2 class=\"passed\">Passed</h2>")?;
+    } else {
+        writeln!(writer, "<h2 class=\"failed\">Failed</h2>")?;
+    }
+    writeln!(writer, "<table>")?;
+    for (name, value) in [
+        ("Total offers", report.total_offers),
+        ("Valid offers", report.valid_offers),
+        ("Offers with errors", report.offers_with_errors),
+        ("Offers with missing fields", report.offers_with_missing_fields),
+        ("Warnings", report.warnings),
+    ] {
+        writeln!(writer, "<tr><th>{}</th><td>{}</td></tr>", name, value)?;
+    }
+    writeln!(writer, "</table>")?;
+
+    let sections = [
+        ("Catalog errors", &report.catalog_errors), ("Errors", &report.errors), ("Warnings", &report.warning_groups),
+    ];
+    for (title, groups) in sections {
+        if groups.is_empty() {
+            continue;
+        }
+        writeln!(writer, "<h2>{}</h2>\n<table>", title)?;
+        writeln!(writer, "<tr><th>Message</th><th>Field</th><th>Count</th><th>Samples</th></tr>")?;
+        for group in groups {
+            write!(
+                writer, "<tr><td>{}</td><td>{}</td><td>{}</td><td>",
+                escape(&group.message, false), escape(field_name(&group.field), false), group.count,
+            )?;
+            for sample in &group.samples {
+                write!(writer, "<div>Line {}:{}", sample.line, sample.column)?;
+                if let Some(ref offer_id) = sample.offer_id {
+                    write!(writer, ", offer <b>{}</b>", escape(offer_id, false))?;
+                }
+                write!(writer, ", value <code>{}</code>", escape(&sample.value, false))?;
+                if !sample.snippet.is_empty() {
+                    write!(writer, "<pre>{}</pre>", escape(&sample.snippet.join("\n"), false))?;
+                }
+                write!(writer, "</div>")?;
+            }
+            writeln!(writer, "</td></tr>")?;
+        }
+        writeln!(writer, "</table>")?;
+    }
+
+    if !report.missing_shop_fields.is_empty() {
+        writeln!(writer, "<h2>Missing shop fields</h2>\n<p>{}</p>", escape(&report.missing_shop_fields.join(", "), false))?;
+    }
+    if !report.missing_fields.is_empty() {
+        writeln!(writer, "<h2>Missing offer fields</h2>\n<table>")?;
+        writeln!(writer, "<tr><th>Field</th><th>Offers</th><th>Sample offers</th></tr>")?;
+        for missing_field in &report.missing_fields {
+            writeln!(
+                writer, "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+                missing_field.field, missing_field.offers, escape(&missing_field.sample_offer_ids.join(", "), false),
+            )?;
+        }
+        writeln!(writer, "</table>")?;
+    }
+    writeln!(writer, "</body>\n</html>")?;
+    writer.flush()
+}
+
+fn field_name(field: &str) -> &str {
+    if field.is_empty() { "unknown field" } else { field }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::market_xml::{Offer, Price, Shop, YmlCatalog};
+    use super::{excerpt, field_at, last_offer_id, write_html_report, write_text_report, Validator};
+
+    const FEED: &str = r#"<offers>
+  <offer id="1">
+    <price>bad</price>
+  </offer>
+  <offer id='2' available="yes">
+  </offer>
+</offers>
+"#;
+
+    #[test]
+    fn test_validation_report() {
+        let mut validator = Validator::new(3);
+        validator.add_catalog(&YmlCatalog {
+            shop: Some(Shop { name: "Shop".to_string(), ..Default::default() }),
+            ..Default::default()
+        });
+        validator.add_error(3, 22, "invalid float literal".to_string(), "bad".to_string());
+        validator.add_error(5, 32, "parse bool".to_string(), "yes".to_string());
+        validator.add_catalog_error(7, 10, "Unexpected end of file".to_string(), String::new());
+        validator.add_offer(&Offer {
+            id: "3".to_string(),
+            name: "Pan".to_string(),
+            price: Some(Price { price: 10.0, from: false }),
+            currency_id: "UAH".to_string(),
+            category_id: 1,
+            ..Default::default()
+        });
+        let report = validator.finish("feed.xml", Some(FEED.as_bytes())).unwrap();
+
+        assert!(!report.passed);
+        assert_eq!((report.total_offers, report.valid_offers, report.offers_with_errors), (3, 0, 2));
+        assert_eq!(report.catalog_errors.len(), 1);
+        assert_eq!((report.catalog_errors[0].count, report.catalog_errors[0].field.as_str()), (1, "offers"));
+        assert_eq!(report.missing_shop_fields, vec!("company", "url", "currencies", "categories"));
+        assert_eq!(report.missing_fields.len(), 1);
+        assert_eq!((report.missing_fields[0].field, report.missing_fields[0].offers), ("url", 1));
+        let groups = report.errors.iter()
+            .map(|group| (group.message.as_str(), group.field.as_str(), group.samples[0].offer_id.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            groups,
+            vec!(("invalid float literal", "price", Some("1")), ("parse bool", "offer", Some("2")))
+        );
+        assert_eq!(
+            report.errors[0].samples[0].snippet,
+            vec!("     2 |   <offer id=\"1\">", "     3 |     <price>bad</price>", "     4 |   </offer>")
+        );
+
+        let mut text = vec!();
+        write_text_report(&report, &mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.starts_with("Feed: feed.xml\nResult: FAILED\n"));
+        assert!(text.contains("  invalid float literal (price): 1\n    line 3:22, offer 1, value \"bad\"\n"));
+        assert!(text.contains("\nCatalog errors:\n  Unexpected end of file (offers): 1\n"));
+        let mut html = vec!();
+        write_html_report(&report, &mut html).unwrap();
+        let html = String::from_utf8(html).unwrap();
+        assert!(html.contains("&lt;price&gt;bad&lt;/price&gt;"));
+    }
+
+    #[test]
+    fn test_finding_source_context() {
+        assert_eq!(field_at("    <price>bad</price>", 22), "price");
+        assert_eq!(field_at("<offer id=\"1\" available=\"x\">", 28), "offer");
+        assert_eq!(field_at("1,Pan,bad", 9), "");
+        assert_eq!(last_offer_id("<offers><offer id=\"1\"></offer><offer type=\"x\" id='2'>"), Some("2".to_string()));
+        assert_eq!(last_offer_id("<offers>"), None);
+        assert_eq!(last_offer_id("<offer group_id=\"5\">"), None);
+        let line = "x".repeat(500);
+        assert_eq!(excerpt(&line, 300).len(), 206);
+        assert!(excerpt(&line, 0).starts_with("xxx"));
+    }
+}
@@ -0,0 +1,443 @@
+use std::io::{self, Write};
+
+use crate::market_xml::{Category, Currency, DeliveryOption, Offer, Price, Shop, YmlCatalog};
+
+/// Writes a yml catalog with a stream of offers.
+/// Shop fields go first so offers can be written one by one without keeping them in memory.
+pub(crate) struct YmlWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> YmlWriter<W> {
+    pub(crate) fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Writes a catalog element with shop fields up to the opening offers tag
+    pub(crate) fn write_start(&mut self, yml_catalog: &YmlCatalog) -> io::Result<()> {
+        writeln!(self.writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        self.write_open("yml_catalog", &[("date", &yml_catalog.date)], 0)?;
+        self.write_open("shop", &[], 1)?;
+        if let Some(ref shop) = yml_catalog.shop {
+            self.write_shop_fields(shop)?;
+        }
+        self.write_open("offers", &[], 2)
+    }
+
+    pub(crate) fn write_end(&mut self) -> io::Result<()> {
+        self.write_close("offers", 2)?;
+        self.write_close("shop", 1)?;
+        self.write_close("yml_catalog", 0)?;
+        self.writer.flush()
+    }
+
+    pub(crate) fn write_offer(&mut self, offer: &Offer) -> io::Result<()> {
+        let available = offer.available.map(|available| available.to_string()).unwrap_or_default();
+        let bid = non_zero(offer.bid);
+        let cbid = non_zero(offer.cbid);
+        let group_id = non_zero(offer.group_id);
+        self.write_open(
+            "offer",
+            &[
+                ("id", &offer.id),
+                ("type", &offer.r#type),
+                ("available", &available),
+                ("bid", &bid),
+                ("cbid", &cbid),
+                ("group_id", &group_id),
+            ],
+            3
+        )?;
+        let depth = 4;
+        self.write_text("name", &offer.name, depth)?;
+        self.write_text("typePrefix", &offer.type_prefix, depth)?;
+        self.write_text("vendor", &offer.vendor, depth)?;
+        self.write_text("vendorCode", &offer.vendor_code, depth)?;
+        self.write_text("model", &offer.model, depth)?;
+        self.write_text("url", &offer.url, depth)?;
+        if let Some(ref price) = offer.price {
+            self.write_price("price", price, depth)?;
+        }
+        if let Some(ref old_price) = offer.old_price {
+            self.write_price("oldprice", old_price, depth)?;
+        }
+        if offer.enable_auto_discounts {
+            self.write_text("enable_auto_discounts", "true", depth)?;
+        }
+        self.write_text("currencyId", &offer.currency_id, depth)?;
+        if offer.category_id != 0 {
+            self.write_text("categoryId", &offer.category_id.to_string(), depth)?;
+        } else {
+            self.write_text("categoryId", &offer.category_raw_id, depth)?;
+        }
+        for picture in &offer.pictures {
+            self.write_text("picture", picture, depth)?;
+        }
+        for (name, value) in &[("delivery", offer.delivery), ("pickup", offer.pickup), ("store", offer.store)] {
+            if let Some(value) = value {
+                self.write_text(name, &value.to_string(), depth)?;
+            }
+        }
+        self.write_delivery_options("delivery-options", &offer.delivery_options, depth)?;
+        self.write_delivery_options("pickup-options", &offer.pickup_options, depth)?;
+        if !offer.description.is_empty() {
+            self.write_cdata("description", &[], &offer.description, depth)?;
+        }
+        if !offer.sales_notes.is_empty() {
+            self.write_markup("sales_notes", &[], &offer.sales_notes, depth)?;
+        }
+        // sorted to get the same output for the same offer
+        let mut localized = offer.localized.iter().collect::<Vec<_>>();
+        localized.sort_by_key(|(lang, _)| lang.as_str());
+        for (lang, text) in localized {
+            if !text.name.is_empty() {
+                self.write_element("name", &[("lang", lang)], &text.name, depth)?;
+            }
+            if !text.description.is_empty() {
+                self.write_cdata("description", &[("lang", lang)], &text.description, depth)?;
+            }
+        }
+        if let Some(min_quantity) = offer.min_quantity {
+            self.write_text("min_quantity", &min_quantity.to_string(), depth)?;
+        }
+        if offer.manufacturer_warranty {
+            self.write_text("manufacturer_warranty", "true", depth)?;
+        }
+        self.write_text("country_of_origin", &offer.country_of_origin, depth)?;
+        if offer.adult {
+            self.write_text("adult", "true", depth)?;
+        }
+        for barcode in &offer.barcodes {
+            self.write_text("barcode", barcode, depth)?;
+        }
+        for param in &offer.params {
+            self.write_element(
+                "param",
+                &[
+                    ("name", &param.name),
+                    ("unit", &param.unit),
+                    ("id", &param.id),
+                    ("valueid", &param.value_id),
+                ],
+                &param.value,
+                depth
+            )?;
+        }
+        if let Some(ref condition) = offer.condition {
+            self.write_open("condition", &[("type", &condition.r#type)], depth)?;
+            self.write_text("reason", &condition.reason, depth + 1)?;
+            self.write_close("condition", depth)?;
+        }
+        if !offer.credit_template_id.is_empty() {
+            self.write_empty("credit-template", &[("id", &offer.credit_template_id)], depth)?;
+        }
+        self.write_text("expiry", &offer.expiry, depth)?;
+        if offer.weight != 0.0 {
+            self.write_text("weight", &offer.weight.to_string(), depth)?;
+        }
+        self.write_text("dimensions", &offer.dimensions, depth)?;
+        if offer.downloadable {
+            self.write_text("downloadable", "true", depth)?;
+        }
+        if let Some(ref age) = offer.age {
+            self.write_element("age", &[("unit", &age.unit)], &age.value.to_string(), depth)?;
+        }
+        if let Some(stock_quantity) = offer.stock_quantity {
+            self.write_text("stock_quantity", &stock_quantity.to_string(), depth)?;
+        }
+        if !offer.keywords.is_empty() {
+            self.write_text("keywords", &offer.keywords.join(", "), depth)?;
+        }
+        // sorted to get the same output for the same offer
+        let mut extra_fields = offer.extra_fields.iter().collect::<Vec<_>>();
+        extra_fields.sort_by_key(|(name, _)| name.as_str());
+        for (name, field) in extra_fields {
+            for value in &field.values {
+                self.write_extra_field(name, value, depth)?;
+            }
+        }
+        self.write_close("offer", 3)
+    }
+
+    fn write_shop_fields(&mut self, shop: &Shop) -> io::Result<()> {
+        let depth = 2;
+        self.write_text("name", &shop.name, depth)?;
+        self.write_text("company", &shop.company, depth)?;
+        self.write_text("url", &shop.url, depth)?;
+        self.write_text("platform", &shop.platform, depth)?;
+        self.write_text("version", &shop.version, depth)?;
+        self.write_text("agency", &shop.agency, depth)?;
+        self.write_text("email", &shop.email, depth)?;
+        self.write_currencies(&shop.currencies, depth)?;
+        self.write_categories(&shop.categories, depth)?;
+        self.write_delivery_options("delivery-options", &shop.delivery_options, depth)?;
+        self.write_delivery_options("pickup-options", &shop.pickup_options, depth)
+    }
+
+    fn write_currencies(&mut self, currencies: &[Currency], depth: usize) -> io::Result<()> {
+        if currencies.is_empty() {
+            return Ok(());
+        }
+        self.write_open("currencies", &[], depth)?;
+        for currency in currencies {
+            self.write_empty(
+                "currency",
+                &[("id", &currency.id), ("rate", &currency.rate), ("plus", &currency.plus)],
+                depth + 1
+            )?;
+        }
+        self.write_close("currencies", depth)
+    }
+
+    fn write_categories(&mut self, categories: &[Category], depth: usize) -> io::Result<()> {
+        if categories.is_empty() {
+            return Ok(());
+        }
+        self.write_open("categories", &[], depth)?;
+        for category in categories {
+            let id = if category.raw_id.is_empty() {
+                category.id.to_string()
+            } else {
+                category.raw_id.clone()
+            };
+            let parent_id = if category.raw_parent_id.is_empty() {
+                non_zero(category.parent_id)
+            } else {
+                category.raw_parent_id.clone()
+            };
+            self.write_element(
+                "category", &[("id", &id), ("parentId", &parent_id)], &category.name, depth + 1
+            )?;
+        }
+        self.write_close("categories", depth)
+    }
+
+    fn write_delivery_options(
+        &mut self, name: &str, options: &[DeliveryOption], depth: usize
+    ) -> io::Result<()> {
+        if options.is_empty() {
+            return Ok(());
+        }
+        self.write_open(name, &[], depth)?;
+        for option in options {
+            let order_before = option.order_before
+                .map(|order_before| order_before.to_string())
+                .unwrap_or_default();
+            self.write_empty(
+                "option",
+                &[
+                    ("cost", &option.cost.to_string()),
+                    ("days", &option.days),
+                    ("order-before", &order_before),
+                ],
+                depth + 1
+            )?;
+        }
+        self.write_close(name, depth)
+    }
+
+    fn write_price(&mut self, name: &str, price: &Price, depth: usize) -> io::Result<()> {
+        let from = if price.from { "true" } else { "" };
+        self.write_element(name, &[("from", from)], &price.price.to_string(), depth)
+    }
+
+    // `{namespace}name` keys of foreign fields get their namespace back
+    fn write_extra_field(&mut self, name: &str, value: &str, depth: usize) -> io::Result<()> {
+        if let Some(ns_name) = name.strip_prefix('{') {
+            if let Some(ns_end) = ns_name.find('}') {
+                let (ns, local_name) = (&ns_name[..ns_end], &ns_name[ns_end + 1..]);
+                return self.write_markup(local_name, &[("xmlns", ns)], value, depth);
+            }
+        }
+        self.write_markup(name, &[], value, depth)
+    }
+
+    // Skips empty values like the parser treats missing elements
+    fn write_text(&mut self, name: &str, value: &str, depth: usize) -> io::Result<()> {
+        if value.is_empty() {
+            return Ok(());
+        }
+        self.write_element(name, &[], value, depth)
+    }
+
+    // Values with markup, that the parser keeps as inner xml, are not escaped
+    fn write_markup(
+        &mut self, name: &str, attrs: &[(&str, &str)], value: &str, depth: usize
+    ) -> io::Result<()> {
+        if value.contains('<') {
+            self.write_cdata(name, attrs, value, depth)
+        } else {
+            self.write_element(name, attrs, value, depth)
+        }
+    }
+
+    fn write_cdata(
+        &mut self, name: &str, attrs: &[(&str, &str)], value: &str, depth: usize
+    ) -> io::Result<()> {
+        indent(&mut self.writer, depth)?;
+        write!(self.writer, "<{}", name)?;
+        write_attrs(&mut self.writer, attrs)?;
+        // a cdata section cannot contain its end marker so it is split
+        writeln!(self.writer, "><![CDATA[{}]]></{}>", value.replace("]]>", "]]]]><![CDATA[>"), name)
+    }
+
+    fn write_element(
+        &mut self, name: &str, attrs: &[(&str, &str)], value: &str, depth: usize
+    ) -> io::Result<()> {
+        indent(&mut self.writer, depth)?;
+        write!(self.writer, "<{}", name)?;
+        write_attrs(&mut self.writer, attrs)?;
+        writeln!(self.writer, ">{}</{}>", escape(value, false), name)
+    }
+
+    fn write_empty(&mut self, name: &str, attrs: &[(&str, &str)], depth: usize) -> io::Result<()> {
+        indent(&mut self.writer, depth)?;
+        write!(self.writer, "<{}", name)?;
+        write_attrs(&mut self.writer, attrs)?;
+        writeln!(self.writer, "/>")
+    }
+
+    fn write_open(&mut self, name: &str, attrs: &[(&str, &str)], depth: usize) -> io::Result<()> {
+        indent(&mut self.writer, depth)?;
+        write!(self.writer, "<{}", name)?;
+        write_attrs(&mut self.writer, attrs)?;
+        writeln!(self.writer, ">")
+    }
+
+    fn write_close(&mut self, name: &str, depth: usize) -> io::Result<()> {
+        indent(&mut self.writer, depth)?;
+        writeln!(self.writer, "</{}>", name)
+    }
+}
+
+// Attributes with empty values are omitted
+fn write_attrs<W: Write>(writer: &mut W, attrs: &[(&str, &str)]) -> io::Result<()> {
+    for (name, value) in attrs {
+        if !value.is_empty() {
+            write!(writer, r#" {}="{}""#, name, escape(value, true))?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn indent<W: Write>(writer: &mut W, depth: usize) -> io::Result<()> {
+    for _ in 0..depth {
+        writer.write_all(b"  ")?;
+    }
+    Ok(())
+}
+
+pub(crate) fn escape(s: &str, is_attr: bool) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' if is_attr => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn non_zero<T: Default + PartialEq + ToString>(v: T) -> String {
+    if v == T::default() {
+        String::new()
+    } else {
+        v.to_string()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufReader;
+
+    use failure::{bail, Error};
+
+    use crate::market_xml::{
+        Category, Condition, Currency, LocalizedText, Offer, OfferExtraField, Param, Price, Shop, YmlCatalog,
+    };
+    use crate::parser::{FeedParser, MarketXmlConfig, MarketXmlParser, ParsedItem};
+    use super::YmlWriter;
+
+    #[test]
+    fn test_writing_round_trip() -> Result<(), Error> {
+        let yml_catalog = YmlCatalog {
+            date: "2020-01-01 12:00".to_string(),
+            shop: Some(Shop {
+                name: "Shop & Co".to_string(),
+                url: "http://example.com".to_string(),
+                currencies: vec!(
+                    Currency { id: "RUR".to_string(), rate: "1".to_string(), plus: "".to_string() },
+                ),
+                categories: vec!(
+                    Category { id: 1, name: "Посуда".to_string(), ..Default::default() },
+                    Category { id: 2, parent_id: 1, name: "Кастрюли".to_string(), ..Default::default() },
+                ),
+                ..Default::default()
+            }),
+        };
+        let mut offer = Offer {
+            id: "1".to_string(),
+            available: Some(true),
+            name: "Кастрюля <10 л>".to_string(),
+            category_id: 2,
+            price: Some(Price { price: 1299.5, from: true }),
+            currency_id: "RUR".to_string(),
+            pictures: vec!("http://example.com/1.jpg?a=1&b=2".to_string()),
+            delivery: Some(false),
+            description: "<p>Описание ]]> с разметкой</p>".to_string(),
+            barcodes: vec!("4601234567890".to_string()),
+            params: vec!(
+                Param {
+                    name: "Объём".to_string(),
+                    unit: "л".to_string(),
+                    value: "10".to_string(),
+                    ..Default::default()
+                },
+            ),
+            condition: Some(Condition { r#type: "likenew".to_string(), reason: "Вмятина".to_string() }),
+            weight: 1.5,
+            ..Default::default()
+        };
+        offer.extra_fields.insert(
+            "color".to_string(), OfferExtraField { values: vec!("red".to_string(), "blue".to_string()) }
+        );
+        offer.extra_fields.insert(
+            "{urn:ext}rating".to_string(), OfferExtraField { values: vec!("4.5".to_string()) }
+        );
+        offer.extra_fields.insert(
+            "features".to_string(), OfferExtraField { values: vec!("<ul><li>Крышка</li></ul>".to_string()) }
+        );
+        offer.localized.insert(
+            "uk".to_string(),
+            LocalizedText {
+                name: "Каструля".to_string(),
+                description: "<p>Опис</p>".to_string(),
+                ..Default::default()
+            }
+        );
+
+        let mut xml = vec!();
+        let mut writer = YmlWriter::new(&mut xml);
+        writer.write_start(&yml_catalog)?;
+        writer.write_offer(&offer)?;
+        writer.write_end()?;
+        assert!(String::from_utf8(xml.clone())?.contains("<features><![CDATA[<ul><li>Крышка</li></ul>]]></features>"));
+
+        let config = MarketXmlConfig { lang_attr: Some(b"lang".to_vec()), ..Default::default() };
+        let mut parser = MarketXmlParser::new(config, BufReader::new(xml.as_slice()));
+        match parser.next_item()? {
+            ParsedItem::Offer(parsed_offer) => assert_eq!(parsed_offer, offer),
+            _ => bail!("Expected offer"),
+        }
+        match parser.next_item()? {
+            ParsedItem::YmlCatalog(parsed_yml_catalog) => assert_eq!(parsed_yml_catalog, yml_catalog),
+            _ => bail!("Expected yml catalog"),
+        }
+
+        Ok(())
+    }
+}